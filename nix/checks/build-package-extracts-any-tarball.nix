@@ -0,0 +1,38 @@
+_: {
+  perSystem =
+    { pkgs, config, ... }:
+    let
+      # A package tarball stored without a `.tgz` extension, as `fetchurl`
+      # names those fetched from urls such as `https://example.com/tiny/latest`
+      tarball =
+        pkgs.runCommand "latest"
+          {
+            nativeBuildInputs = with pkgs; [
+              gnutar
+              gzip
+            ];
+          }
+          ''
+            mkdir package
+            printf '{ "name": "tiny", "version": "1.0.0" }\n' > package/package.json
+            tar --create --gzip --file "$out" package
+          '';
+
+      built = config.fetchBunDeps.buildPackage { } "tiny@1.0.0" tarball;
+    in
+    {
+      # Regression check for tarballs only being extracted when named `*.tgz`
+      checks.buildPackageExtractsAnyTarball =
+        pkgs.runCommand "bun2nix-build-package-extracts-any-tarball" { }
+          ''
+            manifest="${built}/share/bun-packages/tiny@1.0.0/package.json"
+
+            if [[ ! -f "$manifest" ]]; then
+              printf '\n\033[31mError:\033[0m %s\n\n' "buildPackage did not extract a tarball not named *.tgz" >&2
+              exit 1
+            fi
+
+            cp "$manifest" "$out"
+          '';
+    };
+}
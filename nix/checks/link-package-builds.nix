@@ -0,0 +1,37 @@
+_: {
+  perSystem =
+    { pkgs, config, ... }:
+    let
+      # What `bun2nix` writes for a `link:` dependency, kept in step with
+      # the template by the `Fetcher::SymlinkIntoStore` doctest
+      linked = pkgs.callPackage (
+        { runCommandLocal }:
+        runCommandLocal "bun-link" { } ''
+          ln -s ${
+            if builtins.getEnv "BUN2NIX_LINK_IN_PLACE" == "1" then
+              toString ./link-package-builds/my-lib
+            else
+              builtins.path { path = ./link-package-builds/my-lib; }
+          } $out
+        ''
+      ) { };
+
+      built = config.fetchBunDeps.buildPackage { } "my-lib" linked;
+    in
+    {
+      # Regression check for `link:` packages linking outside the store,
+      # where a sandboxed build can't read them
+      checks.linkPackageBuilds =
+        pkgs.runCommand "bun2nix-link-package-builds" { }
+          ''
+            manifest="${built}/share/bun-packages/my-lib/package.json"
+
+            if [[ ! -f "$manifest" ]]; then
+              printf '\n\033[31mError:\033[0m %s\n\n' "buildPackage could not read a linked package" >&2
+              exit 1
+            fi
+
+            cp "$manifest" "$out"
+          '';
+    };
+}
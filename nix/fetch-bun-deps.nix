@@ -116,14 +116,21 @@ let
       credentials = bunfigCredentials // npmrcCredentials;
     in
     # Wrapper for fetchurl that adds auth headers and preserves URL in passthru
+    # Packages with registry mirrors give a list of `urls` instead, the first
+    # of which is preserved
     {
-      url,
+      urls ? [ ],
+      url ? builtins.head urls,
       ...
     }@args:
     let
       token = getAuthHeader credentials url;
+      # Packages whose `.npmrc` token bun2nix read from an environment
+      # variable already send it themselves, and a `${VAR}` reference
+      # left unexpanded here would only be sent as is
+      isEnvReference = token != null && builtins.match "\\$\\{.*}" token != null;
       authArgs =
-        if token != null then
+        if token != null && !(args ? curlOptsList) && !isEnvReference then
           {
             curlOptsList = [
               "-H"
@@ -184,8 +191,21 @@ in
 
           packages = lib.filterAttrs attrIsBunPkg withErrCtx;
 
+          # Patches from the lockfile's `patchedDependencies` are applied
+          # automatically, unless the package is explicitly overridden
+          patchOverrides = config.fetchBunDeps.patchedDependenciesToOverrides {
+            patchedDependencies = lib.mapAttrs (_: pkg: pkg.patch) (
+              lib.filterAttrs (_: pkg: pkg ? patch) packages
+            );
+          };
+
           buildPackage = config.fetchBunDeps.buildPackage args;
-          overridePackage = config.fetchBunDeps.overridePackage args;
+          overridePackage = config.fetchBunDeps.overridePackage (
+            args
+            // {
+              overrides = patchOverrides // overrides;
+            }
+          );
         in
 
         assert lib.asserts.assertEachOneOf "overrides" (builtins.attrNames overrides) (
@@ -202,6 +222,12 @@ in
             (builtins.mapAttrs buildPackage)
             builtins.attrValues
           ];
+
+          # The `node_modules` layout `bun.nix` declares, if any
+          passthru.linker = withErrCtx.linker or null;
+
+          # Whether `bun.nix` was generated with every lifecycle script disabled
+          passthru.disableLifecycleScripts = withErrCtx.disableLifecycleScripts or false;
         };
     };
 }
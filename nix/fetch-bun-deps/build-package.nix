@@ -38,6 +38,18 @@ let
       )
     else
       { };
+
+  # Parse bunfig.toml for the default registry host, which may be a mirror
+  parseDefaultRegistry =
+    bunfigPath:
+    if bunfigPath != null && builtins.pathExists bunfigPath then
+      let
+        bunfig = builtins.fromTOML (builtins.readFile bunfigPath);
+        registry = bunfig.install.registry or null;
+      in
+      if registry != null then extractHost (extractUrl registry) else "registry.npmjs.org"
+    else
+      "registry.npmjs.org";
 in
 {
   options.perSystem = mkPerSystemOption {
@@ -74,6 +86,7 @@ in
         let
           bunWithNode = config.fetchBunDeps.bunWithNode args;
           scopeRegistries = parseScopeRegistries bunfigPath;
+          defaultRegistry = parseDefaultRegistry bunfigPath;
         in
         name: pkg:
         let
@@ -87,7 +100,7 @@ in
               let
                 host = extractHost pkgUrl;
               in
-              if host != null && host != "registry.npmjs.org" then host else null
+              if host != null && host != "registry.npmjs.org" && host != defaultRegistry then host else null
             else
               null;
           # Prefer scope config, fall back to URL
@@ -118,7 +131,7 @@ in
             runHook preExtract
 
             "${lib.getExe config.fetchBunDeps.extractPackage}" \
-              --package "${pkg}" \
+              --package "${pkg}${lib.optionalString (pkg ? dir) "/${pkg.dir}"}" \
               --out "$out/share/bun-packages/${name}"
 
             runHook postExtract
@@ -72,7 +72,9 @@ in
 
           mkdir -p "$out"
 
-          if [[ "$pkg" = *.tgz ]]; then
+          # Tarballs aren't always named `*.tgz`, such as those fetched
+          # from urls like `https://example.com/pkg/latest`
+          if [[ -f "$pkg" ]]; then
             bsdtar --extract \
               --file "$pkg" \
               --directory "$out" \
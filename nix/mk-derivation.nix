@@ -85,6 +85,8 @@ in
             pname = args.pname or package.name or null;
             version = args.version or package.version or null;
             module = args.module or package.module or null;
+
+            linker = args.bunDeps.linker or null;
           in
 
           assert lib.assertMsg (pname != null) ''
@@ -114,6 +116,9 @@ in
 
             inherit (args) bunDeps;
 
+            dontRunLifecycleScripts =
+              args.dontRunLifecycleScripts or (args.bunDeps.disableLifecycleScripts or false);
+
             bunBuildFlags =
               if (args ? bunBuildFlags) then
                 args.bunBuildFlags
@@ -139,6 +144,15 @@ in
             nativeBuildInputs = nativeBuildInputs ++ [
               config.mkDerivation.hook
             ];
+          }
+          # Install with the linker `bun.nix` declares in place of the hook's default
+          // lib.optionalAttrs (linker != null && !(args ? bunInstallFlags)) {
+            bunInstallFlags = [
+              "--linker=${linker}"
+            ]
+            ++ lib.optional pkgs.stdenv.hostPlatform.isDarwin (
+              if linker == "isolated" then "--backend=symlink" else "--backend=copyfile"
+            );
           };
       };
     };
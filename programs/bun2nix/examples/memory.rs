@@ -0,0 +1,120 @@
+//! Measures the peak memory of deserializing a large generated lockfile, as
+//! it is streamed, as it is parsed whole first, and for a full conversion
+//!
+//! ```sh
+//! cargo run --release --example memory -- 100000
+//! ```
+//!
+//! Every package has an integrity hash, so nothing is prefetched.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    env,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use bun2nix::{Lockfile, Options, generate_bun_nix, lockfile::NixPrefetcher};
+
+/// Counts the bytes allocated, and the most which were ever allocated at once
+struct Counting;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc(layout) };
+        if !ptr.is_null() {
+            let allocated = ALLOCATED.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+            PEAK.fetch_max(allocated, Ordering::SeqCst);
+        }
+
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) };
+        ALLOCATED.fetch_sub(layout.size(), Ordering::SeqCst);
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const MIB: f64 = 1024.0 * 1024.0;
+
+fn main() {
+    let count = env::args()
+        .nth(1)
+        .map(|count| count.parse().expect("the package count should be a number"))
+        .unwrap_or(100_000);
+
+    let lockfile = generate_lockfile(count);
+    let options = Options::default();
+
+    println!(
+        "{count} packages, {:.1} MiB of lockfile",
+        lockfile.len() as f64 / MIB
+    );
+
+    let (streamed_peak, streamed) = measure(|| {
+        Lockfile::stream_entries_with_prefetcher(&lockfile, &options, &NixPrefetcher)
+            .expect("the generated lockfile should stream")
+            .1
+    });
+    println!("Streamed:    {:.1} MiB", streamed_peak as f64 / MIB);
+
+    let (parsed_peak, parsed) = measure(|| {
+        lockfile
+            .parse::<Lockfile>()
+            .and_then(|lockfile| lockfile.entries_with_prefetcher(&options, &NixPrefetcher))
+            .expect("the generated lockfile should parse")
+    });
+    println!("Parsed:      {:.1} MiB", parsed_peak as f64 / MIB);
+
+    assert_eq!(
+        format!("{streamed:?}"),
+        format!("{parsed:?}"),
+        "streaming should give the same entries as parsing"
+    );
+    drop((streamed, parsed));
+
+    let (generate_peak, _) = measure(|| {
+        generate_bun_nix(&lockfile, &options).expect("the generated lockfile should convert")
+    });
+    println!("Full run:    {:.1} MiB", generate_peak as f64 / MIB);
+}
+
+/// Run `f`, giving the most memory allocated at once beyond what
+/// already was, along with it's result
+fn measure<T>(f: impl FnOnce() -> T) -> (usize, T) {
+    let before = ALLOCATED.load(Ordering::SeqCst);
+    PEAK.store(before, Ordering::SeqCst);
+
+    let result = f();
+
+    (PEAK.load(Ordering::SeqCst) - before, result)
+}
+
+/// A lockfile of `count` npm packages, each depending on the next
+fn generate_lockfile(count: usize) -> String {
+    let integrity = "sha512-41Cifkg6e8TylSpdtTpeLVMqvSBEVzTttHvERD741+pnZ8ANv0004MRL43QKPDlK9cGvNp6NZWZUBlbGXYxxng==";
+
+    let packages: Vec<_> = (0..count)
+        .map(|i| {
+            let dependencies = match i + 1 {
+                next if next < count => format!(r#""dependencies": {{ "pkg-{next}": "^1.0.0" }}"#),
+                _ => String::new(),
+            };
+
+            format!(
+                r#"    "pkg-{i}": ["pkg-{i}@1.0.{i}", "", {{ {dependencies} }}, "{integrity}"]"#
+            )
+        })
+        .collect();
+
+    format!(
+        "{{\n  \"lockfileVersion\": 1,\n  \"workspaces\": {{ \"\": {{ \"name\": \"app\", \"dependencies\": {{ \"pkg-0\": \"^1.0.0\" }} }} }},\n  \"packages\": {{\n{}\n  }}\n}}\n",
+        packages.join(",\n")
+    )
+}
@@ -0,0 +1,123 @@
+//! This module holds everything related to reading install settings from a project's `bunfig.toml` file
+
+use std::{fs, path::Path, str::FromStr};
+
+use crate::{
+    error::{Error, Result},
+    options::Linker,
+};
+
+/// The name of bun's config file, looked for next to the lockfile
+pub const BUNFIG_FILE_NAME: &str = "bunfig.toml";
+
+/// # Bunfig
+///
+/// The subset of settings from a `bunfig.toml` file which affect how
+/// the packages in `bun.nix` are installed
+///
+/// Only the `[install]` table is read, so the rest of the file may
+/// use any TOML bun accepts.
+///
+/// ## Usage
+///
+/// ```rust
+/// use bun2nix::{bunfig::Bunfig, options::Linker};
+///
+/// let bunfig: Bunfig = r#"
+/// [test]
+/// linker = "ignored"
+///
+/// [install]
+/// linker = "hoisted" # Tools expecting a flat `node_modules`
+///
+/// [install.scopes]
+/// "@acme" = "https://npm.acme.internal"
+/// "#.parse().unwrap();
+///
+/// assert_eq!(bunfig.linker, Some(Linker::Hoisted));
+///
+/// let bunfig: Bunfig = r#"install.linker = 'isolated'"#.parse().unwrap();
+/// assert_eq!(bunfig.linker, Some(Linker::Isolated));
+/// ```
+///
+/// The linker of the `bunfig.toml` in `options.root` is declared by
+/// `bun.nix`, unless `options.linker` is given:
+///
+/// ```rust
+/// use bun2nix::{Options, generate_bun_nix, options::Linker};
+///
+/// let root = std::env::temp_dir().join("bun2nix-bunfig-linker");
+/// std::fs::create_dir_all(&root).unwrap();
+/// std::fs::write(root.join("bunfig.toml"), "[install]\nlinker = \"hoisted\"\n").unwrap();
+///
+/// let lockfile = r#"{ "lockfileVersion": 1, "packages": {} }"#;
+///
+/// let options = Options {
+///     root: Some(root),
+///     ..Default::default()
+/// };
+/// let nix = generate_bun_nix(lockfile, &options).unwrap();
+/// assert!(nix.contains(r#"linker = "hoisted";"#));
+///
+/// let options = Options {
+///     linker: Some(Linker::Isolated),
+///     ..options
+/// };
+/// let nix = generate_bun_nix(lockfile, &options).unwrap();
+/// assert!(nix.contains(r#"linker = "isolated";"#));
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Bunfig {
+    /// The `node_modules` layout `bun install` is configured to use
+    pub linker: Option<Linker>,
+}
+
+impl Bunfig {
+    /// # Load Bunfig
+    ///
+    /// Read and parse a `bunfig.toml` file from disk
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path).map_err(Error::ReadBunfigError)?;
+
+        contents.parse()
+    }
+}
+
+impl FromStr for Bunfig {
+    type Err = Error;
+
+    fn from_str(contents: &str) -> Result<Self> {
+        let mut bunfig = Self::default();
+        let mut table = String::new();
+
+        for line in contents.lines() {
+            let line = line.trim();
+
+            if let Some(header) = line.strip_prefix('[') {
+                table = header
+                    .split(']')
+                    .next()
+                    .unwrap_or_default()
+                    .trim()
+                    .to_owned();
+                continue;
+            }
+
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let key = match table.as_str() {
+                "" => key.trim().to_owned(),
+                table => format!("{table}.{}", key.trim()),
+            };
+
+            if key == "install.linker" {
+                let value = value.split('#').next().unwrap_or_default().trim();
+
+                bunfig.linker = Some(value.trim_matches(['"', '\'']).parse()?);
+            }
+        }
+
+        Ok(bunfig)
+    }
+}
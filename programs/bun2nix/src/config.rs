@@ -0,0 +1,363 @@
+//! This module holds everything related to reading per-project options from a `bun2nix.toml` file
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use serde::Deserialize;
+use serde_json::{Map, Number, Value};
+
+use crate::{
+    Options,
+    error::{Error, Result},
+    options::Linker,
+};
+
+/// The name of the config file looked for next to the lockfile
+pub const CONFIG_FILE_NAME: &str = "bun2nix.toml";
+
+/// # Config
+///
+/// Options read from a `bun2nix.toml` file, each named after the
+/// command line flag it stands in for, or its plural for flags
+/// which may be repeated, such as `registry-mirrors`
+///
+/// Flags given on the command line take precedence over the file,
+/// which in turn takes precedence over the defaults. Only a flat
+/// list of strings, integers, booleans and arrays of them is
+/// supported, rather than the whole of TOML.
+///
+/// ## Usage
+///
+/// ```rust
+/// use bun2nix::{Options, config::Config};
+///
+/// let config: Config = r#"
+/// ## Shared by everyone working on the project
+/// registry = "https://npm.acme.internal"
+/// registry-mirrors = [
+///   "https://mirror-a.acme.internal",
+///   "https://mirror-b.acme.internal",
+/// ]
+/// prefetch-jobs = 4
+/// strict = true
+/// "#.parse().unwrap();
+///
+/// let cli = Options {
+///     default_registry: Some("https://registry.example.com".to_owned()),
+///     strict: false,
+///     ..Default::default()
+/// };
+///
+/// // Only `registry` and `strict` were given on the command line
+/// let options = config.apply(cli, |flag| ["registry", "strict"].contains(&flag));
+///
+/// assert_eq!(options.default_registry.as_deref(), Some("https://registry.example.com"));
+/// assert!(!options.strict);
+/// assert_eq!(options.registry_mirrors.len(), 2);
+/// assert_eq!(options.prefetch_jobs, 4);
+/// assert_eq!(options.gitlab_domain, Options::default().gitlab_domain);
+/// ```
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    /// The prefix to use when copying workspace or file packages
+    pub copy_prefix: Option<String>,
+    /// Globs of files to leave out when copying workspace or file packages
+    pub copy_excludes: Option<Vec<String>>,
+    /// The domain to fetch `gitlab:` packages from
+    pub gitlab_domain: Option<String>,
+    /// The domain to fetch `github:` packages from
+    pub github_domain: Option<String>,
+    /// A registry to fetch packages from in place of the npm registry
+    pub registry: Option<String>,
+    /// Mirrors of the registry to try fetching packages from first
+    pub registry_mirrors: Option<Vec<String>>,
+    /// The maximum number of packages to prefetch at once
+    pub prefetch_jobs: Option<usize>,
+    /// The maximum number of packages to prefetch at once from any one host
+    pub per_host_jobs: Option<usize>,
+    /// The number of times to retry a prefetch which failed transiently
+    pub prefetch_retries: Option<usize>,
+    /// The number of seconds a single prefetch may take
+    pub prefetch_timeout: Option<u64>,
+    /// The command to prefetch packages with
+    pub prefetch_command: Option<String>,
+    /// A proxy to prefetch packages through
+    pub proxy: Option<String>,
+    /// Hosts to prefetch packages from without the proxy
+    pub no_proxy: Option<Vec<String>>,
+    /// Skip the on-disk cache of previously prefetched hashes
+    pub no_prefetch_cache: Option<bool>,
+    /// Report every package which failed to convert
+    pub collect_all_errors: Option<bool>,
+    /// Error, rather than warn, on attribute name collisions
+    pub strict: Option<bool>,
+    /// An `.npmrc` file, relative to the config file
+    pub npmrc: Option<PathBuf>,
+    /// Leave out packages only installed for `devDependencies`
+    pub production: Option<bool>,
+    /// Git dependencies to fetch along with their submodules
+    pub fetch_submodules: Option<Vec<String>>,
+    /// Git dependencies to clone with their full history
+    pub deep_clone: Option<Vec<String>>,
+    /// Git dependencies to fetch with every ref
+    pub all_refs: Option<Vec<String>>,
+    /// Git dependencies to keep the `.git` directory of
+    pub leave_dot_git: Option<Vec<String>>,
+    /// The nix systems to keep platform specific packages for
+    pub target_systems: Option<Vec<String>>,
+    /// Also write the `sha256` of each prefetched package
+    pub emit_sha256: Option<bool>,
+    /// Write statistics about the conversion to stderr
+    pub stats: Option<bool>,
+    /// Never run any package's lifecycle scripts
+    pub disable_lifecycle_scripts: Option<bool>,
+    /// Write each fetcher on a single line
+    pub compact: Option<bool>,
+    /// A directory of templates to render fetchers with, relative to the config file
+    pub template_dir: Option<PathBuf>,
+    /// The `node_modules` layout the packages are installed with
+    pub linker: Option<Linker>,
+}
+
+impl Config {
+    /// # Load Config
+    ///
+    /// Read and parse a `bun2nix.toml` file from disk, resolving
+    /// the paths it contains against its directory
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path).map_err(Error::ReadConfigError)?;
+        let mut config: Self = contents.parse()?;
+
+        if let Some(dir) = path.parent() {
+            config.npmrc = config.npmrc.map(|npmrc| dir.join(npmrc));
+            config.template_dir = config
+                .template_dir
+                .map(|template_dir| dir.join(template_dir));
+        }
+
+        Ok(config)
+    }
+
+    /// # Apply Config
+    ///
+    /// Fill in any options which weren't given on the command line
+    /// from the config file
+    ///
+    /// `from_cli` is given the name of each flag the file sets, and
+    /// returns whether it was also given on the command line.
+    pub fn apply(self, mut options: Options, from_cli: impl Fn(&str) -> bool) -> Options {
+        macro_rules! apply {
+            ($flag:literal, $value:expr => $field:expr) => {
+                if let Some(value) = $value.filter(|_| !from_cli($flag)) {
+                    $field = value;
+                }
+            };
+        }
+
+        apply!("copy-prefix", self.copy_prefix => options.copy_prefix);
+        apply!("copy-excludes", self.copy_excludes => options.copy_excludes);
+        apply!("gitlab-domain", self.gitlab_domain => options.gitlab_domain);
+        apply!("github-domain", self.github_domain => options.github_domain);
+        apply!("registry", self.registry.map(Some) => options.default_registry);
+        apply!("registry-mirrors", self.registry_mirrors => options.registry_mirrors);
+        apply!("prefetch-jobs", self.prefetch_jobs => options.prefetch_jobs);
+        apply!("per-host-jobs", self.per_host_jobs => options.per_host_jobs);
+        apply!("prefetch-retries", self.prefetch_retries => options.prefetch_retries);
+        apply!(
+            "prefetch-timeout",
+            self.prefetch_timeout.map(Duration::from_secs) => options.prefetch_timeout
+        );
+        apply!("prefetch-command", self.prefetch_command.map(Some) => options.prefetch_command);
+        apply!("proxy", self.proxy.map(Some) => options.proxy);
+        apply!("no-proxy", self.no_proxy => options.no_proxy);
+        apply!(
+            "no-prefetch-cache",
+            self.no_prefetch_cache.map(|no_cache| !no_cache) => options.prefetch_cache
+        );
+        apply!("collect-all-errors", self.collect_all_errors => options.collect_all_errors);
+        apply!("strict", self.strict => options.strict);
+        apply!("npmrc", self.npmrc.map(Some) => options.npmrc);
+        apply!("production", self.production => options.production);
+        apply!("fetch-submodules", self.fetch_submodules => options.fetch_submodules);
+        apply!("deep-clone", self.deep_clone => options.deep_clone);
+        apply!("all-refs", self.all_refs => options.all_refs);
+        apply!("leave-dot-git", self.leave_dot_git => options.leave_dot_git);
+        apply!("target-systems", self.target_systems => options.target_systems);
+        apply!("emit-sha256", self.emit_sha256 => options.emit_sha256);
+        apply!("stats", self.stats => options.stats);
+        apply!(
+            "disable-lifecycle-scripts",
+            self.disable_lifecycle_scripts => options.disable_lifecycle_scripts
+        );
+        apply!("compact", self.compact.map(|compact| !compact) => options.pretty);
+        apply!("template-dir", self.template_dir.map(Some) => options.template_dir);
+        apply!("linker", self.linker.map(Some) => options.linker);
+
+        options
+    }
+}
+
+impl FromStr for Config {
+    type Err = Error;
+
+    fn from_str(contents: &str) -> Result<Self> {
+        let mut table = Map::new();
+        let mut lines = contents.lines();
+
+        while let Some(line) = lines.next() {
+            let mut entry = strip_comment(line).trim().to_owned();
+            if entry.is_empty() {
+                continue;
+            }
+
+            if entry.starts_with('[') {
+                return Err(Error::MalformedConfig(format!(
+                    "tables are not supported: '{entry}'"
+                )));
+            }
+
+            // Arrays may be split over several lines
+            while bracket_depth(&entry) > 0 {
+                let Some(next) = lines.next() else {
+                    return Err(Error::MalformedConfig(format!("unclosed array: '{entry}'")));
+                };
+
+                entry.push(' ');
+                entry.push_str(strip_comment(next).trim());
+            }
+
+            let (key, value) = entry.split_once('=').ok_or_else(|| {
+                Error::MalformedConfig(format!("expected `key = value`: '{entry}'"))
+            })?;
+
+            let mut value = value.trim();
+            let parsed = parse_value(&mut value)?;
+            if !value.trim().is_empty() {
+                return Err(Error::MalformedConfig(format!(
+                    "unexpected trailing characters: '{entry}'"
+                )));
+            }
+
+            table.insert(key.trim().to_owned(), parsed);
+        }
+
+        serde_json::from_value(Value::Object(table))
+            .map_err(|err| Error::MalformedConfig(err.to_string()))
+    }
+}
+
+/// Parse a single value from the start of `input`, advancing past it
+fn parse_value(input: &mut &str) -> Result<Value> {
+    let malformed = |input: &str| Error::MalformedConfig(format!("invalid value: '{input}'"));
+
+    *input = input.trim_start();
+
+    if let Some(rest) = input.strip_prefix('[') {
+        *input = rest;
+
+        let mut values = Vec::new();
+        loop {
+            *input = input.trim_start();
+
+            if let Some(rest) = input.strip_prefix(']') {
+                *input = rest;
+                return Ok(Value::Array(values));
+            }
+
+            values.push(parse_value(input)?);
+
+            *input = input.trim_start();
+            if let Some(rest) = input.strip_prefix(',') {
+                *input = rest;
+            } else if !input.starts_with(']') {
+                return Err(malformed(input));
+            }
+        }
+    }
+
+    if let Some(rest) = input.strip_prefix('\'') {
+        let (literal, rest) = rest.split_once('\'').ok_or_else(|| malformed(input))?;
+        *input = rest;
+
+        return Ok(Value::String(literal.to_owned()));
+    }
+
+    if let Some(rest) = input.strip_prefix('"') {
+        let mut string = String::new();
+        let mut chars = rest.char_indices();
+
+        while let Some((idx, char)) = chars.next() {
+            match char {
+                '"' => {
+                    *input = &rest[idx + 1..];
+                    return Ok(Value::String(string));
+                }
+                '\\' => match chars.next().map(|(_, escaped)| escaped) {
+                    Some('n') => string.push('\n'),
+                    Some('t') => string.push('\t'),
+                    Some(escaped @ ('"' | '\\')) => string.push(escaped),
+                    _ => return Err(malformed(input)),
+                },
+                char => string.push(char),
+            }
+        }
+
+        return Err(malformed(input));
+    }
+
+    let end = input.find([',', ']']).unwrap_or(input.len());
+    let (token, rest) = input.split_at(end);
+    let token = token.trim();
+
+    let value = match token {
+        "true" => Value::Bool(true),
+        "false" => Value::Bool(false),
+        token => token
+            .replace('_', "")
+            .parse::<u64>()
+            .map(|int| Value::Number(Number::from(int)))
+            .map_err(|_| malformed(token))?,
+    };
+    *input = rest;
+
+    Ok(value)
+}
+
+/// The line with any `#` comment outside of a string removed
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+
+    for (idx, char) in line.char_indices() {
+        match (char, quote) {
+            ('#', None) => return &line[..idx],
+            ('"' | '\'', None) => quote = Some(char),
+            (char, Some(open)) if char == open => quote = None,
+            _ => (),
+        }
+    }
+
+    line
+}
+
+/// The number of `[` left unclosed, outside of strings
+fn bracket_depth(entry: &str) -> i32 {
+    let mut quote = None;
+    let mut depth = 0;
+
+    for char in entry.chars() {
+        match (char, quote) {
+            ('[', None) => depth += 1,
+            (']', None) => depth -= 1,
+            ('"' | '\'', None) => quote = Some(char),
+            (char, Some(open)) if char == open => quote = None,
+            _ => (),
+        }
+    }
+
+    depth
+}
@@ -0,0 +1,149 @@
+//! This module holds the comparison of the fetchers two lockfiles resolve to, for finding which
+//! packages a dependency update changed
+
+use std::{collections::BTreeMap, fmt};
+
+use crate::{Package, package::Fetcher};
+
+/// # Diff
+///
+/// The packages added, removed or fetched differently between two
+/// lockfiles, keyed by their lockfile entry, written one per line in
+/// sorted order so it is stable and easily grepped
+///
+/// Each line is the change, the lockfile entry, then the fetcher,
+/// source and hash from before and after the change, separated by
+/// tabs. Packages which are fetched identically are left out.
+///
+/// ```rust
+/// use bun2nix::{Options, diff_lockfiles};
+///
+/// let old = r#"{
+///   "lockfileVersion": 1,
+///   "packages": {
+///     "is-number": ["is-number@6.0.0", "", {}, "sha512-Wu1VHeILBK8KAWJUAiSZQX94GmOE45Rg6/538fKwiloUu21KncEkYGPqob2oSZ5mUT73vLGrHQjKw3KMPwfDzg=="],
+///     "is-odd": ["is-odd@3.0.1", "", {}, "sha512-CQpnWPrDwmP1+SMHXZhtLtJv90yiyVfluGsX5iNCVkrhQtU3TQHsUWPG9wkdk9Lgd5yNpAg9jQEo90CBaXgWMA=="],
+///     "zod": ["zod@3.24.2", "", {}, "sha512-lY7CDW43ECgW9u1TcT3IoXHflywfVqDYze4waEz812jR/bZ8FHDsl7pFQoSZTz5N+2NqRXs8GBwnAwo3ZNxqhQ=="],
+///   }
+/// }"#;
+/// let new = r#"{
+///   "lockfileVersion": 1,
+///   "packages": {
+///     "is-number": ["is-number@7.0.0", "", {}, "sha512-41Cifkg6e8TylSpdtTpeLVMqvSBEVzTttHvERD741+pnZ8ANv0004MRL43QKPDlK9cGvNp6NZWZUBlbGXYxxng=="],
+///     "is-odd": ["is-odd@3.0.1", "", {}, "sha512-CQpnWPrDwmP1+SMHXZhtLtJv90yiyVfluGsX5iNCVkrhQtU3TQHsUWPG9wkdk9Lgd5yNpAg9jQEo90CBaXgWMA=="],
+///     "typescript": ["typescript@5.7.3", "", {}, "sha512-84MVSjMEHP+FQRPy3pX9sTVV/INIex71s9TL2Gm5FG/WG1SqXeKyZ0k7/blY/4FdOzI12CBy1vGc4og/eus0fw=="],
+///   }
+/// }"#;
+///
+/// let options = Options::default();
+/// let diff = diff_lockfiles(old, new, &options).unwrap().to_string();
+/// let lines: Vec<_> = diff.lines().collect();
+///
+/// assert_eq!(lines.len(), 4);
+/// assert_eq!(lines[0], "change\tlockfile entry\tbefore\tafter");
+/// assert_eq!(
+///     lines[1],
+///     "changed\tis-number\t\
+///      fetchurl https://registry.npmjs.org/is-number/-/is-number-6.0.0.tgz sha512-Wu1VHeILBK8KAWJUAiSZQX94GmOE45Rg6/538fKwiloUu21KncEkYGPqob2oSZ5mUT73vLGrHQjKw3KMPwfDzg==\t\
+///      fetchurl https://registry.npmjs.org/is-number/-/is-number-7.0.0.tgz sha512-41Cifkg6e8TylSpdtTpeLVMqvSBEVzTttHvERD741+pnZ8ANv0004MRL43QKPDlK9cGvNp6NZWZUBlbGXYxxng=="
+/// );
+/// assert!(lines[2].starts_with("added\ttypescript\t-\tfetchurl https://registry.npmjs.org/typescript/-/typescript-5.7.3.tgz "));
+/// assert!(lines[3].starts_with("removed\tzod\tfetchurl https://registry.npmjs.org/zod/-/zod-3.24.2.tgz "));
+/// assert!(lines[3].ends_with("\t-"));
+/// ```
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Diff {
+    /// The packages which differ, in lockfile entry order
+    pub entries: Vec<DiffEntry>,
+}
+
+/// # Diff Entry
+///
+/// A single lockfile entry whose package was added, removed,
+/// or is fetched differently
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiffEntry {
+    /// The key of the entry in the lockfiles
+    pub key: String,
+
+    /// How the entry's package was fetched before, if it was at all
+    pub before: Option<Fetcher>,
+
+    /// How the entry's package is fetched after, if it is at all
+    pub after: Option<Fetcher>,
+}
+
+impl Diff {
+    /// # New Diff
+    ///
+    /// Compare the deserialized entries of two lockfiles, as returned
+    /// by `Lockfile::entries_with_prefetcher`
+    ///
+    /// Entries which were skipped, such as packages for other
+    /// systems, are treated as missing.
+    pub fn new(
+        before: Vec<(String, Option<Package>)>,
+        after: Vec<(String, Option<Package>)>,
+    ) -> Self {
+        let mut fetchers: BTreeMap<String, (Option<Fetcher>, Option<Fetcher>)> = BTreeMap::new();
+
+        for (key, pkg) in before {
+            fetchers.entry(key).or_default().0 = pkg.map(|pkg| pkg.fetcher);
+        }
+        for (key, pkg) in after {
+            fetchers.entry(key).or_default().1 = pkg.map(|pkg| pkg.fetcher);
+        }
+
+        let entries = fetchers
+            .into_iter()
+            .filter(|(_, (before, after))| before != after)
+            .map(|(key, (before, after))| DiffEntry { key, before, after })
+            .collect();
+
+        Self { entries }
+    }
+}
+
+impl DiffEntry {
+    /// # Change
+    ///
+    /// Whether the package was `added`, `removed` or `changed`
+    pub fn change(&self) -> &'static str {
+        match (&self.before, &self.after) {
+            (None, _) => "added",
+            (_, None) => "removed",
+            _ => "changed",
+        }
+    }
+
+    /// A fetcher's kind, source and hash, or `-` if there is none
+    fn describe(fetcher: Option<&Fetcher>) -> String {
+        let Some(fetcher) = fetcher else {
+            return "-".to_owned();
+        };
+
+        match fetcher.source_hash().filter(|hash| !hash.is_empty()) {
+            Some(hash) => format!("{} {} {hash}", fetcher.kind(), fetcher.source()),
+            None => format!("{} {}", fetcher.kind(), fetcher.source()),
+        }
+    }
+}
+
+impl fmt::Display for Diff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "change\tlockfile entry\tbefore\tafter")?;
+
+        for entry in &self.entries {
+            writeln!(
+                f,
+                "{}\t{}\t{}\t{}",
+                entry.change(),
+                entry.key,
+                DiffEntry::describe(entry.before.as_ref()),
+                DiffEntry::describe(entry.after.as_ref()),
+            )?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,80 @@
+//! This module holds the summary produced by a dry run, which reports what converting a lockfile
+//! would involve without prefetching anything
+
+use std::{collections::BTreeMap, fmt};
+
+use crate::{Package, package::FetcherKind};
+
+/// # Dry Run Summary
+///
+/// The fetchers a lockfile's packages would use, and the
+/// urls which would need a live prefetch to be hashed
+///
+/// ```rust
+/// use bun2nix::{Options, dry_run_lockfile, package::FetcherKind};
+///
+/// let lockfile = r#"{
+///   "lockfileVersion": 1,
+///   "packages": {
+///     "is-number": ["is-number@7.0.0", "", {}, "sha512-41Cifkg6e8TylSpdtTpeLVMqvSBEVzTttHvERD741+pnZ8ANv0004MRL43QKPDlK9cGvNp6NZWZUBlbGXYxxng=="],
+///     "bun": ["bun@github:oven-sh/bun#a1b2c3", {}, "oven-sh-bun-a1b2c3"],
+///   }
+/// }"#;
+///
+/// let options = Options::default();
+/// let summary = dry_run_lockfile(lockfile.to_owned(), options).unwrap();
+///
+/// assert_eq!(summary.fetchers.get(&FetcherKind::Url), Some(&1));
+/// assert_eq!(summary.fetchers.get(&FetcherKind::GitHub), Some(&1));
+/// assert_eq!(summary.prefetch_urls, ["github:oven-sh/bun?ref=a1b2c3"]);
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DryRun {
+    /// The number of packages using each kind of fetcher
+    pub fetchers: BTreeMap<FetcherKind, usize>,
+
+    /// The urls which are not cached and would need to be prefetched, in sorted order
+    pub prefetch_urls: Vec<String>,
+}
+
+impl DryRun {
+    /// # New Dry Run Summary
+    ///
+    /// Summarize the packages deserialized during a dry run,
+    /// along with the urls they would have had to prefetch
+    pub fn new(packages: &[Package], mut prefetch_urls: Vec<String>) -> Self {
+        let mut fetchers = BTreeMap::new();
+        for package in packages {
+            *fetchers.entry(package.fetcher.kind()).or_default() += 1;
+        }
+
+        prefetch_urls.sort();
+        prefetch_urls.dedup();
+
+        Self {
+            fetchers,
+            prefetch_urls,
+        }
+    }
+}
+
+impl fmt::Display for DryRun {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Packages by fetcher:")?;
+        for (fetcher, count) in &self.fetchers {
+            writeln!(f, "  {fetcher}: {count}")?;
+        }
+
+        writeln!(f)?;
+        writeln!(
+            f,
+            "Urls needing a live prefetch: {}",
+            self.prefetch_urls.len()
+        )?;
+        for url in &self.prefetch_urls {
+            writeln!(f, "  {url}")?;
+        }
+
+        Ok(())
+    }
+}
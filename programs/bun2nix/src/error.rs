@@ -1,10 +1,11 @@
 //! Errors which may occur during the running of this program
 //!
-//! This module contains two items:
+//! This module contains three items:
 //! - A giant unified error type `Error`
 //! - An alias for `std::result::Result<T, E>` with that error for convenience
+//! - The `ExitCode` the command line tool exits with for each error
 
-use std::{io, str::Utf8Error};
+use std::{io, str::Utf8Error, time::Duration};
 use thiserror::Error;
 
 /// Result alias for Errors which occur in `bun2nix`
@@ -13,6 +14,26 @@ pub type Result<T> = std::result::Result<T, Error>;
 #[allow(missing_docs)]
 #[derive(Error, Debug)]
 /// Errors which occur in `bun2nix`
+///
+/// Errors caused by the lockfile say which part of it is at fault,
+/// along with how to fix it:
+///
+/// ```rust
+/// use bun2nix::{Error, Lockfile, Options};
+///
+/// let lockfile: Lockfile = r#"{
+///   "lockfileVersion": 1,
+///   "packages": {
+///     "zod": ["zod@github:colinhacks/zod", {}, "colinhacks-zod"],
+///   }
+/// }"#.parse().unwrap();
+///
+/// let options = Options::default();
+/// let err = lockfile.packages(&options).unwrap_err();
+///
+/// assert!(matches!(&err, Error::MissingGitRef(id) if id == "github:colinhacks/zod"));
+/// assert!(err.to_string().contains("try running `bun install`"));
+/// ```
 pub enum Error {
     #[error(
         "Failed to parse lockfile as JSONC (specified here: https://github.com/oven-sh/bun/issues/11863): \n{0}.
@@ -26,30 +47,157 @@ Please make sure your bun lockfile is formatted correctly, try deleting it and r
         "Failed to parse empty lockfile, make sure you are providing a file with text contents"
     )]
     NoJsoncValue,
-    #[error("Missing @ for package name and version declaration.
+    #[error(
+        "Package identifier '{0}' has no `@` between it's name and version.
 
-Make sure all versions in your bun lockfile are formatted properly or try deleting it and running `bun install` to produce a fresh one"
+This usually means the lockfile entry is malformed, make sure all versions in your bun lockfile
+are formatted properly or try deleting it and running `bun install` to produce a fresh one"
     )]
-    NoAtInPackageIdentifier,
-    #[error( "Unsupported lockfile version: '{0}'.
+    NoAtInPackageIdentifier(String),
+    #[error(
+        "Unsupported lockfile version: '{found}', this version of `bun2nix` supports {supported}.
+
+If your lockfile is newer, consider updating `bun2nix`, or contributing to it if this version hasn't
+been supported yet. If it is older, run `bun install` with bun v1.2 or above to upgrade it"
+    )]
+    UnsupportedLockfileVersion { found: u64, supported: String },
+    #[error("The provided file does not start with the binary `bun.lockb` header")]
+    InvalidLockbHeader,
+    #[error("The binary `bun.lockb` lockfile ends before it's format version")]
+    TruncatedLockb,
+    #[error(
+        "Found a binary `bun.lockb` lockfile (format version '{0}').
 
-Consider updating your local package or contributing to `bun2nix` if this version hasn't been supported yet"
+`bun2nix` can not yet read package data from the binary format, please migrate
+to the textual `bun.lock` lockfile with:
+`bun install --save-text-lockfile --frozen-lockfile --lockfile-only`
+
+See https://bun.sh/docs/install/lockfile for more information."
     )]
-    UnsupportedLockfileVersion(u8),
+    UndecodableLockb(u32),
     #[error("Error while fetching package from it's source: \n{0}")]
     FetchingFailed(io::Error),
     #[error("\nConsole error while fetching package from it's source: \n\n{0}")]
     FetchingError(String),
+    #[error("Fetching the package from it's source timed out after {0:?}")]
+    FetchingTimedOut(Duration),
+    #[error("Timed out while prefetching `{package}` from '{url}'")]
+    PrefetchTimeout { package: String, url: String },
+    #[error("Failed to prefetch `{package}` from '{url}': {source}")]
+    PrefetchFailed {
+        package: String,
+        url: String,
+        source: Box<Error>,
+    },
+    #[error(
+        "`{package}` would need to be prefetched, which `--frozen` forbids
+
+Run without `--frozen`, or with `--previous-output`, to record it's hash"
+    )]
+    PrefetchRequiredButFrozen { package: String },
+    #[error("The prefetch command was empty")]
+    EmptyPrefetchCommand,
     #[error("An invalid utf8 string was returned from stdin while fetching a package: {0}")]
     InvalidUtf8String(Utf8Error),
-    #[error("A workspace package was missing the `workspace:` specifier")]
-    MissingWorkspaceSpecifier,
-    #[error("A file package was missing the `file:` specifier")]
-    MissingFileSpecifier,
-    #[error("A git url was missing it's ref")]
-    MissingGitRef,
-    #[error("A github url was formatted incorrectly")]
-    ImproperGithubUrl,
+    #[error(
+        "Workspace package '{0}' is missing the `workspace:` specifier.
+
+Workspace entries look like `name@workspace:packages/name`, try running `bun install` to regenerate them"
+    )]
+    MissingWorkspaceSpecifier(String),
+    #[error(
+        "Workspace package '{0}' does not match any workspace of the lockfile.
+
+Make sure a workspace with this name is listed in the lockfile's `workspaces`, or try running `bun install` to regenerate it"
+    )]
+    UnknownWorkspacePackage(String),
+    #[error(
+        "Workspace package '{package}' requires version '{range}', but the workspace is at version '{version}'.
+
+Update the range in `package.json` and run `bun install` to update the lockfile"
+    )]
+    UnsatisfiedWorkspaceRange {
+        package: String,
+        range: String,
+        version: String,
+    },
+    #[error(
+        "Local package '{0}' is missing the `file:` specifier.
+
+Declare it as `file:./path/to/package` in `package.json` and run `bun install` to update the lockfile"
+    )]
+    MissingFileSpecifier(String),
+    #[error("A `file://` tarball url was formatted incorrectly, expected an absolute path: '{0}'")]
+    ImproperFileUrl(String),
+    #[error(
+        "Git dependency '{0}' has no `#` followed by the commit it is locked to.
+
+Bun records the commit of every git dependency it installs, try running `bun install` to update the lockfile"
+    )]
+    MissingGitRef(String),
+    #[error(
+        "GitHub dependency '{0}' is not of the form `github:owner/repo`.
+
+Check the dependency in `package.json` and run `bun install` to update the lockfile"
+    )]
+    ImproperGithubUrl(String),
+    #[error(
+        "GitLab dependency '{0}' is not of the form `gitlab:owner/repo`.
+
+Check the dependency in `package.json` and run `bun install` to update the lockfile"
+    )]
+    ImproperGitLabUrl(String),
+    #[error(
+        "Bitbucket dependency '{0}' is not of the form `bitbucket:owner/repo`.
+
+Check the dependency in `package.json` and run `bun install` to update the lockfile"
+    )]
+    ImproperBitbucketUrl(String),
+    #[error("Unknown target system: '{0}', expected a nix system such as `x86_64-linux`")]
+    UnknownTargetSystem(String),
+    #[error(
+        "The jsr package `{0}` has no tarball url in the lockfile
+
+Bun records the url each jsr package was fetched from. Your lockfile may
+be corrupt, try deleting it and running `bun install` to produce a fresh one"
+    )]
+    MissingJsrTarballUrl(String),
+    #[error(
+        "Malformed integrity hash in lockfile: '{0}'
+
+Expected a `sha1-`, `sha256-` or `sha512-` prefixed, base64 encoded digest."
+    )]
+    MalformedIntegrity(String),
+    #[error(
+        "Unexpected hash format for package `{package}`: '{hash}'
+
+Expected a `sha1-`, `sha256-` or `sha512-` prefixed, base64 encoded integrity hash. Your lockfile
+may be corrupt, try deleting it and running `bun install` to produce a fresh one"
+    )]
+    UnexpectedHashFormat { package: String, hash: String },
+    #[error(
+        "Unsupported integrity algorithm `{algorithm}` in '{integrity}', expected one of `sha1`, `sha256` or `sha512`"
+    )]
+    UnsupportedIntegrityAlgorithm {
+        algorithm: String,
+        integrity: String,
+    },
+    #[error(
+        "Only Yarn v1 `yarn.lock` files can be read, try converting to a bun lockfile with `bun install`"
+    )]
+    UnsupportedYarnLockVersion,
+    #[error("Failed to parse `yarn.lock` line: '{0}'")]
+    MalformedYarnLock(String),
+    #[error(
+        "Unsupported resolution for `{0}` in `yarn.lock`, only npm, git, github and tarball dependencies can be read"
+    )]
+    UnsupportedYarnResolution(String),
+    #[error(
+        "`{0}` has no integrity hash in `yarn.lock`, try running `yarn install` with a recent version of yarn to add one"
+    )]
+    MissingYarnIntegrity(String),
+    #[error("Expected a string in the lockfile package entry, found: '{0}'")]
+    UnexpectedValueType(String),
     #[error("Unexpected package entry length: \n{0}")]
     UnexpectedPackageEntryLength(usize),
     #[error("Failed to render template: '\n{0}'")]
@@ -66,6 +214,20 @@ Please switch to the native cli instead to use this dependency.
 "
     )]
     UnsupportedWASMCliAction(String),
+    #[error(
+        "Failed to run `nix-instantiate` to validate the generated nix expression: {0}
+
+Validation requires nix to be installed, leave out `--validate` if it isn't available"
+    )]
+    ValidateCommandError(std::io::Error),
+    #[error(
+        "The generated nix expression failed to parse, {location}:
+
+{message}
+
+This is a bug in `bun2nix`, please report it along with the lockfile entry of the package"
+    )]
+    InvalidNixOutput { location: String, message: String },
     #[error("IO Error Occurred: \n{0}
 
 Make sure that the bun lockfile path you gave points to a valid path.
@@ -77,6 +239,241 @@ See https://bun.sh/docs/install/lockfile to find out more information about the
 Try `bun2nix -h` for help.
     ")]
     ReadLockfileError(#[from] io::Error),
+    #[error("Failed to read the provided `.npmrc` file: \n{0}")]
+    ReadNpmrcError(io::Error),
+    #[error(
+        "The `.npmrc` auth token for `{0}` is not given as a single environment variable.
+
+Auth tokens are never written into the output, as it would leave them readable by anyone in the nix
+store, so must instead be read as each package is fetched. Give the token as a reference to the
+variable holding it, such as:
+```ini
+{0}:_authToken=${{NPM_TOKEN}}
+```
+and make sure it is set for the nix daemon."
+    )]
+    UnmappedAuthToken(String),
+    #[error("Failed to read the project's `bunfig.toml` file: \n{0}")]
+    ReadBunfigError(io::Error),
+    #[error("Unsupported linker '{0}', expected either `isolated` or `hoisted`")]
+    UnsupportedLinker(String),
+    #[error("Failed to read the provided `bun2nix.toml` file: \n{0}")]
+    ReadConfigError(io::Error),
+    #[error("Failed to parse `bun2nix.toml`, {0}")]
+    MalformedConfig(String),
+    #[error("Failed to read the template '{path}': \n{source}")]
+    ReadTemplateError { path: String, source: io::Error },
+    #[error("Failed to read the local tarball '{path}' to hash it: \n{source}")]
+    ReadLocalTarballError { path: String, source: io::Error },
+    #[error("The template '{template}' is invalid, {reason}")]
+    MalformedTemplate { template: String, reason: String },
+    #[error("Failed to write the generated nix expression: \n{0}")]
+    WriteOutputError(io::Error),
+    #[error("Failed to read the existing nix expression to check it: \n{0}")]
+    ReadOutputError(io::Error),
+    #[error("Failed to read the previous output to reuse its hashes: \n{0}")]
+    ReadPreviousOutputError(io::Error),
+    #[error(
+        "'{path}' is out of date with the lockfile, regenerate it with `bun2nix -o {path}`:\n\n{summary}"
+    )]
+    StaleOutput { path: String, summary: String },
+    #[error("Failed to write the JSON package list: \n{0}")]
+    WriteJsonOutputError(io::Error),
+    #[error("Failed to write the lockfile report: \n{0}")]
+    WriteReportError(io::Error),
+    #[error("Failed to read a workspace's `package.json` file: \n{0}")]
+    ReadManifestError(io::Error),
+    #[error("`{package}` is declared in '{manifest}', but has no entry in the lockfile")]
+    MissingLockfileEntry { package: String, manifest: String },
+    #[error(
+        "The lockfile is missing {} declared dependencies, try running `bun install` to update it:{}",
+        .0.len(),
+        .0.iter().map(|err| format!("\n\n{err}")).collect::<String>()
+    )]
+    MissingLockfileEntries(Vec<Error>),
+    #[error(
+        "Two packages would be written under the attribute name `{name}`, fetched from '{first}' and '{second}'"
+    )]
+    DuplicateAttributeName {
+        name: String,
+        first: String,
+        second: String,
+    },
+    #[error("`{name}` can't be written out, as {reason}")]
+    InvalidPackage { name: String, reason: String },
+    #[error("`{package}` uses the catalog `{catalog}`, which has no entry for it")]
+    MissingCatalogEntry { package: String, catalog: String },
+    #[error(
+        "Failed to deserialize {} packages:{}",
+        .0.len(),
+        .0.iter().map(|(name, err)| format!("\n\n`{name}`: {err}")).collect::<String>()
+    )]
+    Aggregate(Vec<(String, Error)>),
+}
+
+/// # Exit Code
+///
+/// The stable exit codes the command line tool fails with, one
+/// per class of error, so scripts can tell them apart, such as to
+/// retry after a network error but not after a malformed lockfile
+///
+/// | Code | Class      | Cause                                                       |
+/// |------|------------|-------------------------------------------------------------|
+/// | 1    | `Failure`  | Anything not covered below, such as an invalid option       |
+/// | 2    | -          | Command line usage errors, as reported by `clap`            |
+/// | 3    | `Parse`    | A malformed or unsupported lockfile, config or manifest     |
+/// | 4    | `Prefetch` | A failed or timed out prefetch, usually a network error     |
+/// | 5    | `Io`       | A file which could not be read or written                   |
+/// | 6    | `Stale`    | `--check` found the output out of date with the lockfile    |
+///
+/// ```rust
+/// use bun2nix::{
+///     Options, Result, convert_lockfile_to_nix_expression, error::ExitCode,
+///     generate_bun_nix_with_prefetcher,
+///     lockfile::{Prefetch, Prefetcher},
+/// };
+///
+/// struct Offline;
+///
+/// impl Prefetcher for Offline {
+///     fn prefetch(&self, _url: &str, _options: &Options) -> Result<Prefetch> {
+///         Err(bun2nix::Error::FetchingError("Could not resolve host: github.com".to_owned()))
+///     }
+/// }
+///
+/// let options = || Options::default();
+/// let exit_code = |lockfile: &str, options: Options| {
+///     convert_lockfile_to_nix_expression(lockfile.to_owned(), options)
+///         .unwrap_err()
+///         .exit_code()
+/// };
+/// let empty = r#"{ "lockfileVersion": 1, "packages": {} }"#;
+///
+/// assert_eq!(exit_code("{ not json", options()), ExitCode::Parse);
+///
+/// let zod = r#"{
+///   "lockfileVersion": 1,
+///   "packages": {
+///     "zod": ["zod@github:colinhacks/zod#5bfc8f2", {}, "colinhacks-zod-5bfc8f2"],
+///   }
+/// }"#;
+/// let err = generate_bun_nix_with_prefetcher(zod, &options(), &Offline).unwrap_err();
+/// assert_eq!(err.exit_code(), ExitCode::Prefetch);
+///
+/// let missing = std::env::temp_dir().join("bun2nix-exit-code-missing/bun.nix");
+/// let write_missing = Options { output: Some(missing.clone()), ..options() };
+/// assert_eq!(exit_code(empty, write_missing), ExitCode::Io);
+///
+/// let stale = std::env::temp_dir().join("bun2nix-exit-code-stale.nix");
+/// std::fs::write(&stale, "{ }").unwrap();
+/// let check_stale = Options { output: Some(stale), check: true, ..options() };
+/// assert_eq!(exit_code(empty, check_stale), ExitCode::Stale);
+///
+/// assert_eq!(i32::from(ExitCode::Stale), 6);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitCode {
+    /// Any error not covered by another class
+    Failure = 1,
+    /// A malformed or unsupported lockfile, config or manifest
+    Parse = 3,
+    /// A failed or timed out prefetch
+    Prefetch = 4,
+    /// A file which could not be read or written
+    Io = 5,
+    /// The output checked with `--check` is out of date, or a
+    /// package needs a prefetch which `--frozen` forbids
+    Stale = 6,
+}
+
+impl From<ExitCode> for i32 {
+    fn from(code: ExitCode) -> Self {
+        code as i32
+    }
+}
+
+impl Error {
+    /// # Exit Code
+    ///
+    /// The class of this error, as the code the command line tool exits with
+    ///
+    /// Every variant is listed, so a new one must be classified.
+    pub fn exit_code(&self) -> ExitCode {
+        match self {
+            Self::ParseJsonc(_)
+            | Self::ParseRustType(_)
+            | Self::NoJsoncValue
+            | Self::NoAtInPackageIdentifier(_)
+            | Self::UnsupportedLockfileVersion { .. }
+            | Self::InvalidLockbHeader
+            | Self::TruncatedLockb
+            | Self::UndecodableLockb(_)
+            | Self::MissingWorkspaceSpecifier(_)
+            | Self::UnknownWorkspacePackage(_)
+            | Self::UnsatisfiedWorkspaceRange { .. }
+            | Self::MissingFileSpecifier(_)
+            | Self::ImproperFileUrl(_)
+            | Self::MissingGitRef(_)
+            | Self::ImproperGithubUrl(_)
+            | Self::ImproperGitLabUrl(_)
+            | Self::ImproperBitbucketUrl(_)
+            | Self::MissingJsrTarballUrl(_)
+            | Self::MalformedIntegrity(_)
+            | Self::UnexpectedHashFormat { .. }
+            | Self::UnsupportedIntegrityAlgorithm { .. }
+            | Self::UnsupportedYarnLockVersion
+            | Self::MalformedYarnLock(_)
+            | Self::UnsupportedYarnResolution(_)
+            | Self::MissingYarnIntegrity(_)
+            | Self::UnexpectedValueType(_)
+            | Self::UnexpectedPackageEntryLength(_)
+            | Self::UnsupportedLinker(_)
+            | Self::MalformedConfig(_)
+            | Self::MalformedTemplate { .. }
+            | Self::MissingLockfileEntry { .. }
+            | Self::MissingLockfileEntries(_)
+            | Self::MissingCatalogEntry { .. }
+            | Self::UnmappedAuthToken(_) => ExitCode::Parse,
+            Self::FetchingFailed(_)
+            | Self::FetchingError(_)
+            | Self::FetchingTimedOut(_)
+            | Self::PrefetchTimeout { .. }
+            | Self::PrefetchFailed { .. }
+            | Self::InvalidUtf8String(_) => ExitCode::Prefetch,
+            Self::ReadLockfileError(_)
+            | Self::ReadNpmrcError(_)
+            | Self::ReadBunfigError(_)
+            | Self::ReadConfigError(_)
+            | Self::ReadTemplateError { .. }
+            | Self::ReadLocalTarballError { .. }
+            | Self::WriteOutputError(_)
+            | Self::ReadOutputError(_)
+            | Self::ReadPreviousOutputError(_)
+            | Self::WriteJsonOutputError(_)
+            | Self::WriteReportError(_)
+            | Self::ReadManifestError(_) => ExitCode::Io,
+            Self::StaleOutput { .. } | Self::PrefetchRequiredButFrozen { .. } => ExitCode::Stale,
+            Self::EmptyPrefetchCommand
+            | Self::UnknownTargetSystem(_)
+            | Self::TemplateError(_)
+            | Self::UnsupportedWASMCliAction(_)
+            | Self::ValidateCommandError(_)
+            | Self::InvalidNixOutput { .. }
+            | Self::DuplicateAttributeName { .. }
+            | Self::InvalidPackage { .. } => ExitCode::Failure,
+            // Packages which failed for the same reason keep its class
+            Self::Aggregate(errors) => {
+                let mut codes = errors.iter().map(|(_, err)| err.exit_code());
+                let first = codes.next().unwrap_or(ExitCode::Failure);
+
+                if codes.all(|code| code == first) {
+                    first
+                } else {
+                    ExitCode::Failure
+                }
+            }
+        }
+    }
 }
 
 #[cfg(target_arch = "wasm32")]
@@ -3,36 +3,430 @@
 
 #![warn(missing_docs)]
 
+pub mod bunfig;
+pub mod config;
+pub mod diff;
+pub mod dry_run;
 pub mod error;
 pub mod lockfile;
 pub mod nix_expression;
+pub mod npmrc;
 pub mod options;
 pub mod package;
+pub mod report;
+pub mod stats;
+pub mod validate;
 
+use bunfig::{BUNFIG_FILE_NAME, Bunfig};
+pub use diff::Diff;
+pub use dry_run::DryRun;
 pub use error::{Error, Result};
 pub use lockfile::Lockfile;
+use lockfile::{Entries, NixPrefetcher, Prefetcher, is_yarn_lock};
 use nix_expression::NixExpression;
 pub use options::Options;
 pub use package::Package;
+use report::Report;
+use stats::{CountingPrefetcher, Stats};
+use validate::validate_nix;
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    ffi::OsString,
+    fs, io,
+    path::Path,
+    time::Instant,
+};
+
+/// The most changed lines shown when `options.check` finds stale output
+const STALE_OUTPUT_SUMMARY_LINES: usize = 20;
 
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 
 /// # Convert Bun Lockfile to a Nix expression
 ///
-/// Takes a string input of the contents of a bun lockfile and converts it into a ready to use Nix expression which fetches the packages
+/// Takes a string input of the contents of a bun lockfile, or a Yarn v1 `yarn.lock`, and converts it into a ready to use Nix expression which fetches the packages
+///
+/// Packages are always written in the same, sorted, order:
+///
+/// ```rust
+/// use bun2nix::{Options, convert_lockfile_to_nix_expression};
+///
+/// let lockfile = |packages: &[&str]| {
+///     format!(r#"{{ "lockfileVersion": 1, "packages": {{ {} }} }}"#, packages.join(","))
+/// };
+///
+/// let packages = [
+///     r#""typescript": ["typescript@5.7.3", "", {}, "sha512-84MVSjMEHP+FQRPy3pX9sTVV/INIex71s9TL2Gm5FG/WG1SqXeKyZ0k7/blY/4FdOzI12CBy1vGc4og/eus0fw=="]"#,
+///     r#""@types/bun": ["@types/bun@1.2.4", "", {}, "sha512-QtuV5OMR8/rdKJs213iwXDpfVvnskPXY/S0ZiFbsTjQZycuqPbMW8Gf/XhLfwE5njW8sxI2WjISURXPlHypMFA=="]"#,
+///     r#""undici-types": ["undici-types@6.20.0", "", {}, "sha512-Ny6QZ2Nju20vw1SRHe3d9jVu6gJ+4e3+MMpqu7pqE5HT6WsTSlce++GQmK5UXS8mzV8DSYHrQH+Xrf2jVcuKNg=="]"#,
+/// ];
+/// let shuffled = [packages[2], packages[0], packages[1]];
+///
+/// let options = || Options::default();
+///
+/// assert_eq!(
+///     convert_lockfile_to_nix_expression(lockfile(&packages), options()).unwrap(),
+///     convert_lockfile_to_nix_expression(lockfile(&shuffled), options()).unwrap(),
+/// );
+/// ```
+///
+/// With `options.json_output`, the resolved packages are also written
+/// out as JSON, for tooling which can't easily read nix:
+///
+/// ```rust
+/// use bun2nix::{Options, convert_lockfile_to_nix_expression};
+/// use serde_json::Value;
+///
+/// let lockfile = r#"{
+///   "lockfileVersion": 1,
+///   "packages": {
+///     "is-number": ["is-number@7.0.0", "", {}, "sha512-41Cifkg6e8TylSpdtTpeLVMqvSBEVzTttHvERD741+pnZ8ANv0004MRL43QKPDlK9cGvNp6NZWZUBlbGXYxxng=="],
+///   }
+/// }"#;
+///
+/// let json_output = std::env::temp_dir().join("bun2nix-json-output.json");
+/// let options = Options {
+///     json_output: Some(json_output.clone()),
+///     ..Default::default()
+/// };
+/// convert_lockfile_to_nix_expression(lockfile.to_owned(), options).unwrap();
+///
+/// let packages: Value = serde_json::from_str(&std::fs::read_to_string(json_output).unwrap()).unwrap();
+///
+/// assert_eq!(packages[0]["name"], "is-number@7.0.0");
+/// assert_eq!(
+///     packages[0]["fetcher"]["FetchUrl"]["url"],
+///     "https://registry.npmjs.org/is-number/-/is-number-7.0.0.tgz"
+/// );
+/// ```
+///
+/// With `options.output`, the expression is also written to that file,
+/// which is left untouched when it already holds the same expression:
+///
+/// ```rust
+/// use bun2nix::{Options, convert_lockfile_to_nix_expression};
+///
+/// let lockfile = r#"{
+///   "lockfileVersion": 1,
+///   "packages": {
+///     "is-number": ["is-number@7.0.0", "", {}, "sha512-41Cifkg6e8TylSpdtTpeLVMqvSBEVzTttHvERD741+pnZ8ANv0004MRL43QKPDlK9cGvNp6NZWZUBlbGXYxxng=="],
+///   }
+/// }"#;
+///
+/// let output = std::env::temp_dir().join("bun2nix-output.nix");
+/// let options = || Options {
+///     output: Some(output.clone()),
+///     ..Default::default()
+/// };
+///
+/// let nix = convert_lockfile_to_nix_expression(lockfile.to_owned(), options()).unwrap();
+/// assert_eq!(std::fs::read_to_string(&output).unwrap(), nix);
+///
+/// let modified = std::fs::metadata(&output).unwrap().modified().unwrap();
+/// convert_lockfile_to_nix_expression(lockfile.to_owned(), options()).unwrap();
+/// assert_eq!(std::fs::metadata(&output).unwrap().modified().unwrap(), modified);
+/// ```
+///
+/// With `options.check` as well, the file is compared against instead,
+/// failing if it is out of date:
+///
+/// ```rust
+/// use bun2nix::{Error, Options, convert_lockfile_to_nix_expression};
+///
+/// let lockfile = r#"{
+///   "lockfileVersion": 1,
+///   "packages": {
+///     "is-number": ["is-number@7.0.0", "", {}, "sha512-41Cifkg6e8TylSpdtTpeLVMqvSBEVzTttHvERD741+pnZ8ANv0004MRL43QKPDlK9cGvNp6NZWZUBlbGXYxxng=="],
+///   }
+/// }"#;
+///
+/// let output = std::env::temp_dir().join("bun2nix-check.nix");
+/// let options = |check| Options {
+///     output: Some(output.clone()),
+///     check,
+///     ..Default::default()
+/// };
+///
+/// let nix = convert_lockfile_to_nix_expression(lockfile.to_owned(), options(false)).unwrap();
+/// assert!(convert_lockfile_to_nix_expression(lockfile.to_owned(), options(true)).is_ok());
+///
+/// let stale = nix.replace(
+///     r#"    url = "https://registry.npmjs.org/is-number/-/is-number-7.0.0.tgz""#,
+///     r#"    url = "https://registry.npmjs.org/is-number/-/is-number-6.0.0.tgz""#,
+/// );
+/// std::fs::write(&output, &stale).unwrap();
+///
+/// let Err(Error::StaleOutput { summary, .. }) =
+///     convert_lockfile_to_nix_expression(lockfile.to_owned(), options(true))
+/// else {
+///     panic!("Expected the stale output to be found");
+/// };
+/// assert_eq!(summary.lines().count(), 2);
+/// assert!(summary.starts_with(r#"-     url = "https://registry.npmjs.org/is-number/-/is-number-6.0.0.tgz";"#));
+///
+/// // The existing file is left as is
+/// assert_eq!(std::fs::read_to_string(&output).unwrap(), stale);
+/// ```
 #[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
 #[cfg_attr(target_arch = "wasm32", no_mangle)]
 pub fn convert_lockfile_to_nix_expression(contents: String, options: Options) -> Result<String> {
-    let lockfile = contents.parse::<Lockfile>()?;
+    let start = Instant::now();
+    let prefetcher = CountingPrefetcher::new(&NixPrefetcher);
+
+    let (lockfile_version, entries) = read_entries(&contents, &options, &prefetcher)?;
+    drop(contents);
+
+    if let Some(report) = &options.report {
+        fs::write(report, Report::new(&entries).to_string()).map_err(Error::WriteReportError)?;
+    }
+
+    let packages = key_entries(entries, options.strict)?;
+
+    if let Some(json_output) = &options.json_output {
+        let json = serde_json::to_string_pretty(&packages.values().collect::<Vec<_>>())?;
+        fs::write(json_output, json).map_err(Error::WriteJsonOutputError)?;
+    }
+
+    let stats = options
+        .stats
+        .then(|| Stats::new(packages.values(), prefetcher.count(), Default::default()));
+
+    let output = options.output.clone();
+    let check = options.check;
+    let validate = options.validate;
+    let options = with_bunfig_linker(options)?;
+    let nix = NixExpression::new(packages, lockfile_version)?.render_with_options(options)?;
 
-    if lockfile.lockfile_version != 1 {
-        return Err(Error::UnsupportedLockfileVersion(lockfile.lockfile_version));
+    if validate {
+        validate_nix(&nix)?;
+    }
+
+    match output {
+        Some(output) if check => check_output(&output, &nix)?,
+        Some(output) => write_output(&output, &nix).map_err(Error::WriteOutputError)?,
+        None => (),
+    }
+
+    // Written to stderr, so as not to mix with the expression on stdout
+    if let Some(stats) = stats {
+        eprint!(
+            "{}",
+            Stats {
+                elapsed: start.elapsed(),
+                ..stats
+            }
+        );
+    }
+
+    Ok(nix)
+}
+
+/// # Generate `bun.nix`
+///
+/// Takes the contents of a bun lockfile, or a Yarn v1 `yarn.lock`, and
+/// returns the rendered Nix expression, without writing it anywhere
+///
+/// Unlike `convert_lockfile_to_nix_expression`, `options.output`,
+/// `options.check` and `options.json_output` are ignored, so that only
+/// the prefetch cache, if `options.prefetch_cache` is set, is ever
+/// written to.
+///
+/// ```rust
+/// use bun2nix::{Options, generate_bun_nix};
+///
+/// let lockfile = r#"{
+///   "lockfileVersion": 1,
+///   "packages": {
+///     "is-number": ["is-number@7.0.0", "", {}, "sha512-41Cifkg6e8TylSpdtTpeLVMqvSBEVzTttHvERD741+pnZ8ANv0004MRL43QKPDlK9cGvNp6NZWZUBlbGXYxxng=="],
+///   }
+/// }"#;
+///
+/// let output = std::env::temp_dir().join("bun2nix-generate-bun-nix.nix");
+/// let _ = std::fs::remove_file(&output);
+///
+/// let options = Options {
+///     output: Some(output.clone()),
+///     ..Default::default()
+/// };
+/// let nix = generate_bun_nix(lockfile, &options).unwrap();
+///
+/// assert!(nix.contains(r#""is-number@7.0.0" = fetchurl {"#));
+/// assert!(!output.exists());
+/// ```
+pub fn generate_bun_nix(lockfile: &str, options: &Options) -> Result<String> {
+    generate_bun_nix_with_prefetcher(lockfile, options, &NixPrefetcher)
+}
+
+/// # Generate `bun.nix` With Prefetcher
+///
+/// Generate a `bun.nix` as with `generate_bun_nix`, calculating
+/// the hashes of packages which must be prefetched with the
+/// given prefetcher in place of `nix flake prefetch`
+pub fn generate_bun_nix_with_prefetcher(
+    lockfile: &str,
+    options: &Options,
+    prefetcher: &dyn Prefetcher,
+) -> Result<String> {
+    let (lockfile_version, entries) = read_entries(lockfile, options, prefetcher)?;
+    let packages = key_entries(entries, options.strict)?;
+
+    let nix = NixExpression::new(packages, lockfile_version)?
+        .render_with_options(with_bunfig_linker(options.clone())?)?;
+
+    if options.validate {
+        validate_nix(&nix)?;
+    }
+
+    Ok(nix)
+}
+
+/// # Dry Run Bun Lockfile
+///
+/// Takes a string input of the contents of a bun lockfile, or a Yarn v1 `yarn.lock`, and summarizes the fetchers its packages
+/// use, and which of them would need to be prefetched, without prefetching or rendering anything
+pub fn dry_run_lockfile(contents: String, options: Options) -> Result<DryRun> {
+    let lockfile = parse_text_lockfile(&contents)?;
+
+    Lockfile::check_version(lockfile.lockfile_version.into())?;
+
+    lockfile.check_manifests(&options)?;
+    lockfile.dry_run(&options)
+}
+
+/// # Diff Bun Lockfiles
+///
+/// Takes the contents of two bun lockfiles, or Yarn v1 `yarn.lock`s, and compares the fetchers
+/// their packages resolve to, for reviewing what a dependency update changed
+///
+/// Both lockfiles are deserialized exactly as they would be converted, so any packages whose
+/// hashes aren't known are prefetched.
+pub fn diff_lockfiles(old: &str, new: &str, options: &Options) -> Result<Diff> {
+    let (_, old) = read_entries(old, options, &NixPrefetcher)?;
+    let (_, new) = read_entries(new, options, &NixPrefetcher)?;
+
+    Ok(Diff::new(old, new))
+}
+
+/// Parse either a bun lockfile or, as they are told apart by
+/// their first line, a Yarn v1 `yarn.lock`
+fn parse_text_lockfile(contents: &str) -> Result<Lockfile> {
+    if is_yarn_lock(contents) {
+        Lockfile::from_yarn_lock(contents)
+    } else {
+        contents.parse()
+    }
+}
+
+/// Deserialize the entries of a lockfile from it's contents, along with
+/// their lockfile keys and the `lockfileVersion`
+///
+/// Bun lockfiles are deserialized as they are read, rather than parsed first.
+fn read_entries(
+    contents: &str,
+    options: &Options,
+    prefetcher: &dyn Prefetcher,
+) -> Result<(u8, Entries)> {
+    if !is_yarn_lock(contents) {
+        return Lockfile::stream_entries_with_prefetcher(contents, options, prefetcher);
+    }
+
+    let lockfile = Lockfile::from_yarn_lock(contents)?;
+    let lockfile_version = lockfile.lockfile_version;
+
+    Lockfile::check_version(lockfile_version.into())?;
+    lockfile.check_manifests(options)?;
+
+    Ok((
+        lockfile_version,
+        lockfile.entries_with_prefetcher(options, prefetcher)?,
+    ))
+}
+
+/// Key the packages of the deserialized entries, ready to render
+fn key_entries(
+    entries: Vec<(String, Option<Package>)>,
+    strict: bool,
+) -> Result<BTreeMap<String, Package>> {
+    let packages = entries.into_iter().filter_map(|(_, pkg)| pkg).collect();
+
+    NixExpression::key_packages(packages, strict)
+}
+
+/// Fill in the linker from the `bunfig.toml` in `options.root`, if one
+/// wasn't given and the project has one
+fn with_bunfig_linker(mut options: Options) -> Result<Options> {
+    let Some(root) = options.root.as_ref().filter(|_| options.linker.is_none()) else {
+        return Ok(options);
+    };
+
+    let path = root.join(BUNFIG_FILE_NAME);
+    if path.is_file() {
+        options.linker = Bunfig::load(&path)?.linker;
+    }
+
+    Ok(options)
+}
+
+/// Check that `path` already holds exactly `contents`, summarizing
+/// the lines which differ if it doesn't
+fn check_output(path: &Path, contents: &str) -> Result<()> {
+    let existing = match fs::read_to_string(path) {
+        Ok(existing) => existing,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(Error::ReadOutputError(err)),
     };
 
-    let mut packages = lockfile.packages();
-    packages.sort();
-    packages.dedup_by(|a, b| a.name == b.name);
+    if existing == contents {
+        return Ok(());
+    }
+
+    let existing_lines: HashSet<_> = existing.lines().collect();
+    let lines: HashSet<_> = contents.lines().collect();
+
+    let removed = existing.lines().filter(|line| !lines.contains(line));
+    let added = contents
+        .lines()
+        .filter(|line| !existing_lines.contains(line));
+
+    let mut summary: Vec<String> = removed
+        .map(|line| format!("- {line}"))
+        .chain(added.map(|line| format!("+ {line}")))
+        .collect();
+    let total = summary.len();
+    summary.truncate(STALE_OUTPUT_SUMMARY_LINES);
+
+    if total > STALE_OUTPUT_SUMMARY_LINES {
+        summary.push(format!(
+            "... and {} more",
+            total - STALE_OUTPUT_SUMMARY_LINES
+        ));
+    }
+
+    Err(Error::StaleOutput {
+        path: path.display().to_string(),
+        summary: summary.join("\n"),
+    })
+}
+
+/// Replace the contents of `path` by writing to a temporary file beside it and
+/// renaming that into place, so an interrupted write never leaves it truncated
+///
+/// The file is left alone if it already holds `contents`, to keep its mtime
+fn write_output(path: &Path, contents: &str) -> io::Result<()> {
+    if fs::read(path).is_ok_and(|existing| existing == contents.as_bytes()) {
+        return Ok(());
+    }
+
+    let mut tmp_name = OsString::from(".");
+    tmp_name.push(path.file_name().unwrap_or_default());
+    tmp_name.push(format!(".{}.tmp", std::process::id()));
+    let tmp_path = path.with_file_name(tmp_name);
 
-    NixExpression::new(packages)?.render_with_options(options)
+    fs::write(&tmp_path, contents)
+        .and_then(|()| fs::rename(&tmp_path, path))
+        .inspect_err(|_| {
+            let _ = fs::remove_file(&tmp_path);
+        })
 }
@@ -1,25 +1,55 @@
 //! This module holds everything related to deserialization of the bun lockfile, including type
 //! mappings and custom deserialization methods
 
-use std::{collections::HashMap, str::FromStr};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs, mem,
+    ops::RangeInclusive,
+    panic,
+    str::FromStr,
+    sync::Mutex,
+    thread,
+    time::Instant,
+};
 
-use log::warn;
-use serde::{Deserialize, Deserializer, Serialize};
+use log::{debug, info, warn};
+use serde::{Deserialize, Deserializer, Serialize, de::DeserializeSeed};
 use serde_json::Value;
 
 use crate::{
-    Package,
+    Options, Package,
+    dry_run::DryRun,
     error::{Error, Result},
+    options::default_prefetch_jobs,
+    package::Fetcher,
 };
 
+mod jsonc;
+mod lockb;
 mod package_deserializer;
-mod package_visitor;
+mod stream;
+mod yarn_lock;
+pub use jsonc::StripJsonc;
+pub use lockb::{LOCKB_HEADER, is_lockb, lockb_format_version};
 pub use package_deserializer::{
-    PackageDeserializer, drop_prefix, split_once_owned, swap_remove_value,
+    DEFAULT_PREFETCH_COMMAND, DEFAULT_PREFETCH_RETRIES, DEFAULT_PREFETCH_TIMEOUT,
+    DeserializerContext, LIFECYCLE_SCRIPTS, LocalWorkspace, NIX_PREFETCH_GIT_COMMAND,
+    NixPrefetcher, PREFETCH_CACHE_VERSION, PREFETCH_RETRY_DELAY, PackageDeserializer, Prefetch,
+    PrefetchCache, Prefetcher, PreviousOutput, Progress, Proxy, TargetSystem, Values, drop_prefix,
+    prefetch_host, satisfies_range, split_git_subdirectory, split_once_owned, strip_name_prefix,
+    strip_npm_alias, swap_remove_value,
 };
-pub use package_visitor::PackageVisitor;
+pub use yarn_lock::{is_yarn_lock, parse_yarn_lock};
+
+use stream::{CollectPackages, LockfileSeed, ReadPackages, Results, StreamPackages};
+
+/// The `lockfileVersion`s of the bun lockfiles which can be read
+pub const SUPPORTED_LOCKFILE_VERSIONS: RangeInclusive<u64> = 1..=1;
 
-#[derive(Serialize, Deserialize, Debug)]
+/// The packages deserialized from a lockfile, along with their lockfile keys
+pub type Entries = Vec<(String, Option<Package>)>;
+
+#[derive(Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
 /// # Bun Lockfile
 ///
@@ -75,17 +105,1235 @@ pub struct Lockfile {
     #[serde(default)]
     pub workspaces: HashMap<String, Workspace>,
 
-    /// The list of all packages needed by the lockfile
-    #[serde(deserialize_with = "Lockfile::deserialize_packages")]
-    pub packages: Vec<Package>,
+    /// The list of all packages needed by the lockfile, as their raw lockfile tuples
+    pub packages: BTreeMap<String, Values>,
+
+    /// Patch files to apply to packages, keyed by package identifier
+    #[serde(default)]
+    pub patched_dependencies: BTreeMap<String, String>,
+
+    /// The names of packages bun is allowed to run lifecycle scripts for
+    #[serde(default)]
+    pub trusted_dependencies: BTreeSet<String>,
+
+    /// The default catalog of shared dependency versions, used by `catalog:`
+    #[serde(default)]
+    pub catalog: Dependencies,
+
+    /// Named catalogs of shared dependency versions, used by `catalog:<name>`
+    #[serde(default)]
+    pub catalogs: HashMap<String, Dependencies>,
+
+    /// Versions every copy of a package is forced to, keyed by package name
+    #[serde(default)]
+    pub overrides: Dependencies,
 }
 
 impl Lockfile {
-    /// # Lockfile Packages
+    /// # Resolve Catalogs
+    ///
+    /// Substitute the version from the matching catalog for every
+    /// workspace dependency using the `catalog:` protocol
+    ///
+    /// This is done as the lockfile is parsed:
+    ///
+    /// ```rust
+    /// use bun2nix::{Error, Lockfile};
+    ///
+    /// let lockfile: Lockfile = r#"{
+    ///   "lockfileVersion": 1,
+    ///   "workspaces": {
+    ///     "": {
+    ///       "dependencies": {
+    ///         "is-odd": "catalog:",
+    ///         "zod": "catalog:git",
+    ///       },
+    ///       "devDependencies": {
+    ///         "bun2nix": "catalog:tooling",
+    ///       },
+    ///     },
+    ///   },
+    ///   "catalog": { "is-odd": "^3.0.1" },
+    ///   "catalogs": {
+    ///     "tooling": { "bun2nix": "^2.0.0" },
+    ///     "git": { "zod": "github:colinhacks/zod#5bfc8f2" },
+    ///   },
+    ///   "packages": {}
+    /// }"#.parse().unwrap();
+    ///
+    /// let root = &lockfile.workspaces[""];
+    /// assert_eq!(root.dependencies["is-odd"], "^3.0.1");
+    /// assert_eq!(root.dependencies["zod"], "github:colinhacks/zod#5bfc8f2");
+    /// assert_eq!(root.dev_dependencies["bun2nix"], "^2.0.0");
+    ///
+    /// // References to a missing catalog entry are rejected
+    /// let lockfile = r#"{
+    ///   "lockfileVersion": 1,
+    ///   "workspaces": { "": { "dependencies": { "is-odd": "catalog:missing" } } },
+    ///   "packages": {}
+    /// }"#;
+    ///
+    /// assert!(matches!(
+    ///     lockfile.parse::<Lockfile>(),
+    ///     Err(Error::MissingCatalogEntry { .. })
+    /// ));
+    /// ```
+    pub fn resolve_catalogs(&mut self) -> Result<()> {
+        let workspace_dependencies = self
+            .workspaces
+            .values_mut()
+            .flat_map(|workspace| [&mut workspace.dependencies, &mut workspace.dev_dependencies]);
+
+        // Overrides may use catalogs too
+        for dependencies in workspace_dependencies.chain([&mut self.overrides]) {
+            for (package, version) in dependencies.iter_mut() {
+                let Some(catalog_name) = version.strip_prefix("catalog:") else {
+                    continue;
+                };
+                let catalog_name = match catalog_name {
+                    "" => "default",
+                    name => name,
+                };
+
+                let catalog = match catalog_name {
+                    "default" => Some(&self.catalog),
+                    name => self.catalogs.get(name),
+                };
+
+                *version = catalog
+                    .and_then(|catalog| catalog.get(package))
+                    .ok_or_else(|| Error::MissingCatalogEntry {
+                        package: package.clone(),
+                        catalog: catalog_name.to_owned(),
+                    })?
+                    .clone();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// # Apply Overrides
+    ///
+    /// Point every copy of an overridden package, including those
+    /// nested under other packages, at the entry for the version
+    /// it is forced to
+    ///
+    /// Bun already records overridden versions when installing, so this
+    /// only changes a lockfile whose nested copies disagree with the
+    /// override. Overrides which no entry matches exactly, such as version
+    /// ranges, are left as bun resolved them. This is done as the lockfile
+    /// is parsed:
+    ///
+    /// ```rust
+    /// use bun2nix::Lockfile;
+    ///
+    /// let lockfile: Lockfile = r#"{
+    ///   "lockfileVersion": 1,
+    ///   "overrides": {
+    ///     "undici-types": "6.21.0",
+    ///     "@types/ws": "catalog:",
+    ///   },
+    ///   "catalog": { "@types/ws": "8.5.14" },
+    ///   "packages": {
+    ///     "undici-types": ["undici-types@6.21.0", "", {}, "sha512-iwDZqg0QAGrg9Rav5H4n0M64c3mkR59cJ6wQp+7C4nI0gsmExaedaYLNO44eT4AtBBwjbTiGPMlt2Md0T9H9JQ=="],
+    ///     "@types/node/undici-types": ["undici-types@6.20.0", "", {}, "sha512-Ny6QZ2Nju20vw1SRHe3d9jVu6gJ+4e3+MMpqu7pqE5HT6WsTSlce++GQmK5UXS8mzV8DSYHrQH+Xrf2jVcuKNg=="],
+    ///     "@types/ws": ["@types/ws@8.5.14", "", {}, "sha512-bd/YFLW+URhBzMXurx7lWByOu+xzU9+kb3RboOteXYDfW+tr+JZa99OyNmPINEGB/ahzKrEuc8rcv4gnpJmxTw=="],
+    ///     "bun-types/@types/ws": ["@types/ws@8.5.10", "", {}, "sha512-vmQSUcfalpIq0R9q7uTo2lXs6eGIpt9wtnLdMv9LVpIjCA/+ufZRozlVoVelIYixx1ugCBKDhn89vnsEGOCx9A=="],
+    ///     "@acme/undici-types": ["@acme/undici-types@1.0.0", "", {}, "sha512-41Cifkg6e8TylSpdtTpeLVMqvSBEVzTttHvERD741+pnZ8ANv0004MRL43QKPDlK9cGvNp6NZWZUBlbGXYxxng=="],
+    ///   }
+    /// }"#.parse().unwrap();
+    ///
+    /// assert_eq!(lockfile.packages["@types/node/undici-types"][0], "undici-types@6.21.0");
+    /// assert_eq!(lockfile.packages["bun-types/@types/ws"][0], "@types/ws@8.5.14");
+    ///
+    /// // A scoped package which happens to share the name is left alone
+    /// assert_eq!(lockfile.packages["@acme/undici-types"][0], "@acme/undici-types@1.0.0");
+    /// ```
+    pub fn apply_overrides(&mut self) {
+        for (package, spec) in &self.overrides {
+            let keys: Vec<_> = self
+                .packages
+                .keys()
+                .filter(|key| Self::key_package_name(key) == package)
+                .cloned()
+                .collect();
+
+            let is_overridden = |values: &Values| {
+                values
+                    .first()
+                    .and_then(Value::as_str)
+                    .and_then(strip_name_prefix)
+                    == Some(spec.as_str())
+            };
+
+            let Some(forced) = keys
+                .iter()
+                .map(|key| &self.packages[key])
+                .find(|values| is_overridden(values))
+                .cloned()
+            else {
+                debug!("No entry for `{package}` is exactly `{spec}`, so leaving it as resolved");
+                continue;
+            };
+
+            for key in keys {
+                let Some(values) = self.packages.get_mut(&key) else {
+                    continue;
+                };
+
+                if !is_overridden(values) {
+                    debug!("Overriding `{key}` with `{package}@{spec}`");
+
+                    *values = forced.clone();
+                }
+            }
+        }
+    }
+
+    /// # Filter Packages
+    ///
+    /// Keep only the lockfile entries matching one of the `only` globs,
+    /// if any are given, and none of the `exclude` globs
+    ///
+    /// Globs are matched against both the lockfile key and the name of
+    /// the package, and may use `*` for any run of characters and `?` for
+    /// any one. This is meant for debugging a handful of packages, as the
+    /// output is then only a fragment of a complete `bun.nix`.
+    ///
+    /// ```rust
+    /// use bun2nix::{Lockfile, Options};
     ///
-    /// Consume the parsed lockfile and output it's packages set
-    pub fn packages(self) -> Vec<Package> {
+    /// let lockfile: Lockfile = r#"{
+    ///   "lockfileVersion": 1,
+    ///   "packages": {
+    ///     "@types/bun": ["@types/bun@1.2.4", "", {}, "sha512-QtuV5OMR8/rdKJs213iwXDpfVvnskPXY/S0ZiFbsTjQZycuqPbMW8Gf/XhLfwE5njW8sxI2WjISURXPlHypMFA=="],
+    ///     "@types/node": ["@types/node@22.13.5", "", {}, "sha512-+lTU0PxZXn0Dr1NBtC7Y8cR21AJr87dLLU953CWA6pMxxv/UDc7jYAY90upcrie1nRcD6XNG5HOYEDtgW5TxAg=="],
+    ///     "@types/ws": ["@types/ws@8.5.14", "", {}, "sha512-bd/YFLW+URhBzMXurx7lWByOu+xzU9+kb3RboOteXYDfW+tr+JZa99OyNmPINEGB/ahzKrEuc8rcv4gnpJmxTw=="],
+    ///     "bun-types/@types/node": ["@types/node@22.13.4", "", {}, "sha512-+lTU0PxZXn0Dr1NBtC7Y8cR21AJr87dLLU953CWA6pMxxv/UDc7jYAY90upcrie1nRcD6XNG5HOYEDtgW5TxAg=="],
+    ///     "typescript": ["typescript@5.7.3", "", {}, "sha512-84MVSjMEHP+FQRPy3pX9sTVV/INIex71s9TL2Gm5FG/WG1SqXeKyZ0k7/blY/4FdOzI12CBy1vGc4og/eus0fw=="],
+    ///   }
+    /// }"#.parse().unwrap();
+    ///
+    /// let options = Options {
+    ///     only: vec!["@types/*".to_owned()],
+    ///     exclude: vec!["@types/w?".to_owned()],
+    ///     ..Default::default()
+    /// };
+    /// let packages = lockfile.packages(&options).unwrap();
+    /// let names: Vec<_> = packages.iter().map(|pkg| pkg.name.as_str()).collect();
+    ///
+    /// assert_eq!(names, ["@types/bun@1.2.4", "@types/node@22.13.5", "@types/node@22.13.4"]);
+    /// ```
+    pub fn filter_packages(&mut self, only: &[String], exclude: &[String]) {
         self.packages
+            .retain(|key, _| Self::is_kept(key, only, exclude));
+    }
+
+    /// Whether the entry with the given lockfile key matches one of the
+    /// `only` globs, if any are given, and none of the `exclude` globs
+    fn is_kept(key: &str, only: &[String], exclude: &[String]) -> bool {
+        let matches = |globs: &[String]| {
+            let name = Self::key_package_name(key);
+
+            globs
+                .iter()
+                .any(|glob| glob_matches(glob, key) || glob_matches(glob, name))
+        };
+
+        let keep = (only.is_empty() || matches(only)) && !matches(exclude);
+
+        if !keep {
+            debug!("Filtering out `{key}`");
+        }
+
+        keep
+    }
+
+    /// # Resolve Workspace Packages
+    ///
+    /// Walk the workspace graph from the root, following every
+    /// `workspace:` dependency of each workspace reached, and add a
+    /// package for any workspace the lockfile doesn't list on it's own,
+    /// so workspaces which are only depended on through another
+    /// workspace are still copied into the store
+    ///
+    /// ```rust
+    /// use bun2nix::{Lockfile, Options, package::Fetcher};
+    ///
+    /// let lockfile = r#"{
+    ///   "lockfileVersion": 1,
+    ///   "workspaces": {
+    ///     "": {
+    ///       "name": "app",
+    ///       "dependencies": { "lib-a": "workspace:*" },
+    ///     },
+    ///     "packages/lib-a": {
+    ///       "name": "lib-a",
+    ///       "dependencies": { "lib-b": "workspace:*", "is-number": "^7.0.0" },
+    ///     },
+    ///     "packages/lib-b": {
+    ///       "name": "lib-b",
+    ///       "devDependencies": { "lib-c": "workspace:^1.0.0" },
+    ///     },
+    ///     "packages/lib-c": {
+    ///       "name": "lib-c",
+    ///       "version": "1.0.0",
+    ///     },
+    ///     "packages/unused": {
+    ///       "name": "unused",
+    ///     },
+    ///   },
+    ///   "packages": {
+    ///     "is-number": ["is-number@7.0.0", "", {}, "sha512-41Cifkg6e8TylSpdtTpeLVMqvSBEVzTttHvERD741+pnZ8ANv0004MRL43QKPDlK9cGvNp6NZWZUBlbGXYxxng=="],
+    ///     "lib-a": ["lib-a@workspace:packages/lib-a"],
+    ///   }
+    /// }"#;
+    ///
+    /// let options = Options::default();
+    /// let packages = lockfile.parse::<Lockfile>().unwrap().packages(&options).unwrap();
+    ///
+    /// let copied: Vec<_> = packages
+    ///     .iter()
+    ///     .filter_map(|pkg| match &pkg.fetcher {
+    ///         Fetcher::CopyToStore { path, .. } => Some((pkg.name.as_str(), path.as_str())),
+    ///         _ => None,
+    ///     })
+    ///     .collect();
+    ///
+    /// assert_eq!(
+    ///     copied,
+    ///     [
+    ///         ("lib-a", "packages/lib-a"),
+    ///         ("lib-b", "packages/lib-b"),
+    ///         ("lib-c", "packages/lib-c"),
+    ///     ]
+    /// );
+    /// ```
+    ///
+    /// This is done before any package is deserialized.
+    pub fn resolve_workspace_packages(&mut self) {
+        let paths: HashMap<&str, &str> = self
+            .workspaces
+            .iter()
+            .filter_map(|(path, workspace)| Some((workspace.name.as_deref()?, path.as_str())))
+            .collect();
+
+        let mut pending = vec![""];
+        let mut reached = BTreeSet::new();
+
+        while let Some(path) = pending.pop() {
+            let Some(workspace) = self.workspaces.get(path) else {
+                continue;
+            };
+            if !reached.insert(path) {
+                continue;
+            }
+
+            let dependencies = workspace
+                .dependencies
+                .iter()
+                .chain(&workspace.dev_dependencies)
+                .chain(&workspace.optional_dependencies)
+                .filter(|(_, version)| version.starts_with("workspace:"));
+
+            for (name, _) in dependencies {
+                match paths.get(name.as_str()) {
+                    Some(path) => pending.push(path),
+                    None => warn!("`{name}` is not one of the lockfile's workspaces"),
+                }
+            }
+        }
+
+        for path in reached {
+            let Some(name) = self.workspaces[path].name.as_deref() else {
+                continue;
+            };
+            if path.is_empty() || self.packages.contains_key(name) {
+                continue;
+            }
+
+            debug!("Adding the workspace `{name}`, as it is only depended on by other workspaces");
+            self.packages.insert(
+                name.to_owned(),
+                vec![Value::String(format!("{name}@workspace:{path}"))],
+            );
+        }
+    }
+
+    /// # Prune Dev Dependencies
+    ///
+    /// Remove the packages which are only installed for the
+    /// `devDependencies` of the workspaces, for a production build
+    ///
+    /// Packages are kept if they can be reached from the `dependencies`,
+    /// `optionalDependencies` or `peerDependencies` of a workspace, or
+    /// of another package which is kept. Dependencies resolve to the copy
+    /// nested under the closest ancestor, as they do when installing, so
+    /// a package shared with a production dependency always survives.
+    ///
+    /// Lockfiles without workspaces, such as a `yarn.lock`, don't say
+    /// which dependencies are for development, so are left as they are.
+    ///
+    /// ```rust
+    /// use bun2nix::{Lockfile, Options};
+    ///
+    /// let lockfile = r#"{
+    ///   "lockfileVersion": 1,
+    ///   "workspaces": {
+    ///     "": {
+    ///       "name": "app",
+    ///       "dependencies": { "server": "^1.0.0", "lib": "workspace:*" },
+    ///       "devDependencies": { "test-runner": "^1.0.0" },
+    ///     },
+    ///     "packages/lib": {
+    ///       "name": "lib",
+    ///       "optionalDependencies": { "fast-path": "^1.0.0" },
+    ///       "devDependencies": { "linter": "^1.0.0" },
+    ///     },
+    ///   },
+    ///   "packages": {
+    ///     "fast-path": ["fast-path@1.0.0", "", {}, "sha512-AAAA"],
+    ///     "lib": ["lib@workspace:packages/lib"],
+    ///     "linter": ["linter@1.0.0", "", {}, "sha512-AAAA"],
+    ///     "server": ["server@1.0.0", "", { "dependencies": { "shared": "^2.0.0" } }, "sha512-AAAA"],
+    ///     "shared": ["shared@2.0.0", "", {}, "sha512-AAAA"],
+    ///     "test-runner": ["test-runner@1.0.0", "", { "dependencies": { "shared": "^1.0.0", "dev-helper": "^1.0.0" } }, "sha512-AAAA"],
+    ///     "test-runner/shared": ["shared@1.0.0", "", {}, "sha512-AAAA"],
+    ///     "dev-helper": ["dev-helper@1.0.0", "", { "dependencies": { "deep-dev": "^1.0.0" } }, "sha512-AAAA"],
+    ///     "deep-dev": ["deep-dev@1.0.0", "", {}, "sha512-AAAA"],
+    ///   }
+    /// }"#;
+    ///
+    /// let mut lockfile: Lockfile = lockfile.parse().unwrap();
+    /// lockfile.prune_dev_dependencies();
+    ///
+    /// let keys: Vec<_> = lockfile.packages.keys().map(String::as_str).collect();
+    /// assert_eq!(keys, ["fast-path", "lib", "server", "shared"]);
+    /// ```
+    ///
+    /// With `options.production`, this is done before any package is
+    /// deserialized, so pruned packages are never prefetched.
+    pub fn prune_dev_dependencies(&mut self) {
+        if self.workspaces.is_empty() {
+            warn!(
+                "The lockfile doesn't say which dependencies are for development, so none were pruned"
+            );
+            return;
+        }
+
+        // Each workspace is installed, along with it's non dev dependencies
+        let mut pending: Vec<(Option<String>, String)> = self
+            .workspaces
+            .values()
+            .flat_map(|workspace| {
+                let scope = workspace.name.clone();
+                let dependencies = workspace
+                    .dependencies
+                    .keys()
+                    .chain(workspace.optional_dependencies.keys())
+                    .chain(&workspace.name);
+
+                dependencies.map(move |name| (scope.clone(), name.clone()))
+            })
+            .collect();
+        let mut kept = BTreeSet::new();
+
+        while let Some((scope, dependency)) = pending.pop() {
+            let Some(key) = self.resolve_key(scope.as_deref(), &dependency) else {
+                continue;
+            };
+            if !kept.insert(key.clone()) {
+                continue;
+            }
+
+            let meta = self.packages[&key].iter().find_map(Value::as_object);
+            let dependencies = ["dependencies", "optionalDependencies", "peerDependencies"]
+                .into_iter()
+                .filter_map(|field| meta?.get(field)?.as_object())
+                .flat_map(|dependencies| dependencies.keys());
+
+            for name in dependencies {
+                pending.push((Some(key.clone()), name.clone()));
+            }
+        }
+
+        self.packages.retain(|key, _| {
+            let keep = kept.contains(key);
+
+            if !keep {
+                debug!("Pruning `{key}`, as it is only a dev dependency");
+            }
+
+            keep
+        });
+    }
+
+    /// The key of the package a dependency of the package at `scope`
+    /// resolves to, trying the copy nested under the closest ancestor
+    /// before the hoisted one
+    fn resolve_key(&self, scope: Option<&str>, dependency: &str) -> Option<String> {
+        let mut scope = scope;
+
+        while let Some(parent) = scope {
+            let nested = format!("{parent}/{dependency}");
+            if self.packages.contains_key(&nested) {
+                return Some(nested);
+            }
+
+            scope = Self::parent_key(parent);
+        }
+
+        Some(dependency.to_owned()).filter(|key| self.packages.contains_key(key))
+    }
+
+    /// # Lockfile Packages
+    ///
+    /// Consume the parsed lockfile and deserialize it's packages set
+    /// using the given options
+    ///
+    /// Packages are deserialized by a pool of one worker per CPU, or
+    /// `options.prefetch_jobs` if that is more, so that npm packages,
+    /// whose hashes are in the lockfile, are spread across every core.
+    /// At most `options.prefetch_jobs` of them prefetch at once, so any
+    /// slow prefetches run concurrently without flooding the network.
+    /// The output is always in lockfile order regardless of which worker
+    /// finishes first.
+    ///
+    /// Packages which can't be installed on any of
+    /// `options.target_systems` are left out:
+    ///
+    /// ```rust
+    /// use bun2nix::{Lockfile, Options};
+    ///
+    /// let lockfile = r#"{
+    ///   "lockfileVersion": 1,
+    ///   "packages": {
+    ///     "@esbuild/darwin-arm64": ["@esbuild/darwin-arm64@0.25.0", "", { "os": "darwin", "cpu": "arm64" }, "sha512-+lTU0PxZXn0Dr1NBtC7Y8cR21AJr87dLLU953CWA6pMxxv/UDc7jYAY90upcrie1nRcD6XNG5HOYEDtgW5TxAg=="],
+    ///     "@esbuild/linux-x64": ["@esbuild/linux-x64@0.25.0", "", { "os": "linux", "cpu": "x64" }, "sha512-+lTU0PxZXn0Dr1NBtC7Y8cR21AJr87dLLU953CWA6pMxxv/UDc7jYAY90upcrie1nRcD6XNG5HOYEDtgW5TxAg=="],
+    ///   }
+    /// }"#;
+    ///
+    /// let options = Options {
+    ///     target_systems: vec!["x86_64-linux".to_owned()],
+    ///     ..Default::default()
+    /// };
+    /// let packages = lockfile.parse::<Lockfile>().unwrap().packages(&options).unwrap();
+    ///
+    /// assert_eq!(packages.len(), 1);
+    /// assert_eq!(packages[0].name, "@esbuild/linux-x64@0.25.0");
+    ///
+    /// // All platforms are kept by default
+    /// let options = Options::default();
+    /// let packages = lockfile.parse::<Lockfile>().unwrap().packages(&options).unwrap();
+    ///
+    /// assert_eq!(packages.len(), 2);
+    /// ```
+    ///
+    /// With `options.collect_all_errors` every package which failed
+    /// is reported at once:
+    ///
+    /// ```rust
+    /// use bun2nix::{Error, Lockfile, Options};
+    ///
+    /// let lockfile: Lockfile = r#"{
+    ///   "lockfileVersion": 1,
+    ///   "packages": {
+    ///     "bad-hash": ["bad-hash@1.0.0", "", {}, "md5-AAAA"],
+    ///     "bad-shape": ["bad-shape@1.0.0", "", {}, "", "", ""],
+    ///   }
+    /// }"#.parse().unwrap();
+    ///
+    /// let options = Options {
+    ///     collect_all_errors: true,
+    ///     ..Default::default()
+    /// };
+    ///
+    /// let Err(Error::Aggregate(errors)) = lockfile.packages(&options) else {
+    ///     panic!("Expected every error to be collected");
+    /// };
+    /// let names: Vec<_> = errors.iter().map(|(name, _)| name.as_str()).collect();
+    ///
+    /// assert_eq!(names, ["bad-hash", "bad-shape"]);
+    /// ```
+    pub fn packages(self, options: &Options) -> Result<Vec<Package>> {
+        self.packages_with_prefetcher(options, &NixPrefetcher)
+    }
+
+    /// # Packages With Prefetcher
+    ///
+    /// Deserialize the lockfile's packages as with `packages`,
+    /// prefetching them with the given prefetcher
+    ///
+    /// ```rust
+    /// use std::{
+    ///     collections::BTreeMap,
+    ///     sync::atomic::{AtomicUsize, Ordering},
+    ///     thread,
+    ///     time::Duration,
+    /// };
+    ///
+    /// use bun2nix::{
+    ///     Lockfile, Options, Result,
+    ///     lockfile::{Prefetch, Prefetcher},
+    /// };
+    ///
+    /// /// Records the most prefetches which were ever running at once
+    /// #[derive(Default)]
+    /// struct Counting {
+    ///     running: AtomicUsize,
+    ///     most: AtomicUsize,
+    /// }
+    ///
+    /// impl Prefetcher for Counting {
+    ///     fn prefetch(&self, _url: &str, _options: &Options) -> Result<Prefetch> {
+    ///         let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+    ///         self.most.fetch_max(running, Ordering::SeqCst);
+    ///
+    ///         thread::sleep(Duration::from_millis(5));
+    ///         self.running.fetch_sub(1, Ordering::SeqCst);
+    ///
+    ///         Ok(Prefetch { hash: "sha256-AAAA".to_owned(), rev: None, sha256: None })
+    ///     }
+    /// }
+    ///
+    /// // Thousands of npm packages, with a few git packages among them,
+    /// // keyed by lockfile key along with the name each should be given
+    /// let entries: BTreeMap<_, _> = (0..5000)
+    ///     .map(|i| match i % 500 {
+    ///         0 => (
+    ///             format!("git-{i}"),
+    ///             (format!(r#"["git-{i}@github:acme/git-{i}#a1b2c3", {{}}, "acme-git-{i}-a1b2c3"]"#), format!("github:acme-git-{i}-a1b2c3")),
+    ///         ),
+    ///         _ => (
+    ///             format!("npm-{i}"),
+    ///             (format!(r#"["npm-{i}@1.0.0", "", {{}}, "sha512-41Cifkg6e8TylSpdtTpeLVMqvSBEVzTttHvERD741+pnZ8ANv0004MRL43QKPDlK9cGvNp6NZWZUBlbGXYxxng=="]"#), format!("npm-{i}@1.0.0")),
+    ///         ),
+    ///     })
+    ///     .collect();
+    /// let packages: Vec<_> = entries
+    ///     .iter()
+    ///     .map(|(key, (entry, _))| format!(r#""{key}": {entry}"#))
+    ///     .collect();
+    /// let lockfile = format!(r#"{{ "lockfileVersion": 1, "packages": {{ {} }} }}"#, packages.join(","));
+    ///
+    /// let options = Options {
+    ///     prefetch_jobs: 2,
+    ///     ..Default::default()
+    /// };
+    /// let prefetcher = Counting::default();
+    /// let packages = lockfile
+    ///     .parse::<Lockfile>()
+    ///     .unwrap()
+    ///     .packages_with_prefetcher(&options, &prefetcher)
+    ///     .unwrap();
+    ///
+    /// // Always in lockfile order, however many workers there were
+    /// let names: Vec<_> = packages.iter().map(|pkg| &pkg.name).collect();
+    /// let expected: Vec<_> = entries.values().map(|(_, name)| name).collect();
+    /// assert_eq!(names, expected);
+    ///
+    /// assert!(prefetcher.most.load(Ordering::SeqCst) <= 2);
+    /// ```
+    pub fn packages_with_prefetcher(
+        self,
+        options: &Options,
+        prefetcher: &dyn Prefetcher,
+    ) -> Result<Vec<Package>> {
+        let entries = self.entries_with_prefetcher(options, prefetcher)?;
+
+        Ok(entries.into_iter().filter_map(|(_, pkg)| pkg).collect())
+    }
+
+    /// # Entries With Prefetcher
+    ///
+    /// Deserialize the lockfile's packages as with `packages_with_prefetcher`,
+    /// keeping the lockfile key of each, along with the entries which were
+    /// skipped, such as platform specific packages for other systems
+    ///
+    /// ```rust
+    /// use bun2nix::{Lockfile, Options, lockfile::NixPrefetcher};
+    ///
+    /// let lockfile: Lockfile = r#"{
+    ///   "lockfileVersion": 1,
+    ///   "packages": {
+    ///     "fsevents": ["fsevents@2.3.3", "", { "os": "darwin" }, "sha512-5xoDfX+fL7faATnagmWPpbFtwh/R77WmMMqqHGS65C3vvB0YHrgF+B1YmZ3441tMj5n63k0212XNoJwzlhffQw=="],
+    ///     "is-number": ["is-number@7.0.0", "", {}, "sha512-41Cifkg6e8TylSpdtTpeLVMqvSBEVzTttHvERD741+pnZ8ANv0004MRL43QKPDlK9cGvNp6NZWZUBlbGXYxxng=="],
+    ///   }
+    /// }"#.parse().unwrap();
+    ///
+    /// let options = Options {
+    ///     target_systems: vec!["x86_64-linux".to_owned()],
+    ///     ..Default::default()
+    /// };
+    /// let entries = lockfile.entries_with_prefetcher(&options, &NixPrefetcher).unwrap();
+    ///
+    /// assert_eq!(entries[0].0, "fsevents");
+    /// assert!(entries[0].1.is_none());
+    /// assert_eq!(entries[1].0, "is-number");
+    /// assert_eq!(entries[1].1.as_ref().unwrap().name, "is-number@7.0.0");
+    /// ```
+    pub fn entries_with_prefetcher(
+        self,
+        options: &Options,
+        prefetcher: &dyn Prefetcher,
+    ) -> Result<Vec<(String, Option<Package>)>> {
+        self.entries_with_known_hashes(options, prefetcher, Vec::new())
+    }
+
+    /// Deserialize the lockfile's packages as with `entries_with_prefetcher`,
+    /// reusing the hashes of fetchers which were already prefetched
+    fn entries_with_known_hashes(
+        mut self,
+        options: &Options,
+        prefetcher: &dyn Prefetcher,
+        known: Vec<Fetcher>,
+    ) -> Result<Vec<(String, Option<Package>)>> {
+        let patched_dependencies = mem::take(&mut self.patched_dependencies);
+        let trusted_dependencies = mem::take(&mut self.trusted_dependencies);
+        let context =
+            DeserializerContext::new(options, patched_dependencies, trusted_dependencies)?
+                .with_workspaces(&self.workspaces)
+                .with_prefetcher(prefetcher)
+                .with_known_hashes(known);
+
+        let start = Instant::now();
+        let entries = self.deserialize_packages(&context);
+        context.cache.save();
+
+        if let Ok(entries) = &entries {
+            info!(
+                "Deserialized {} packages in {:?}",
+                entries.iter().filter(|(_, pkg)| pkg.is_some()).count(),
+                start.elapsed()
+            );
+        }
+
+        entries
+    }
+
+    /// # Stream Entries With Prefetcher
+    ///
+    /// Read a bun lockfile from it's text and deserialize it's packages as
+    /// with `entries_with_prefetcher`, handing each entry to the pool of
+    /// workers as soon as it is read, along with the `lockfileVersion`
+    ///
+    /// Only the entries waiting for a worker are held at once, rather than
+    /// every entry in the lockfile alongside the packages deserialized from
+    /// them. Entries for an overridden package, or a workspace only reached
+    /// through another, depend on the rest of the lockfile, so they are
+    /// deserialized once it has all been read.
+    ///
+    /// The whole lockfile is parsed first instead when `options.production`
+    /// is set, as which packages are pruned depends on every entry, or if
+    /// `packages` comes before the fields it depends on, which bun never
+    /// writes. Either way the entries are the same, and nothing is
+    /// prefetched twice:
+    ///
+    /// ```rust
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    ///
+    /// use bun2nix::{
+    ///     Lockfile, Options, Result,
+    ///     lockfile::{NixPrefetcher, Prefetch, Prefetcher},
+    /// };
+    ///
+    /// let lockfile = r#"{
+    ///   "lockfileVersion": 1,
+    ///   "workspaces": {
+    ///     "": { "name": "app", "dependencies": { "lib": "workspace:*" } },
+    ///     "packages/lib": { "name": "lib" },
+    ///   },
+    ///   "overrides": { "undici-types": "6.21.0" },
+    ///   "packages": {
+    ///     "@types/node": ["@types/node@22.13.5", "", { "dependencies": { "undici-types": "~6.20.0" } }, "sha512-+lTU0PxZXn0Dr1NBtC7Y8cR21AJr87dLLU953CWA6pMxxv/UDc7jYAY90upcrie1nRcD6XNG5HOYEDtgW5TxAg=="],
+    ///     "@types/node/undici-types": ["undici-types@6.20.0", "", {}, "sha512-Ny6QZ2Nju20vw1SRHe3d9jVu6gJ+4e3+MMpqu7pqE5HT6WsTSlce++GQmK5UXS8mzV8DSYHrQH+Xrf2jVcuKNg=="],
+    ///     "fsevents": ["fsevents@2.3.3", "", { "os": "darwin" }, "sha512-5xoDfX+fL7faATnagmWPpbFtwh/R77WmMMqqHGS65C3vvB0YHrgF+B1YmZ3441tMj5n63k0212XNoJwzlhffQw=="],
+    ///     "is-number": ["is-number@7.0.0", "", {}, "sha512-41Cifkg6e8TylSpdtTpeLVMqvSBEVzTttHvERD741+pnZ8ANv0004MRL43QKPDlK9cGvNp6NZWZUBlbGXYxxng=="],
+    ///     "undici-types": ["undici-types@6.21.0", "", {}, "sha512-iwDZqg0QAGrg9Rav5H4n0M64c3mkR59cJ6wQp+7C4nI0gsmExaedaYLNO44eT4AtBBwjbTiGPMlt2Md0T9H9JQ=="],
+    ///   }
+    /// }"#;
+    ///
+    /// for prefetch_jobs in [1, 4] {
+    ///     let options = Options {
+    ///         target_systems: vec!["x86_64-linux".to_owned()],
+    ///         exclude: vec!["is-*".to_owned()],
+    ///         prefetch_jobs,
+    ///         ..Default::default()
+    ///     };
+    ///
+    ///     let (version, streamed) =
+    ///         Lockfile::stream_entries_with_prefetcher(lockfile, &options, &NixPrefetcher).unwrap();
+    ///     let parsed = lockfile
+    ///         .parse::<Lockfile>()
+    ///         .unwrap()
+    ///         .entries_with_prefetcher(&options, &NixPrefetcher)
+    ///         .unwrap();
+    ///
+    ///     assert_eq!(version, 1);
+    ///     assert_eq!(format!("{streamed:?}"), format!("{parsed:?}"));
+    ///
+    ///     let keys: Vec<_> = streamed.iter().map(|(key, _)| key.as_str()).collect();
+    ///     assert_eq!(keys, ["@types/node", "@types/node/undici-types", "fsevents", "lib", "undici-types"]);
+    ///
+    ///     // The nested copy is overridden, even though it was read first
+    ///     let nested = streamed[1].1.as_ref().unwrap();
+    ///     assert_eq!(nested.name, "undici-types@6.21.0");
+    /// }
+    ///
+    /// // A package whose `trustedDependencies` come after it is deserialized
+    /// // again once they are known, but isn't prefetched again
+    /// #[derive(Default)]
+    /// struct Counting(AtomicUsize);
+    ///
+    /// impl Prefetcher for Counting {
+    ///     fn prefetch(&self, _url: &str, _options: &Options) -> Result<Prefetch> {
+    ///         self.0.fetch_add(1, Ordering::SeqCst);
+    ///
+    ///         Ok(Prefetch { hash: "sha256-AAAA".to_owned(), rev: None, sha256: None })
+    ///     }
+    /// }
+    ///
+    /// let lockfile = r#"{
+    ///   "lockfileVersion": 1,
+    ///   "packages": {
+    ///     "zod": ["zod@github:colinhacks/zod#5bfc8f2", {}, "colinhacks-zod-5bfc8f2"],
+    ///   },
+    ///   "trustedDependencies": ["zod"],
+    /// }"#;
+    ///
+    /// for prefetch_jobs in [1, 4] {
+    ///     let options = Options {
+    ///         prefetch_jobs,
+    ///         ..Default::default()
+    ///     };
+    ///     let prefetcher = Counting::default();
+    ///
+    ///     let (_, entries) =
+    ///         Lockfile::stream_entries_with_prefetcher(lockfile, &options, &prefetcher).unwrap();
+    ///
+    ///     assert_eq!(prefetcher.0.load(Ordering::SeqCst), 1);
+    ///     assert!(entries[0].1.as_ref().unwrap().runs_scripts);
+    /// }
+    /// ```
+    pub fn stream_entries_with_prefetcher(
+        lockfile: &str,
+        options: &Options,
+        prefetcher: &dyn Prefetcher,
+    ) -> Result<(u8, Entries)> {
+        if options.production {
+            return Self::parse_entries_with_prefetcher(lockfile, options, prefetcher, Vec::new());
+        }
+
+        let start = Instant::now();
+        let mut packages = StreamPackages::new(options, prefetcher, Self::jobs(options));
+        let read = Self::read(lockfile, &mut packages);
+
+        let StreamPackages {
+            context,
+            mut results,
+            out_of_order,
+            ..
+        } = packages;
+
+        // Keep whatever was prefetched, however reading the lockfile ended
+        if let Some(context) = &context {
+            context.cache.save();
+        }
+
+        if out_of_order {
+            debug!(
+                "The lockfile's packages come before the fields they depend on, so parsing it first"
+            );
+
+            // The packages may differ once those fields are known, but
+            // their hashes don't, so nothing is prefetched twice
+            let known = results
+                .into_iter()
+                .filter_map(|(_, pkg)| pkg.ok().flatten())
+                .map(|pkg| pkg.fetcher)
+                .collect();
+
+            return Self::parse_entries_with_prefetcher(lockfile, options, prefetcher, known);
+        }
+
+        let mut lockfile = read?;
+        let context = context.expect("the context is made as the packages are read");
+
+        lockfile.resolve_catalogs()?;
+        lockfile.apply_overrides();
+        lockfile.check_manifests(options)?;
+        lockfile.resolve_workspace_packages();
+
+        // Only the deferred entries are left with their tuples
+        lockfile.packages.retain(|_, values| !values.is_empty());
+        lockfile.filter_packages(&options.only, &options.exclude);
+
+        let failed = !options.collect_all_errors && results.iter().any(|(_, pkg)| pkg.is_err());
+        if !failed {
+            context.progress.add(
+                lockfile
+                    .packages
+                    .values()
+                    .filter(|values| PackageDeserializer::is_prefetched(values))
+                    .count(),
+            );
+
+            let jobs = Self::jobs(options).clamp(1, lockfile.packages.len().max(1));
+            results.extend(Self::deserialize_entries(
+                lockfile.packages.into_iter(),
+                &context,
+                jobs,
+            ));
+            context.cache.save();
+        }
+
+        results.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        let entries = Self::collect_results(results, options.collect_all_errors)?;
+
+        info!(
+            "Deserialized {} packages in {:?}",
+            entries.iter().filter(|(_, pkg)| pkg.is_some()).count(),
+            start.elapsed()
+        );
+
+        Ok((lockfile.lockfile_version, entries))
+    }
+
+    /// Parse the whole of a lockfile, then deserialize it's packages,
+    /// reusing the hashes of any fetchers which were already prefetched
+    fn parse_entries_with_prefetcher(
+        lockfile: &str,
+        options: &Options,
+        prefetcher: &dyn Prefetcher,
+        known: Vec<Fetcher>,
+    ) -> Result<(u8, Entries)> {
+        let lockfile: Self = lockfile.parse()?;
+        let lockfile_version = lockfile.lockfile_version;
+
+        lockfile.check_manifests(options)?;
+        let entries = lockfile.entries_with_known_hashes(options, prefetcher, known)?;
+
+        Ok((lockfile_version, entries))
+    }
+
+    /// # Dry Run
+    ///
+    /// Deserialize the lockfile's packages without prefetching any,
+    /// summarizing the fetchers they use and the urls which would
+    /// need a live prefetch
+    pub fn dry_run(mut self, options: &Options) -> Result<DryRun> {
+        let options = Options {
+            dry_run: true,
+            ..options.clone()
+        };
+
+        let patched_dependencies = mem::take(&mut self.patched_dependencies);
+        let trusted_dependencies = mem::take(&mut self.trusted_dependencies);
+        let context =
+            DeserializerContext::new(&options, patched_dependencies, trusted_dependencies)?
+                .with_workspaces(&self.workspaces);
+        let packages: Vec<_> = self
+            .deserialize_packages(&context)?
+            .into_iter()
+            .filter_map(|(_, pkg)| pkg)
+            .collect();
+
+        let prefetch_urls = context
+            .live_prefetches
+            .into_inner()
+            .unwrap_or_else(|err| err.into_inner());
+
+        Ok(DryRun::new(&packages, prefetch_urls))
+    }
+
+    /// # Check Manifests
+    ///
+    /// Cross check the `package.json` of the root and each workspace
+    /// against the lockfile, erroring on any declared dependency which
+    /// has no lockfile entry, as `bun install` would otherwise try to
+    /// fetch it from the network at build time
+    ///
+    /// Manifests are read relative to `options.root`, and the check is
+    /// skipped when it isn't given.
+    ///
+    /// ```rust
+    /// use std::fs;
+    ///
+    /// use bun2nix::{Error, Lockfile, Options};
+    ///
+    /// let root = std::env::temp_dir().join("bun2nix-check-manifests");
+    /// fs::create_dir_all(&root).unwrap();
+    /// fs::write(
+    ///     root.join("package.json"),
+    ///     r#"{ "dependencies": { "is-number": "^7.0.0", "is-odd": "^3.0.0" } }"#,
+    /// )
+    /// .unwrap();
+    ///
+    /// let lockfile: Lockfile = r#"{
+    ///   "lockfileVersion": 1,
+    ///   "workspaces": { "": { "dependencies": { "is-number": "^7.0.0" } } },
+    ///   "packages": {
+    ///     "is-number": ["is-number@7.0.0", "", {}, "sha512-41Cifkg6e8TylSpdtTpeLVMqvSBEVzTttHvERD741+pnZ8ANv0004MRL43QKPDlK9cGvNp6NZWZUBlbGXYxxng=="],
+    ///   }
+    /// }"#.parse().unwrap();
+    ///
+    /// let options = Options {
+    ///     root: Some(root.clone()),
+    ///     ..Default::default()
+    /// };
+    ///
+    /// let Err(Error::MissingLockfileEntries(missing)) = lockfile.check_manifests(&options) else {
+    ///     panic!("Expected `is-odd` to be missing");
+    /// };
+    /// assert!(matches!(
+    ///     &missing[..],
+    ///     [Error::MissingLockfileEntry { package, .. }] if package == "is-odd"
+    /// ));
+    ///
+    /// // Without a root there are no manifests to check against
+    /// assert!(lockfile.check_manifests(&Options::default()).is_ok());
+    /// ```
+    pub fn check_manifests(&self, options: &Options) -> Result<()> {
+        let Some(root) = &options.root else {
+            return Ok(());
+        };
+
+        if cfg!(target_arch = "wasm32") {
+            return Ok(());
+        }
+
+        let mut workspace_paths: Vec<_> = self.workspaces.keys().collect();
+        workspace_paths.sort();
+
+        let mut missing = Vec::new();
+        for workspace_path in workspace_paths {
+            let manifest_path = root.join(workspace_path).join("package.json");
+            if !manifest_path.is_file() {
+                continue;
+            }
+
+            let contents = fs::read_to_string(&manifest_path).map_err(Error::ReadManifestError)?;
+            let manifest: Manifest = serde_json::from_str(&contents)?;
+
+            let workspace_name = manifest
+                .name
+                .as_ref()
+                .or(self.workspaces[workspace_path].name.as_ref());
+
+            for package in manifest.dependency_names() {
+                let nested = workspace_name.map(|name| format!("{name}/{package}"));
+
+                if self.packages.contains_key(package)
+                    || nested.is_some_and(|nested| self.packages.contains_key(&nested))
+                {
+                    continue;
+                }
+
+                missing.push(Error::MissingLockfileEntry {
+                    package: package.to_owned(),
+                    manifest: manifest_path.display().to_string(),
+                });
+            }
+        }
+
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::MissingLockfileEntries(missing))
+        }
+    }
+
+    fn deserialize_packages(
+        mut self,
+        context: &DeserializerContext,
+    ) -> Result<Vec<(String, Option<Package>)>> {
+        self.resolve_workspace_packages();
+        if context.options.production {
+            self.prune_dev_dependencies();
+        }
+        self.filter_packages(&context.options.only, &context.options.exclude);
+
+        let jobs = Self::jobs(context.options).clamp(1, self.packages.len().max(1));
+
+        context.progress.start(
+            self.packages
+                .values()
+                .filter(|values| PackageDeserializer::is_prefetched(values))
+                .count(),
+        );
+
+        let mut results = Self::deserialize_entries(self.packages.into_iter(), context, jobs);
+        results.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+
+        Self::collect_results(results, context.options.collect_all_errors)
+    }
+
+    /// The number of workers to deserialize packages with, one per CPU,
+    /// or `options.prefetch_jobs` if that is more
+    fn jobs(options: &Options) -> usize {
+        default_prefetch_jobs().max(options.prefetch_jobs).max(1)
+    }
+
+    /// Deserialize entries as they are taken from a queue, by a pool of
+    /// `jobs` workers, giving the results in whatever order they finish
+    fn deserialize_entries(
+        entries: impl Iterator<Item = (String, Values)> + Send,
+        context: &DeserializerContext,
+        jobs: usize,
+    ) -> Results {
+        // Threads can't be spawned from wasm
+        if jobs == 1 || cfg!(target_arch = "wasm32") {
+            let mut results = Vec::new();
+
+            for (name, values) in entries {
+                let pkg = PackageDeserializer::deserialize_package(name.clone(), values, context);
+                let failed = pkg.is_err() && !context.options.collect_all_errors;
+                results.push((name, pkg));
+
+                // Don't prefetch the rest once the outcome is decided
+                if failed {
+                    break;
+                }
+            }
+
+            return results;
+        }
+
+        let queue = Mutex::new(entries);
+        let next_entry = || queue.lock().unwrap_or_else(|err| err.into_inner()).next();
+
+        // Shared, rather than gathered from each worker, so the
+        // results are never held twice
+        let results = Mutex::new(Vec::new());
+
+        thread::scope(|scope| {
+            let workers: Vec<_> = (0..jobs)
+                .map(|_| {
+                    scope.spawn(|| {
+                        while let Some((name, values)) = next_entry() {
+                            let pkg = PackageDeserializer::deserialize_package(
+                                name.clone(),
+                                values,
+                                context,
+                            );
+
+                            results
+                                .lock()
+                                .unwrap_or_else(|err| err.into_inner())
+                                .push((name, pkg));
+                        }
+                    })
+                })
+                .collect();
+
+            for worker in workers {
+                worker
+                    .join()
+                    .unwrap_or_else(|err| panic::resume_unwind(err));
+            }
+        });
+
+        results.into_inner().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Gather the deserialized packages, failing with either the first
+    /// error or, if collecting all errors, every one of them
+    fn collect_results(
+        results: Results,
+        collect_all_errors: bool,
+    ) -> Result<Vec<(String, Option<Package>)>> {
+        let mut errors = Vec::new();
+
+        // Collected in place of the results, rather than alongside them
+        let packages = results
+            .into_iter()
+            .filter_map(|(name, pkg)| match pkg {
+                Ok(pkg) => Some(Ok((name, pkg))),
+                Err(err) if collect_all_errors => {
+                    errors.push((name, err));
+                    None
+                }
+                Err(err) => Some(Err(err)),
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        if errors.is_empty() {
+            Ok(Self::resolve_dependencies(packages))
+        } else {
+            Err(Error::Aggregate(errors))
+        }
+    }
+
+    /// Replace the dependency names of each package, keyed by its lockfile
+    /// key, with the attribute names of the packages they resolve to
+    ///
+    /// Like node, bun resolves a dependency to the copy nested under the
+    /// closest of the package and its ancestors, i.e. `parent/dep`, before
+    /// the hoisted `dep`. Dependencies which resolve to nothing, such as
+    /// platform specific packages skipped for the target systems, are dropped.
+    fn resolve_dependencies(
+        packages: Vec<(String, Option<Package>)>,
+    ) -> Vec<(String, Option<Package>)> {
+        let attribute_names: HashMap<String, String> = packages
+            .iter()
+            .filter_map(|(key, pkg)| Some((key.clone(), pkg.as_ref()?.nix_attr_name())))
+            .collect();
+
+        let resolve = |key: &str, dependency: &str| {
+            let mut scope = Some(key);
+
+            while let Some(parent) = scope {
+                let nested = format!("{parent}/{dependency}");
+                if let Some(name) = attribute_names.get(&nested) {
+                    return Some(name.clone());
+                }
+
+                scope = Self::parent_key(parent);
+            }
+
+            attribute_names.get(dependency).cloned()
+        };
+
+        packages
+            .into_iter()
+            .map(|(key, mut pkg)| {
+                if let Some(pkg) = &mut pkg {
+                    let mut dependencies: Vec<_> = pkg
+                        .dependencies
+                        .iter()
+                        .filter_map(|dependency| resolve(&key, dependency))
+                        .collect();
+                    dependencies.sort();
+                    dependencies.dedup();
+
+                    pkg.dependencies = dependencies;
+                }
+
+                (key, pkg)
+            })
+            .collect()
+    }
+
+    /// The name of the package a lockfile key is for, without the
+    /// keys of any packages it is nested under
+    fn key_package_name(key: &str) -> &str {
+        match Self::parent_key(key) {
+            Some(parent) => &key[parent.len() + 1..],
+            None => key,
+        }
+    }
+
+    /// The lockfile key a nested package is nested under, if any,
+    /// taking care not to split a scoped name such as `@scope/name`
+    fn parent_key(key: &str) -> Option<&str> {
+        let (parent, _) = key.rsplit_once('/')?;
+
+        match parent.rsplit_once('/') {
+            Some((grandparent, scope)) if scope.starts_with('@') => Some(grandparent),
+            None if parent.starts_with('@') => None,
+            _ => Some(parent),
+        }
     }
 
     /// # Lockfile Workspaces
@@ -102,6 +1350,129 @@ impl Lockfile {
         self.workspaces.iter().any(|(key, _)| !key.is_empty())
     }
 
+    /// # From Yarn Lock
+    ///
+    /// Parse a Yarn v1 `yarn.lock` into a lockfile with the same
+    /// packages, so a project can be packaged before it has moved
+    /// to bun's lockfile
+    ///
+    /// Workspaces aren't read, so the result has none, and yarn
+    /// records no `bin` or platform metadata for it's packages.
+    ///
+    /// ```rust
+    /// use bun2nix::{Error, Lockfile, Options, package::Fetcher};
+    ///
+    /// let yarn_lock = r#"# yarn lockfile v1
+    ///
+    /// is-number@^7.0.0:
+    ///   version "7.0.0"
+    ///   resolved "https://registry.yarnpkg.com/is-number/-/is-number-7.0.0.tgz#78c8f9b3f0fba9d5b9b0c1d1e4f2a2e7f0a2c4d6"
+    ///   integrity sha512-41Cifkg6e8TylSpdtTpeLVMqvSBEVzTttHvERD741+pnZ8ANv0004MRL43QKPDlK9cGvNp6NZWZUBlbGXYxxng==
+    /// "#;
+    ///
+    /// let options = Options::default();
+    /// let packages = Lockfile::from_yarn_lock(yarn_lock).unwrap().packages(&options).unwrap();
+    ///
+    /// assert_eq!(packages[0].name, "is-number@7.0.0");
+    /// assert!(matches!(
+    ///     &packages[0].fetcher,
+    ///     Fetcher::FetchUrl { url, .. }
+    ///         if url == "https://registry.npmjs.org/is-number/-/is-number-7.0.0.tgz"
+    /// ));
+    ///
+    /// // Yarn berry lockfiles are a different format altogether
+    /// let berry_lock = "# This file is generated by running \"yarn install\" inside your project.\n\n__metadata:\n  version: 6\n";
+    ///
+    /// assert!(matches!(
+    ///     Lockfile::from_yarn_lock(berry_lock),
+    ///     Err(Error::UnsupportedYarnLockVersion)
+    /// ));
+    /// ```
+    pub fn from_yarn_lock(contents: &str) -> Result<Self> {
+        Ok(Self {
+            lockfile_version: 1,
+            workspaces: HashMap::new(),
+            packages: parse_yarn_lock(contents)?,
+            patched_dependencies: BTreeMap::new(),
+            trusted_dependencies: BTreeSet::new(),
+            catalog: Dependencies::new(),
+            catalogs: HashMap::new(),
+            overrides: Dependencies::new(),
+        })
+    }
+
+    /// # Check Lockfile Version
+    ///
+    /// Ensure a `lockfileVersion` is one of `SUPPORTED_LOCKFILE_VERSIONS`
+    ///
+    /// This is done as soon as the version is read, before the rest of
+    /// the lockfile:
+    ///
+    /// ```rust
+    /// use bun2nix::{Error, Lockfile};
+    ///
+    /// let lockfile = r#"{
+    ///   "lockfileVersion": 2,
+    ///   "packages": { "is-number": { "version": "7.0.0" } }
+    /// }"#;
+    ///
+    /// let Err(err) = lockfile.parse::<Lockfile>() else {
+    ///     panic!("Expected the version to be rejected");
+    /// };
+    ///
+    /// assert!(matches!(err, Error::UnsupportedLockfileVersion { found: 2, .. }));
+    /// assert!(err.to_string().contains("supports version 1"));
+    /// ```
+    pub fn check_version(version: u64) -> Result<()> {
+        let supported = &SUPPORTED_LOCKFILE_VERSIONS;
+
+        if supported.contains(&version) {
+            return Ok(());
+        }
+
+        let supported = if supported.start() == supported.end() {
+            format!("version {}", supported.start())
+        } else {
+            format!("versions {} to {}", supported.start(), supported.end())
+        };
+
+        Err(Error::UnsupportedLockfileVersion {
+            found: version,
+            supported,
+        })
+    }
+
+    /// Read a lockfile from it's text in a single pass, reading it's
+    /// packages as `packages` does
+    ///
+    /// Malformed lockfiles are parsed again as JSONC, for it's error
+    /// messages.
+    fn read(lockfile: &str, packages: &mut impl ReadPackages) -> Result<Self> {
+        let mut error = None;
+        let mut deserializer = serde_json::Deserializer::from_reader(StripJsonc::new(lockfile));
+
+        let read = LockfileSeed {
+            packages,
+            error: &mut error,
+        }
+        .deserialize(&mut deserializer)
+        .and_then(|value| deserializer.end().map(|()| value));
+
+        let err = match read {
+            Ok(value) => return Ok(value),
+            Err(err) => err,
+        };
+
+        if let Some(err) = error {
+            return Err(err);
+        }
+        if err.is_syntax() || err.is_eof() {
+            Self::parse_to_value(lockfile)?;
+        }
+
+        Err(err.into())
+    }
+
     /// # Parse to Value
     ///
     /// Parse the lockfile into a serde json value
@@ -109,29 +1480,93 @@ impl Lockfile {
         jsonc_parser::parse_to_serde_value(lockfile, &Default::default())?
             .ok_or(Error::NoJsoncValue)
     }
+}
 
-    /// # Deserialize Packages
-    ///
-    /// Use the `PackagesVisitor` to deserialize the packages into a list of packages
-    pub fn deserialize_packages<'de, D>(data: D) -> std::result::Result<Vec<Package>, D::Error>
+impl FromStr for Lockfile {
+    type Err = Error;
+
+    /// The lockfile is read in a single pass, straight from it's text,
+    /// rather than through a `Value` of the whole document
+    fn from_str(lockfile: &str) -> std::result::Result<Self, Self::Err> {
+        let mut lockfile = Self::read(lockfile, &mut CollectPackages)?;
+        lockfile.resolve_catalogs()?;
+        lockfile.apply_overrides();
+
+        Ok(lockfile)
+    }
+}
+
+impl<'de> Deserialize<'de> for Lockfile {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
     where
         D: Deserializer<'de>,
     {
-        data.deserialize_map(PackageVisitor)
+        LockfileSeed {
+            packages: &mut CollectPackages,
+            error: &mut None,
+        }
+        .deserialize(deserializer)
     }
 }
 
-impl FromStr for Lockfile {
-    type Err = Error;
+type Dependencies = HashMap<String, String>;
 
-    fn from_str(lockfile: &str) -> std::result::Result<Self, Self::Err> {
-        let value = Self::parse_to_value(lockfile)?;
+/// Whether a glob of `*` and `?` wildcards matches the whole of `text`
+fn glob_matches(glob: &str, text: &str) -> bool {
+    let glob: Vec<char> = glob.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    // The glob and text positions to resume from if the last `*` should
+    // have matched one more character
+    let (mut g, mut t) = (0, 0);
+    let mut backtrack = None;
 
-        Ok(serde_json::from_value(value)?)
+    while t < text.len() {
+        match glob.get(g) {
+            Some('*') => {
+                backtrack = Some((g, t));
+                g += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                g += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, start)) => {
+                    backtrack = Some((star, start + 1));
+                    g = star + 1;
+                    t = start + 1;
+                }
+                None => return false,
+            },
+        }
     }
+
+    glob[g..].iter().all(|&c| c == '*')
 }
 
-type Dependencies = HashMap<String, String>;
+#[derive(Default, Deserialize, Debug)]
+#[serde(rename_all = "camelCase", default)]
+/// # Package Manifest
+///
+/// The fields of a `package.json` which declare a workspace's dependencies
+struct Manifest {
+    name: Option<String>,
+    dependencies: BTreeMap<String, Value>,
+    dev_dependencies: BTreeMap<String, Value>,
+    optional_dependencies: BTreeMap<String, Value>,
+}
+
+impl Manifest {
+    /// The names of every dependency which must be installed
+    fn dependency_names(&self) -> impl Iterator<Item = &str> {
+        self.dependencies
+            .keys()
+            .chain(self.dev_dependencies.keys())
+            .chain(self.optional_dependencies.keys())
+            .map(String::as_str)
+    }
+}
 
 #[derive(Default, Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase", default)]
@@ -142,6 +1577,9 @@ pub struct Workspace {
     /// The name of the workspace
     pub name: Option<String>,
 
+    /// The version of the workspace
+    pub version: Option<String>,
+
     /// Dependencies of the workspace
     #[serde(default, deserialize_with = "Workspace::deserialize_dependencies")]
     pub dependencies: Dependencies,
@@ -149,6 +1587,10 @@ pub struct Workspace {
     /// Dev dependencies of the workspace
     #[serde(default, deserialize_with = "Workspace::deserialize_dependencies")]
     pub dev_dependencies: Dependencies,
+
+    /// Optional dependencies of the workspace
+    #[serde(default, deserialize_with = "Workspace::deserialize_dependencies")]
+    pub optional_dependencies: Dependencies,
 }
 
 impl Workspace {
@@ -0,0 +1,144 @@
+use std::io::{self, Read};
+
+/// # Strip JSONC
+///
+/// A reader over the JSONC of a textual bun lockfile which yields it
+/// as plain JSON, without it's comments and trailing commas, so it can
+/// be streamed straight into `serde_json::from_reader`
+///
+/// The JSON is produced as it is read, so no second copy of the
+/// lockfile is ever made. Each stripped byte is read as a space, other
+/// than the newlines in comments, so errors still point at the same
+/// line and column. Malformed input is passed through for `serde_json`
+/// to reject.
+///
+/// ```rust
+/// use std::io::Read;
+///
+/// use bun2nix::lockfile::StripJsonc;
+///
+/// let jsonc = r#"{
+///   // The format version
+///   "lockfileVersion": 1, /* bun 1.2 */
+///   "packages": {
+///     "tiny": ["tiny@https://example.com/a//b/*c*/", {},],
+///   },
+/// }"#;
+///
+/// let value: serde_json::Value = serde_json::from_reader(StripJsonc::new(jsonc)).unwrap();
+///
+/// assert_eq!(value["lockfileVersion"], 1);
+/// assert_eq!(value["packages"]["tiny"][0], "tiny@https://example.com/a//b/*c*/");
+/// assert_eq!(value["packages"]["tiny"].as_array().unwrap().len(), 2);
+///
+/// let mut json = String::new();
+/// StripJsonc::new(jsonc).read_to_string(&mut json).unwrap();
+/// assert_eq!(json.len(), jsonc.len());
+/// assert_eq!(json.lines().count(), jsonc.lines().count());
+///
+/// // Escaped quotes don't end a string
+/// let mut json = String::new();
+/// StripJsonc::new(r#"["a\"//b",]"#).read_to_string(&mut json).unwrap();
+/// assert_eq!(json, r#"["a\"//b" ]"#);
+/// ```
+#[derive(Debug, Clone)]
+pub struct StripJsonc<'a> {
+    jsonc: &'a [u8],
+    position: usize,
+    state: State,
+}
+
+/// Where in the JSONC the reader is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Json,
+    String,
+    Escape,
+    LineComment,
+    BlockCommentStart,
+    BlockComment,
+    BlockCommentEnd,
+}
+
+impl<'a> StripJsonc<'a> {
+    /// # New Strip JSONC
+    ///
+    /// Read the given JSONC as plain JSON
+    pub fn new(jsonc: &'a str) -> Self {
+        Self {
+            jsonc: jsonc.as_bytes(),
+            position: 0,
+            state: State::Json,
+        }
+    }
+
+    /// The next byte of JSON, if the JSONC isn't exhausted
+    fn next_byte(&mut self) -> Option<u8> {
+        let byte = *self.jsonc.get(self.position)?;
+        let next = self.jsonc.get(self.position + 1).copied();
+
+        let (state, output) = match (self.state, byte) {
+            (State::Json, b'/') if next == Some(b'/') => (State::LineComment, b' '),
+            (State::Json, b'/') if next == Some(b'*') => (State::BlockCommentStart, b' '),
+            (State::Json, b',') if matches!(self.next_token(), Some(b'}' | b']')) => {
+                (State::Json, b' ')
+            }
+            (State::Json, b'"') => (State::String, byte),
+            (State::Json, byte) => (State::Json, byte),
+            (State::String, b'\\') => (State::Escape, byte),
+            (State::String, b'"') => (State::Json, byte),
+            (State::String, byte) | (State::Escape, byte) => (State::String, byte),
+            (State::LineComment, b'\n') => (State::Json, byte),
+            (State::LineComment, _) => (State::LineComment, b' '),
+            (State::BlockCommentStart, _) => (State::BlockComment, b' '),
+            (State::BlockComment, b'*') if next == Some(b'/') => (State::BlockCommentEnd, b' '),
+            (State::BlockComment, b'\n') => (State::BlockComment, byte),
+            (State::BlockComment, _) => (State::BlockComment, b' '),
+            (State::BlockCommentEnd, _) => (State::Json, b' '),
+        };
+
+        self.state = state;
+        self.position += 1;
+
+        Some(output)
+    }
+
+    /// The first byte after the current one which isn't whitespace or
+    /// part of a comment
+    fn next_token(&self) -> Option<u8> {
+        let mut rest = &self.jsonc[self.position + 1..];
+
+        loop {
+            match rest {
+                [byte, tail @ ..] if byte.is_ascii_whitespace() => rest = tail,
+                [b'/', b'/', tail @ ..] => {
+                    let end = tail.iter().position(|byte| *byte == b'\n')?;
+                    rest = &tail[end..];
+                }
+                [b'/', b'*', tail @ ..] => {
+                    let end = tail.windows(2).position(|window| window == b"*/")?;
+                    rest = &tail[end + 2..];
+                }
+                [byte, ..] => return Some(*byte),
+                [] => return None,
+            }
+        }
+    }
+}
+
+impl Read for StripJsonc<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut read = 0;
+
+        while read < buf.len() {
+            let Some(byte) = self.next_byte() else {
+                break;
+            };
+
+            buf[read] = byte;
+            read += 1;
+        }
+
+        Ok(read)
+    }
+}
@@ -0,0 +1,48 @@
+use crate::error::{Error, Result};
+
+/// The magic header every binary `bun.lockb` file starts with
+pub const LOCKB_HEADER: &[u8] = b"#!/usr/bin/env bun\nbun-lockfile-format-v0\n";
+
+/// # Is Lockb
+///
+/// Check if the given bytes begin with the binary lockfile magic header
+///
+/// ```rust
+/// use bun2nix::lockfile::{LOCKB_HEADER, is_lockb};
+///
+/// assert!(is_lockb(LOCKB_HEADER));
+/// assert!(!is_lockb(br#"{ "lockfileVersion": 1 }"#));
+/// ```
+pub fn is_lockb(bytes: &[u8]) -> bool {
+    bytes.starts_with(LOCKB_HEADER)
+}
+
+/// # Lockb Format Version
+///
+/// Read the little endian `u32` format version which directly follows
+/// the magic header of a binary lockfile
+///
+/// ```rust
+/// use bun2nix::{Error, lockfile::{LOCKB_HEADER, lockb_format_version}};
+///
+/// let mut lockb = LOCKB_HEADER.to_vec();
+/// lockb.extend(3u32.to_le_bytes());
+///
+/// assert_eq!(lockb_format_version(&lockb).unwrap(), 3);
+/// assert!(matches!(lockb_format_version(b"bun.lock"), Err(Error::InvalidLockbHeader)));
+///
+/// // A valid header cut off before the whole version
+/// assert!(matches!(
+///     lockb_format_version(&lockb[..LOCKB_HEADER.len() + 2]),
+///     Err(Error::TruncatedLockb)
+/// ));
+/// ```
+pub fn lockb_format_version(bytes: &[u8]) -> Result<u32> {
+    let version = bytes
+        .strip_prefix(LOCKB_HEADER)
+        .ok_or(Error::InvalidLockbHeader)?
+        .first_chunk::<4>()
+        .ok_or(Error::TruncatedLockb)?;
+
+    Ok(u32::from_le_bytes(*version))
+}
@@ -1,44 +1,328 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    time::Instant,
+};
+
+use log::{debug, info, trace, warn};
+use serde_json::Value;
+
 use crate::{
     Package,
     error::{Error, Result},
     package::Fetcher,
 };
 
+mod context;
+mod nar_hash;
 mod prefetch;
-pub use prefetch::Prefetch;
+mod prefetch_cache;
+mod previous_output;
+mod progress;
+mod proxy;
+mod target_system;
+pub use context::{DeserializerContext, LocalWorkspace, prefetch_host};
+pub use prefetch::{
+    DEFAULT_PREFETCH_COMMAND, DEFAULT_PREFETCH_RETRIES, DEFAULT_PREFETCH_TIMEOUT,
+    NIX_PREFETCH_GIT_COMMAND, NixPrefetcher, PREFETCH_RETRY_DELAY, Prefetch, Prefetcher,
+};
+pub use prefetch_cache::{PREFETCH_CACHE_VERSION, PrefetchCache};
+pub use previous_output::PreviousOutput;
+pub use progress::Progress;
+pub use proxy::Proxy;
+pub use target_system::TargetSystem;
+
+/// The raw tuple of serde json values for a lockfile package entry
+pub type Values = Vec<Value>;
+
+/// The placeholder hash given to packages which would be prefetched during a dry run
+const DRY_RUN_HASH: &str = "sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
 
-type Values = Vec<serde_json::Value>;
+/// The lifecycle scripts bun runs when installing a dependency, in order
+pub const LIFECYCLE_SCRIPTS: &[&str] = &["preinstall", "install", "postinstall"];
 
 /// # Package Deserializer
 ///
 /// Deserializes a given bun lockfile entry line into it's
 /// name and nix fetcher implementation
 #[derive(Debug)]
-pub struct PackageDeserializer {
+pub struct PackageDeserializer<'a> {
     /// The name for the package
     pub name: String,
 
     /// The list of serde json values for the tuple in question
     pub values: Values,
+
+    /// The state shared with the rest of the lockfile's packages
+    pub context: &'a DeserializerContext<'a>,
 }
 
-impl PackageDeserializer {
+impl<'a> PackageDeserializer<'a> {
     /// # Deserialize package
     ///
-    /// Deserialize a given package from it's lockfile representation
-    pub fn deserialize_package(name: String, values: Values) -> Result<Package> {
+    /// Deserialize a given package from it's lockfile representation,
+    /// or `None` if it can't be installed on any of the target systems
+    pub fn deserialize_package(
+        name: String,
+        values: Values,
+        context: &'a DeserializerContext<'a>,
+    ) -> Result<Option<Package>> {
+        trace!("Deserializing `{name}` from {values:?}");
+
         let arity = values.len();
-        let deserializer = Self { name, values };
+        let deserializer = Self {
+            name,
+            values,
+            context,
+        };
+
+        if !deserializer.is_for_target_systems() {
+            debug!(
+                "Skipping `{}`, as it is not for any target system",
+                deserializer.name
+            );
+
+            return Ok(None);
+        }
+
+        let runs_scripts = !context.options.disable_lifecycle_scripts
+            && deserializer
+                .package_name()
+                .is_some_and(|name| context.trusted_dependencies.contains(name));
+        let lifecycle_scripts = deserializer.lifecycle_scripts();
+        let bin = deserializer.bin();
+        let dependencies = deserializer.dependency_names();
+        let peer_dependencies = deserializer.peer_dependencies();
+        let os = deserializer.platform_constraint("os");
+        let cpu = deserializer.platform_constraint("cpu");
 
-        match arity {
+        let package = match arity {
             1 => deserializer.deserialize_workspace_package(),
             2 => deserializer.deserialize_tarball_or_file_package(),
             3 => deserializer.deserialize_git_or_github_package(),
             4 => deserializer.deserialize_npm_package(),
             x => Err(Error::UnexpectedPackageEntryLength(x)),
+        }?;
+
+        let patch = context.patched_dependencies.get(&package.name).cloned();
+
+        debug!(
+            "Fetching `{}` with {} from '{}'",
+            package.name,
+            package.fetcher.kind(),
+            package.fetcher.source()
+        );
+
+        Ok(Some(
+            package
+                .with_patch(patch)
+                .with_runs_scripts(runs_scripts)
+                .with_lifecycle_scripts(lifecycle_scripts)
+                .with_bin(bin)
+                .with_dependencies(dependencies)
+                .with_peer_dependencies(peer_dependencies)
+                .with_platforms(os, cpu),
+        ))
+    }
+
+    /// # Is Prefetched
+    ///
+    /// Whether a lockfile entry is one of the git, github or tarball
+    /// packages which are prefetched, going by its tuple alone
+    pub fn is_prefetched(values: &Values) -> bool {
+        let source = values
+            .first()
+            .and_then(Value::as_str)
+            .and_then(strip_name_prefix);
+
+        let has_integrity = values
+            .iter()
+            .skip(1)
+            .filter_map(Value::as_str)
+            .any(is_integrity)
+            || source
+                .and_then(|source| source.rsplit_once('#'))
+                .is_some_and(|(_, fragment)| is_integrity(fragment));
+
+        match values.len() {
+            2 => source.is_some_and(|path| path.starts_with("http")) && !has_integrity,
+            3 => !has_integrity,
+            _ => false,
+        }
+    }
+
+    /// # Package Name
+    ///
+    /// The name of the package, as declared in a `package.json`,
+    /// taken from the identifier at the start of it's lockfile tuple
+    pub fn package_name(&self) -> Option<&str> {
+        let id = self.values.first()?.as_str()?;
+        let source = strip_name_prefix(id)?;
+
+        Some(&id[..id.len() - source.len() - 1])
+    }
+
+    /// # Bin
+    ///
+    /// The executables the package declares in it's metadata, keyed by
+    /// the name they are linked as in `node_modules/.bin`
+    ///
+    /// A single string is named after the package, without it's scope:
+    ///
+    /// ```rust
+    /// use bun2nix::{Lockfile, Options};
+    ///
+    /// let lockfile: Lockfile = r#"{
+    ///   "lockfileVersion": 1,
+    ///   "packages": {
+    ///     "@scope/cli": ["@scope/cli@1.0.0", "", { "bin": "dist/cli.js" }, "sha512-41Cifkg6e8TylSpdtTpeLVMqvSBEVzTttHvERD741+pnZ8ANv0004MRL43QKPDlK9cGvNp6NZWZUBlbGXYxxng=="],
+    ///     "typescript": ["typescript@5.9.3", "", { "bin": { "tsc": "bin/tsc", "tsserver": "bin/tsserver" } }, "sha512-jl1vZzPDinLr9eUt3J/t7V6FgNEw9QjvBPdysz9KfQDD41fQrC2Y4vKQdiaUpFT4bXlb1RHhLpp8wtm6M5TgSw=="],
+    ///   }
+    /// }"#.parse().unwrap();
+    ///
+    /// let options = Options::default();
+    /// let packages = lockfile.packages(&options).unwrap();
+    ///
+    /// assert_eq!(packages[0].bin["cli"], "dist/cli.js");
+    /// assert_eq!(packages[1].bin.len(), 2);
+    /// assert_eq!(packages[1].bin["tsserver"], "bin/tsserver");
+    /// ```
+    pub fn bin(&self) -> BTreeMap<String, String> {
+        let bin = self
+            .values
+            .iter()
+            .find(|value| value.is_object())
+            .and_then(|meta| meta.get("bin"));
+
+        match bin {
+            Some(Value::String(path)) => self
+                .package_name()
+                .map(|name| name.rsplit('/').next().unwrap_or(name))
+                .map(|name| BTreeMap::from([(name.to_owned(), path.clone())]))
+                .unwrap_or_default(),
+            Some(Value::Object(bins)) => bins
+                .iter()
+                .filter_map(|(name, path)| Some((name.clone(), path.as_str()?.to_owned())))
+                .collect(),
+            _ => BTreeMap::new(),
+        }
+    }
+
+    /// # Lifecycle Scripts
+    ///
+    /// The install time lifecycle scripts declared in the package's
+    /// metadata, in the order bun runs them
+    pub fn lifecycle_scripts(&self) -> Vec<String> {
+        let Some(scripts) = self
+            .values
+            .iter()
+            .find(|value| value.is_object())
+            .and_then(|meta| meta.get("scripts"))
+            .and_then(Value::as_object)
+        else {
+            return Vec::new();
+        };
+
+        LIFECYCLE_SCRIPTS
+            .iter()
+            .filter(|script| scripts.contains_key(**script))
+            .map(|script| (*script).to_owned())
+            .collect()
+    }
+
+    /// # Platform Constraint
+    ///
+    /// The entries of the package's `os` or `cpu` metadata, which may
+    /// be given as either a single string or a list
+    ///
+    /// ```rust
+    /// use bun2nix::{Lockfile, Options};
+    ///
+    /// let lockfile: Lockfile = r#"{
+    ///   "lockfileVersion": 1,
+    ///   "packages": {
+    ///     "@esbuild/linux-x64": ["@esbuild/linux-x64@0.25.0", "", { "os": "linux", "cpu": "x64" }, "sha512-+lTU0PxZXn0Dr1NBtC7Y8cR21AJr87dLLU953CWA6pMxxv/UDc7jYAY90upcrie1nRcD6XNG5HOYEDtgW5TxAg=="],
+    ///     "fsevents": ["fsevents@2.3.3", "", { "os": ["darwin", "!win32"] }, "sha512-+lTU0PxZXn0Dr1NBtC7Y8cR21AJr87dLLU953CWA6pMxxv/UDc7jYAY90upcrie1nRcD6XNG5HOYEDtgW5TxAg=="],
+    ///   }
+    /// }"#.parse().unwrap();
+    ///
+    /// let options = Options::default();
+    /// let packages = lockfile.packages(&options).unwrap();
+    ///
+    /// assert_eq!(packages[0].os, ["linux"]);
+    /// assert_eq!(packages[0].cpu, ["x64"]);
+    /// assert_eq!(packages[1].os, ["darwin", "!win32"]);
+    /// assert!(packages[1].cpu.is_empty());
+    /// ```
+    pub fn platform_constraint(&self, field: &str) -> Vec<String> {
+        let constraint = self
+            .values
+            .iter()
+            .find(|value| value.is_object())
+            .and_then(|meta| meta.get(field));
+
+        match constraint {
+            Some(Value::String(entry)) => vec![entry.clone()],
+            Some(Value::Array(entries)) => entries
+                .iter()
+                .filter_map(Value::as_str)
+                .map(str::to_owned)
+                .collect(),
+            _ => Vec::new(),
         }
     }
 
+    /// # Dependency Names
+    ///
+    /// The names of the packages listed in the package's `dependencies`
+    /// and `optionalDependencies` metadata, sorted and without duplicates
+    pub fn dependency_names(&self) -> Vec<String> {
+        let Some(meta) = self.values.iter().find(|value| value.is_object()) else {
+            return Vec::new();
+        };
+
+        let names: BTreeSet<_> = ["dependencies", "optionalDependencies"]
+            .into_iter()
+            .filter_map(|field| meta.get(field)?.as_object())
+            .flat_map(|dependencies| dependencies.keys().cloned())
+            .collect();
+
+        names.into_iter().collect()
+    }
+
+    /// # Peer Dependencies
+    ///
+    /// The `peerDependencies` of the package's metadata, keyed by
+    /// name, with the version range each must satisfy
+    pub fn peer_dependencies(&self) -> BTreeMap<String, String> {
+        self.values
+            .iter()
+            .find(|value| value.is_object())
+            .and_then(|meta| meta.get("peerDependencies")?.as_object())
+            .map(|peers| {
+                peers
+                    .iter()
+                    .filter_map(|(name, range)| Some((name.clone(), range.as_str()?.to_owned())))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// # Is For Target Systems
+    ///
+    /// Check if the package's `os` and `cpu` metadata allow it to be
+    /// installed on any of `options.target_systems`, which is always
+    /// the case if none are set
+    pub fn is_for_target_systems(&self) -> bool {
+        let targets = &self.context.target_systems;
+
+        // The metadata is the only object in any of the tuple shapes
+        let Some(meta) = self.values.iter().find(|value| value.is_object()) else {
+            return true;
+        };
+
+        targets.is_empty() || targets.iter().any(|target| target.accepts(meta))
+    }
+
     /// # Deserialize an NPM Package
     ///
     /// Deserialize an npm package from it's bun lockfile representation
@@ -48,6 +332,78 @@ impl PackageDeserializer {
     ///
     /// The tarball_url field is empty for the default registry (registry.npmjs.org),
     /// or contains the exact URL to the package tarball for non-default registries.
+    ///
+    /// Aliased dependencies (`"foo": "npm:bar@1.2.3"`) are fetched, and named, as
+    /// their target package, as that is what bun keys it's cache on. The alias
+    /// itself only affects the `node_modules` layout, which bun derives from the
+    /// lockfile key at install time.
+    ///
+    /// bun writes an alias as the lockfile key, with the target as the
+    /// identifier, though an `alias@npm:target` identifier is read too:
+    ///
+    /// ```rust
+    /// use bun2nix::{Lockfile, Options, package::Fetcher};
+    ///
+    /// let lockfile: Lockfile = r#"{
+    ///   "lockfileVersion": 1,
+    ///   "packages": {
+    ///     "node-types": ["@types/node@22.13.5", "", { "license": "MIT" }, "sha512-+lTU0PxZXn0Dr1NBtC7Y8cR21AJr87dLLU953CWA6pMxxv/UDc7jYAY90upcrie1nRcD6XNG5HOYEDtgW5TxAg=="],
+    ///     "old-node-types": ["old-node-types@npm:@types/node@22.13.4", "", {}, "sha512-+lTU0PxZXn0Dr1NBtC7Y8cR21AJr87dLLU953CWA6pMxxv/UDc7jYAY90upcrie1nRcD6XNG5HOYEDtgW5TxAg=="],
+    ///   }
+    /// }"#.parse().unwrap();
+    ///
+    /// let options = Options::default();
+    /// let packages = lockfile.packages(&options).unwrap();
+    ///
+    /// assert_eq!(packages[0].name, "@types/node@22.13.5");
+    /// assert_eq!(packages[0].license.as_deref(), Some("MIT"));
+    /// assert!(matches!(
+    ///     &packages[0].fetcher,
+    ///     Fetcher::FetchUrl { url, .. }
+    ///         if url == "https://registry.npmjs.org/@types/node/-/node-22.13.5.tgz"
+    /// ));
+    ///
+    /// assert_eq!(packages[1].name, "@types/node@22.13.4");
+    /// assert!(matches!(
+    ///     &packages[1].fetcher,
+    ///     Fetcher::FetchUrl { url, .. }
+    ///         if url == "https://registry.npmjs.org/@types/node/-/node-22.13.4.tgz"
+    /// ));
+    /// ```
+    ///
+    /// The `homepage` and `repository` of the metadata are kept too,
+    /// where `repository` may be given as a url or an object holding one:
+    ///
+    /// ```rust
+    /// use bun2nix::{Lockfile, Options};
+    ///
+    /// let lockfile: Lockfile = r#"{
+    ///   "lockfileVersion": 1,
+    ///   "packages": {
+    ///     "is-number": ["is-number@7.0.0", "", { "homepage": "https://github.com/jonschlinkert/is-number", "repository": { "type": "git", "url": "git+https://github.com/jonschlinkert/is-number.git" } }, "sha512-41Cifkg6e8TylSpdtTpeLVMqvSBEVzTttHvERD741+pnZ8ANv0004MRL43QKPDlK9cGvNp6NZWZUBlbGXYxxng=="],
+    ///     "zod": ["zod@3.24.2", "", { "repository": "colinhacks/zod", "homepage": "" }, "sha512-lY7CDW43ECgW9u1TcT3IoXHflywfVqDYze4waEz812jR/bZ8FHDsl7pFQoSZTz5N+2NqRXs8GBwnAwo3ZNxqhQ=="],
+    ///   }
+    /// }"#.parse().unwrap();
+    ///
+    /// let options = Options::default();
+    /// let packages = lockfile.packages(&options).unwrap();
+    ///
+    /// assert_eq!(
+    ///     packages[0].homepage.as_deref(),
+    ///     Some("https://github.com/jonschlinkert/is-number")
+    /// );
+    /// assert_eq!(
+    ///     packages[0].repository.as_deref(),
+    ///     Some("git+https://github.com/jonschlinkert/is-number.git")
+    /// );
+    /// assert_eq!(packages[1].homepage, None);
+    /// assert_eq!(packages[1].repository.as_deref(), Some("colinhacks/zod"));
+    ///
+    /// let nix = packages[0].to_string();
+    /// assert!(nix.contains(r#"meta.homepage = "https://github.com/jonschlinkert/is-number";"#));
+    /// assert!(nix.contains(r#"passthru.repository = "git+https://github.com/jonschlinkert/is-number.git";"#));
+    /// assert!(!packages[1].to_string().contains("meta.homepage"));
+    /// ```
     pub fn deserialize_npm_package(mut self) -> Result<Package> {
         // The bun.lock format for npm packages is:
         // [identifier, tarball_url, metadata, hash]
@@ -56,10 +412,11 @@ impl PackageDeserializer {
         // - metadata: object with dependencies, bin, etc.
         // - hash: integrity hash (sha512-...)
 
-        let npm_identifier_raw = swap_remove_value(&mut self.values, 0);
+        let npm_identifier_raw = swap_remove_value(&mut self.values, 0)?;
+        let npm_identifier_raw = strip_npm_alias(&npm_identifier_raw).to_owned();
         // After swap_remove(0): [hash, tarball_url, meta]
 
-        let hash = swap_remove_value(&mut self.values, 0);
+        let hash = swap_remove_value(&mut self.values, 0)?;
         // After swap_remove(0): [meta, tarball_url]
 
         // Get the tarball URL from what's now at index 1
@@ -70,14 +427,51 @@ impl PackageDeserializer {
             .and_then(|v| v.as_str())
             .filter(|s| !s.is_empty());
 
-        debug_assert!(
-            hash.contains("sha512-"),
-            "Expected hash to be in sri format and contain sha512"
-        );
+        let meta = self.values.first();
+        let meta_str = |key| {
+            meta.and_then(|meta| meta.get(key))
+                .and_then(Value::as_str)
+                .filter(|value| !value.is_empty())
+                .map(str::to_owned)
+        };
+
+        let license = meta_str("license");
+        let homepage = meta_str("homepage");
+        // `repository` is either a url, or an object holding one
+        let repository = meta_str("repository").or_else(|| {
+            meta.and_then(|meta| meta.pointer("/repository/url"))
+                .and_then(Value::as_str)
+                .filter(|url| !url.is_empty())
+                .map(str::to_owned)
+        });
 
-        let fetcher = Fetcher::new_npm_package(&npm_identifier_raw, hash, tarball_url)?;
+        let integrity = hash;
+        let hash = Fetcher::sri_from_integrity(&integrity).map_err(|err| match err {
+            Error::MalformedIntegrity(_) => Error::UnexpectedHashFormat {
+                package: npm_identifier_raw.clone(),
+                hash: integrity.clone(),
+            },
+            err => err,
+        })?;
 
-        Ok(Package::new(npm_identifier_raw, fetcher))
+        let fetcher = if Fetcher::is_jsr_identifier(&npm_identifier_raw) {
+            Fetcher::new_jsr_package(&npm_identifier_raw, hash, tarball_url)?
+        } else {
+            Fetcher::new_npm_package(
+                &npm_identifier_raw,
+                hash,
+                tarball_url,
+                &self.context.npmrc,
+                &self.context.default_registry,
+                &self.context.registry_mirrors,
+            )?
+        };
+
+        Ok(Package::new(npm_identifier_raw, fetcher)
+            .with_license(license)
+            .with_homepage(homepage)
+            .with_repository(repository)
+            .with_integrity(Some(integrity)))
     }
 
     /// # Deserialize a Git or Github Package
@@ -86,15 +480,24 @@ impl PackageDeserializer {
     ///
     /// This is found in the source as a tuple of arity 3
     pub fn deserialize_git_or_github_package(mut self) -> Result<Package> {
-        let mut id = swap_remove_value(&mut self.values, 0);
-
-        let at_pos = id.rfind('@').ok_or(Error::NoAtInPackageIdentifier)?;
-        id.drain(..=at_pos);
+        let raw_id = swap_remove_value(&mut self.values, 0)?;
+        let id = strip_name_prefix(&raw_id)
+            .ok_or_else(|| Error::NoAtInPackageIdentifier(raw_id.clone()))?
+            .to_owned();
 
-        if id.starts_with("github:") {
-            Self::deserialize_github_package(id)
+        if id.starts_with("file://") {
+            self.deserialize_file_url_package(id)
+        } else if id.starts_with("http") {
+            // Tarballs are only of arity 3 when they carry their integrity
+            self.deserialize_tarball_package(id)
+        } else if id.starts_with("github:") {
+            self.deserialize_github_package(id)
+        } else if id.starts_with("gitlab:") {
+            self.deserialize_gitlab_package(id)
+        } else if id.starts_with("bitbucket:") {
+            self.deserialize_bitbucket_package(id)
         } else {
-            Self::deserialize_git_package(id)
+            self.deserialize_git_package(id)
         }
     }
 
@@ -103,25 +506,126 @@ impl PackageDeserializer {
     /// Deserialize a github package from it's bun lockfile representation
     ///
     /// This is found in the source as a tuple of arity 3
-    pub fn deserialize_github_package(id: String) -> Result<Package> {
-        let (url, rev) = split_once_owned(id, '#').ok_or(Error::MissingGitRef)?;
+    ///
+    /// Packages are fetched from `options.github_domain`, unless their
+    /// url names a host of its own, as in `github:github.acme.com/acme/ui`:
+    ///
+    /// ```rust
+    /// use bun2nix::{Lockfile, Options, package::Fetcher};
+    ///
+    /// let lockfile: Lockfile = r#"{
+    ///   "lockfileVersion": 1,
+    ///   "packages": {
+    ///     "ui": ["ui@github:acme/ui#5bfc8f2", {}, "acme-ui-5bfc8f2"],
+    ///     "icons": ["icons@github:github.internal.acme.com/acme/icons#a1b2c3d", {}, "acme-icons-a1b2c3d"],
+    ///   }
+    /// }"#.parse().unwrap();
+    ///
+    /// let options = Options {
+    ///     github_domain: "github.acme.com".to_owned(),
+    ///     dry_run: true,
+    ///     ..Default::default()
+    /// };
+    /// let packages = lockfile.packages(&options).unwrap();
+    /// let domains: Vec<_> = packages
+    ///     .iter()
+    ///     .filter_map(|package| match &package.fetcher {
+    ///         Fetcher::FetchGitHub { owner, domain, .. } => Some((owner.as_str(), domain.as_str())),
+    ///         _ => None,
+    ///     })
+    ///     .collect();
+    ///
+    /// assert_eq!(domains, [("acme", "github.internal.acme.com"), ("acme", "github.acme.com")]);
+    /// ```
+    pub fn deserialize_github_package(self, id: String) -> Result<Package> {
+        let (url, rev) = split_once_owned(id.clone(), '#').ok_or(Error::MissingGitRef(id))?;
+        let (rev, dir) = split_git_subdirectory(rev);
 
-        let prefetch_url = format!("{}?ref={}", &url, &rev);
-        let prefetch = Prefetch::prefetch_package(&prefetch_url)?;
+        let path = drop_prefix(url.clone(), "github:");
+        let (domain, path) = match path.split_once('/') {
+            Some((host, rest)) if host.contains('.') && rest.contains('/') => {
+                (host.to_owned(), rest.to_owned())
+            }
+            _ => (self.context.options.github_domain.clone(), path),
+        };
 
-        let (owner_with_pre, repo) = split_once_owned(url, '/').ok_or(Error::ImproperGithubUrl)?;
-        let owner = drop_prefix(owner_with_pre, "github:");
+        let (owner, repo) = split_once_owned(path, '/').ok_or(Error::ImproperGithubUrl(url))?;
 
         let id_with_ver = format!("github:{}-{}-{}", &owner, &repo, &rev);
 
-        let fetcher = Fetcher::FetchGitHub {
+        let name = Fetcher::git_source_name(&repo, &rev);
+
+        let (fetcher, sha256) = self.prefetch_fetcher(Fetcher::FetchGitHub {
             owner,
             repo,
             rev,
-            hash: prefetch.hash,
-        };
+            hash: String::new(),
+            domain,
+            dir,
+            name: Some(name),
+        })?;
+
+        Ok(Package::new(id_with_ver, fetcher).with_sha256(sha256))
+    }
+
+    /// # Deserialize a GitLab Package
+    ///
+    /// Deserialize a gitlab package from it's bun lockfile representation
+    ///
+    /// This is found in the source as a tuple of arity 3
+    ///
+    /// Bun installs these as regular git dependencies, so the package
+    /// keeps a `git:` name for it's cache entry.
+    pub fn deserialize_gitlab_package(self, id: String) -> Result<Package> {
+        let (url, rev) = split_once_owned(id.clone(), '#').ok_or(Error::MissingGitRef(id))?;
+        let path = drop_prefix(url, "gitlab:");
+
+        // Nested groups are allowed, so the repo is only the final segment
+        let (owner, repo) = path
+            .rsplit_once('/')
+            .map(|(owner, repo)| (owner.to_owned(), repo.to_owned()))
+            .ok_or_else(|| Error::ImproperGitLabUrl(path.clone()))?;
+        let domain = self.context.options.gitlab_domain.clone();
+
+        let id_with_rev = format!("git:{}", &rev);
+
+        let (fetcher, sha256) = self.prefetch_fetcher(Fetcher::FetchFromGitLab {
+            owner,
+            repo,
+            rev,
+            hash: String::new(),
+            domain,
+        })?;
 
-        Ok(Package::new(id_with_ver, fetcher))
+        Ok(Package::new(id_with_rev, fetcher).with_sha256(sha256))
+    }
+
+    /// # Deserialize a Bitbucket Package
+    ///
+    /// Deserialize a bitbucket package from it's bun lockfile representation
+    ///
+    /// This is found in the source as a tuple of arity 3
+    ///
+    /// Nix has no `bitbucket:` flake input, so the same archive
+    /// `fetchFromBitbucket` downloads is prefetched instead. Like
+    /// GitLab, bun installs these as regular git dependencies.
+    pub fn deserialize_bitbucket_package(self, id: String) -> Result<Package> {
+        let (url, rev) = split_once_owned(id.clone(), '#').ok_or(Error::MissingGitRef(id))?;
+
+        let (owner_with_pre, repo) =
+            split_once_owned(url.clone(), '/').ok_or(Error::ImproperBitbucketUrl(url))?;
+        let owner = drop_prefix(owner_with_pre, "bitbucket:");
+
+        let id_with_rev = format!("git:{}", &rev);
+
+        let (fetcher, sha256) = self.prefetch_fetcher(Fetcher::FetchFromBitbucket {
+            owner,
+            repo,
+            rev,
+            hash: String::new(),
+        })?;
+
+        Ok(Package::new(id_with_rev, fetcher).with_sha256(sha256))
     }
 
     /// # Deserialize a Git Package
@@ -129,22 +633,49 @@ impl PackageDeserializer {
     /// Deserialize a git package from it's bun lockfile representation
     ///
     /// This is found in the source as a tuple of arity 3
-    pub fn deserialize_git_package(id: String) -> Result<Package> {
+    ///
+    /// Submodules are only fetched for packages listed in
+    /// `options.fetch_submodules`, as the lockfile does not record
+    /// whether a repo has any. The choice is always written out, as
+    /// `fetchgit` otherwise defaults to fetching them.
+    ///
+    /// Likewise, only the locked commit is fetched unless the package is
+    /// listed in `options.deep_clone`. As `fetchgit` only deep clones when
+    /// keeping the `.git` directory, such packages keep it, and are
+    /// prefetched with `nix-prefetch-git` for the hash to match. Packages
+    /// listed in `options.leave_dot_git` keep it without the full history.
+    ///
+    /// Packages listed in `options.all_refs` have every ref fetched, for
+    /// commits which can't be reached from the default branch, both
+    /// when prefetched and by `fetchgit`.
+    pub fn deserialize_git_package(self, id: String) -> Result<Package> {
         let git_url = drop_prefix(id, "git+");
-        let (url, rev) = split_once_owned(git_url, '#').ok_or(Error::MissingGitRef)?;
+        let (url, rev) =
+            split_once_owned(git_url.clone(), '#').ok_or(Error::MissingGitRef(git_url))?;
+        let (rev, dir) = split_git_subdirectory(rev);
 
-        let prefetch_url = format!("git+{}?rev={}", &url, &rev);
-        let prefetch = Prefetch::prefetch_package(&prefetch_url)?;
+        let fetch_submodules = self.context.options.fetch_submodules.contains(&self.name);
+        let deep_clone = self.context.options.deep_clone.contains(&self.name);
+        let all_refs = self.context.options.all_refs.contains(&self.name);
+        // `fetchgit` asserts that deep clones keep `.git`
+        let leave_dot_git = deep_clone || self.context.options.leave_dot_git.contains(&self.name);
 
         let id_with_rev = format!("git:{}", &rev);
+        let name = Fetcher::git_source_name(&url, &rev);
 
-        let fetcher = Fetcher::FetchGit {
+        let (fetcher, sha256) = self.prefetch_fetcher(Fetcher::FetchGit {
             url,
             rev,
-            hash: prefetch.hash,
-        };
+            hash: String::new(),
+            fetch_submodules,
+            deep_clone,
+            all_refs,
+            leave_dot_git,
+            dir,
+            name: Some(name),
+        })?;
 
-        Ok(Package::new(id_with_rev, fetcher))
+        Ok(Package::new(id_with_rev, fetcher).with_sha256(sha256))
     }
 
     /// # Deserialize a tarball or file package
@@ -157,13 +688,19 @@ impl PackageDeserializer {
     /// paths starting with `http` are considered
     /// tarballs
     pub fn deserialize_tarball_or_file_package(mut self) -> Result<Package> {
-        let id = swap_remove_value(&mut self.values, 0);
-        let path = Self::drain_after_substring(id, "@").ok_or(Error::NoAtInPackageIdentifier)?;
+        let id = swap_remove_value(&mut self.values, 0)?;
+        let path = strip_name_prefix(&id)
+            .ok_or_else(|| Error::NoAtInPackageIdentifier(id.clone()))?
+            .to_owned();
 
-        if path.starts_with("http") {
-            Self::deserialize_tarball_package(path)
+        if path.starts_with("file://") {
+            self.deserialize_file_url_package(path)
+        } else if path.starts_with("http") {
+            self.deserialize_tarball_package(path)
+        } else if path.starts_with("link:") {
+            self.deserialize_link_package(path)
         } else {
-            Self::deserialize_file_package(self.name, path)
+            self.deserialize_file_package(path)
         }
     }
 
@@ -180,22 +717,231 @@ impl PackageDeserializer {
     /// See:
     /// - https://github.com/oven-sh/bun/blob/7ebfdf97a872908aeacce7af7eba21658b265ad7/src/install/dependency.zig#L514-L517
     /// - https://github.com/oven-sh/bun/blob/7ebfdf97a872908aeacce7af7eba21658b265ad7/src/install/resolution.zig#L46-L59
-    pub fn deserialize_file_package(name: String, path: String) -> Result<Package> {
-        debug_assert!(
-            !path.contains("http"),
-            "File path can never contain http, because then it would be a tarball"
-        );
-
+    ///
+    /// Paths are resolved against `options.root`, rather than wherever the
+    /// lockfile is, so a lockfile in `./subdir` may refer to packages
+    /// relative to the repo root:
+    ///
+    /// ```rust
+    /// use bun2nix::{Options, generate_bun_nix};
+    ///
+    /// let lockfile = r#"{
+    ///   "lockfileVersion": 1,
+    ///   "packages": {
+    ///     "lib": ["lib@file:./packages/lib", {}],
+    ///   }
+    /// }"#;
+    ///
+    /// // `bun.nix` is written next to the lockfile, in `./subdir`
+    /// let options = Options {
+    ///     root: Some("/srv/repo".into()),
+    ///     output_dir: Some("/srv/repo/subdir".into()),
+    ///     ..Default::default()
+    /// };
+    /// let nix = generate_bun_nix(lockfile, &options).unwrap();
+    ///
+    /// assert!(nix.contains("src = ./../packages/lib;"));
+    /// ```
+    pub fn deserialize_file_package(self, path: String) -> Result<Package> {
         // Strip prefix: explicit "file:" or implicit "./" (Bun strips file: for local tarballs)
         let path = path
             .strip_prefix("file:")
             .or_else(|| path.strip_prefix("./"))
-            .ok_or(Error::MissingFileSpecifier)?;
+            .ok_or_else(|| Error::MissingFileSpecifier(path.clone()))?;
+
+        if let Some(stem) = path
+            .strip_suffix(".tgz")
+            .or_else(|| path.strip_suffix(".tar.gz"))
+        {
+            return self.deserialize_local_tarball_package(path, stem);
+        }
 
         Ok(Package::new(
-            name,
+            self.name,
             Fetcher::CopyToStore {
-                path: path.to_string(),
+                path: self.context.resolve_path(path),
+                exclude: self.context.copy_excludes.clone(),
+            },
+        ))
+    }
+
+    /// # Deserialize a local tarball package
+    ///
+    /// Deserialize a `file:` package which points at a `.tgz` or
+    /// `.tar.gz` file, rather than a directory
+    ///
+    /// The tarball is copied to the store under a `.tgz` name, so it is
+    /// unpacked in the same way as a downloaded one. As its contents
+    /// may change without the lockfile changing, it is hashed afresh
+    /// each time, which is done here rather than by the prefetch command,
+    /// so the hash doesn't depend on where the project is checked out.
+    ///
+    /// ```rust
+    /// use bun2nix::{
+    ///     Options, Result, generate_bun_nix_with_prefetcher,
+    ///     lockfile::{Prefetch, Prefetcher},
+    /// };
+    ///
+    /// struct Offline;
+    ///
+    /// impl Prefetcher for Offline {
+    ///     fn prefetch(&self, url: &str, _options: &Options) -> Result<Prefetch> {
+    ///         panic!("Nothing should be prefetched, but '{url}' was");
+    ///     }
+    /// }
+    ///
+    /// let root = std::env::temp_dir().join("bun2nix-local-tarball");
+    /// std::fs::create_dir_all(root.join("vendor")).unwrap();
+    /// std::fs::write(root.join("vendor/foo.tgz"), "hello\n").unwrap();
+    ///
+    /// let lockfile = r#"{
+    ///   "lockfileVersion": 1,
+    ///   "packages": {
+    ///     "foo": ["foo@file:./vendor/foo.tgz", {}],
+    ///   }
+    /// }"#;
+    ///
+    /// let options = Options {
+    ///     root: Some(root.clone()),
+    ///     output_dir: Some(root),
+    ///     ..Default::default()
+    /// };
+    /// let nix = generate_bun_nix_with_prefetcher(lockfile, &options, &Offline).unwrap();
+    ///
+    /// assert!(nix.contains("path = ./vendor/foo.tgz;"));
+    /// assert!(nix.contains(r#"name = "foo.tgz";"#));
+    /// assert!(nix.contains(r#"sha256 = "sha256-HDfQGvQL4ugGkd48w99EN3ppmvuxfGjwgJZLL9Bx/BM=";"#));
+    /// ```
+    pub fn deserialize_local_tarball_package(self, path: &str, stem: &str) -> Result<Package> {
+        let file_stem = stem.rsplit('/').next().unwrap_or(stem);
+        let prefetch = Prefetch::from_local_file(&self.context.absolute_path(path)?)?;
+
+        let sha256 = self.emitted_sha256(&prefetch);
+
+        let fetcher = Fetcher::LocalTarball {
+            path: self.context.resolve_path(path),
+            name: format!("{file_stem}.tgz"),
+            hash: prefetch.hash,
+        };
+
+        Ok(Package::new(self.name, fetcher).with_sha256(sha256))
+    }
+
+    /// # Deserialize a file url package
+    ///
+    /// Deserialize a tarball fetched from a `file://` url, such as one
+    /// served by a registry on the local filesystem for air-gapped builds
+    ///
+    /// This is found in the source as a tuple of arity 2, or 3 along
+    /// with it's integrity
+    ///
+    /// These are copied to the store and unpacked in the same way as a
+    /// `file:` tarball, from the absolute path in the url. The integrity
+    /// is of the tarball itself rather than of the store path, so the
+    /// tarball is always hashed afresh.
+    ///
+    /// ```rust
+    /// use bun2nix::{
+    ///     Options, Result, generate_bun_nix_with_prefetcher,
+    ///     lockfile::{Prefetch, Prefetcher},
+    /// };
+    ///
+    /// struct Offline;
+    ///
+    /// impl Prefetcher for Offline {
+    ///     fn prefetch(&self, url: &str, _options: &Options) -> Result<Prefetch> {
+    ///         panic!("Nothing should be prefetched, but '{url}' was");
+    ///     }
+    /// }
+    ///
+    /// let registry = std::env::temp_dir().join("bun2nix-file-url-registry");
+    /// std::fs::create_dir_all(registry.join("foo/-")).unwrap();
+    /// std::fs::create_dir_all(registry.join("bar/-")).unwrap();
+    /// std::fs::write(registry.join("foo/-/foo-1.0.0.tgz"), "hello\n").unwrap();
+    /// std::fs::write(registry.join("bar/-/bar-2.0.0.tgz"), "hello\n").unwrap();
+    /// let registry = registry.display();
+    ///
+    /// let lockfile = format!(r#"{{
+    ///   "lockfileVersion": 1,
+    ///   "packages": {{
+    ///     "foo": ["foo@file://{registry}/foo/-/foo-1.0.0.tgz", {{}}],
+    ///     "bar": ["bar@file://localhost{registry}/bar/-/bar-2.0.0.tgz", {{}}, "sha512-41Cifkg6e8TylSpdtTpeLVMqvSBEVzTttHvERD741+pnZ8ANv0004MRL43QKPDlK9cGvNp6NZWZUBlbGXYxxng=="],
+    ///   }}
+    /// }}"#);
+    ///
+    /// let options = Options {
+    ///     root: Some("/srv/repo".into()),
+    ///     output_dir: Some("/srv/repo".into()),
+    ///     ..Default::default()
+    /// };
+    /// let nix = generate_bun_nix_with_prefetcher(&lockfile, &options, &Offline).unwrap();
+    ///
+    /// assert!(nix.contains(&format!("path = {registry}/foo/-/foo-1.0.0.tgz;")));
+    /// assert!(nix.contains(r#"name = "foo-1.0.0.tgz";"#));
+    /// assert!(nix.contains(&format!("path = {registry}/bar/-/bar-2.0.0.tgz;")));
+    /// assert!(nix.contains(r#"name = "bar-2.0.0.tgz";"#));
+    /// assert!(nix.contains(r#"sha256 = "sha256-HDfQGvQL4ugGkd48w99EN3ppmvuxfGjwgJZLL9Bx/BM=";"#));
+    ///
+    /// // Only absolute paths on this machine can be fetched
+    /// let lockfile = r#"{
+    ///   "lockfileVersion": 1,
+    ///   "packages": {
+    ///     "foo": ["foo@file://registry.internal/foo-1.0.0.tgz", {}],
+    ///   }
+    /// }"#;
+    /// assert!(generate_bun_nix_with_prefetcher(lockfile, &options, &Offline).is_err());
+    /// ```
+    pub fn deserialize_file_url_package(self, url: String) -> Result<Package> {
+        let path = url
+            .strip_prefix("file://")
+            .map(|path| path.strip_prefix("localhost").unwrap_or(path))
+            .filter(|path| path.starts_with('/'))
+            .ok_or_else(|| Error::ImproperFileUrl(url.clone()))?;
+
+        let stem = path
+            .strip_suffix(".tgz")
+            .or_else(|| path.strip_suffix(".tar.gz"))
+            .unwrap_or(path);
+
+        self.deserialize_local_tarball_package(path, stem)
+    }
+
+    /// # Deserialize a link package
+    ///
+    /// Deserialize a `link:` package from it's bun lockfile representation
+    ///
+    /// This is found in the source as a tuple of arity 2
+    ///
+    /// Unlike `file:` packages, these are symlinked to rather than copied,
+    /// though to the source as added to the store, so they build in the
+    /// sandbox. The path is resolved relative to the lockfile in the same
+    /// way.
+    ///
+    /// ```rust
+    /// use bun2nix::{Lockfile, Options, package::Fetcher};
+    ///
+    /// let lockfile: Lockfile = r#"{
+    ///   "lockfileVersion": 1,
+    ///   "packages": {
+    ///     "my-lib": ["my-lib@link:../my-lib", {}],
+    ///   }
+    /// }"#.parse().unwrap();
+    ///
+    /// let options = Options::default();
+    /// let packages = lockfile.packages(&options).unwrap();
+    ///
+    /// assert!(matches!(
+    ///     &packages[0].fetcher,
+    ///     Fetcher::SymlinkIntoStore { path } if path == "../my-lib"
+    /// ));
+    /// ```
+    pub fn deserialize_link_package(self, path: String) -> Result<Package> {
+        let path = drop_prefix(path, "link:");
+
+        Ok(Package::new(
+            self.name,
+            Fetcher::SymlinkIntoStore {
+                path: self.context.resolve_path(&path),
             },
         ))
     }
@@ -205,37 +951,461 @@ impl PackageDeserializer {
     /// Deserialize a tarball package from it's bun lockfile representation
     ///
     /// This is found in the source as a tuple of arity 2
-    pub fn deserialize_tarball_package(url: String) -> Result<Package> {
-        debug_assert!(url.contains("http"), "Expected tarball url to contain http");
+    ///
+    /// Zip archives are fetched with `fetchzip` rather than
+    /// `builtins.fetchTarball`, and so are prefetched explicitly
+    /// as an unpacked archive to get a matching hash.
+    ///
+    /// Urls which redirect elsewhere, such as to the latest build of a
+    /// package, are written out as given in the lockfile. Both nix and the
+    /// prefetch follow the redirect, so the hash is that of the content
+    /// actually downloaded, while the store path keeps the fixed name
+    /// `source` whatever the redirect's target is called:
+    ///
+    /// ```rust
+    /// use bun2nix::{
+    ///     Options, Result, generate_bun_nix_with_prefetcher,
+    ///     lockfile::{Prefetch, Prefetcher},
+    /// };
+    ///
+    /// /// Stands in for a server which redirects to `tiny-1.0.1.tgz`
+    /// struct Redirecting;
+    ///
+    /// impl Prefetcher for Redirecting {
+    ///     fn prefetch(&self, url: &str, _options: &Options) -> Result<Prefetch> {
+    ///         // Prefetched unpacked, even without an archive extension
+    ///         assert_eq!(url, "tarball+https://example.com/tiny/latest");
+    ///
+    ///         Ok(Prefetch { hash: "sha256-dGlueS0xLjAuMQ==".to_owned(), rev: None, sha256: None })
+    ///     }
+    /// }
+    ///
+    /// let lockfile = r#"{
+    ///   "lockfileVersion": 1,
+    ///   "packages": {
+    ///     "tiny": ["tiny@https://example.com/tiny/latest", {}],
+    ///   }
+    /// }"#;
+    ///
+    /// let options = Options::default();
+    /// let nix = generate_bun_nix_with_prefetcher(lockfile, &options, &Redirecting).unwrap();
+    ///
+    /// assert!(nix.contains(r#"url = "https://example.com/tiny/latest";"#));
+    /// assert!(nix.contains(r#"sha256 = "sha256-dGlueS0xLjAuMQ==";"#));
+    /// assert!(!nix.contains("tiny-1.0.1.tgz"));
+    /// ```
+    ///
+    /// Tarballs whose integrity the lockfile gives, after their metadata
+    /// or as the fragment of their url, are fetched with `fetchurl`
+    /// using that hash, without being prefetched at all:
+    ///
+    /// ```rust
+    /// use bun2nix::{
+    ///     Options, Result, generate_bun_nix_with_prefetcher,
+    ///     lockfile::{Prefetch, Prefetcher},
+    /// };
+    ///
+    /// struct Offline;
+    ///
+    /// impl Prefetcher for Offline {
+    ///     fn prefetch(&self, url: &str, _options: &Options) -> Result<Prefetch> {
+    ///         panic!("Nothing should be prefetched, but '{url}' was");
+    ///     }
+    /// }
+    ///
+    /// let lockfile = r#"{
+    ///   "lockfileVersion": 1,
+    ///   "packages": {
+    ///     "tiny": ["tiny@https://example.com/tiny-1.0.0.tgz", {}, "sha512-41Cifkg6e8TylSpdtTpeLVMqvSBEVzTttHvERD741+pnZ8ANv0004MRL43QKPDlK9cGvNp6NZWZUBlbGXYxxng=="],
+    ///     "small": ["small@https://example.com/small/latest#sha1-KmxDRN9mBE/646QCOWUIKYql+ZE=", {}],
+    ///   }
+    /// }"#;
+    ///
+    /// let options = Options::default();
+    /// let nix = generate_bun_nix_with_prefetcher(lockfile, &options, &Offline).unwrap();
+    ///
+    /// assert!(nix.contains(r#"url = "https://example.com/tiny-1.0.0.tgz";"#));
+    /// assert!(nix.contains(r#"hash = "sha512-41Cifkg6e8TylSpdtTpeLVMqvSBEVzTttHvERD741+pnZ8ANv0004MRL43QKPDlK9cGvNp6NZWZUBlbGXYxxng==";"#));
+    /// assert!(nix.contains(r#"url = "https://example.com/small/latest";"#));
+    /// assert!(nix.contains(r#"hash = "sha1-KmxDRN9mBE/646QCOWUIKYql+ZE=";"#));
+    /// ```
+    pub fn deserialize_tarball_package(self, url: String) -> Result<Package> {
+        let name = format!("tarball:{}", url);
 
-        let prefetch = Prefetch::prefetch_package(&url)?;
+        if let Some((url, integrity)) = self.tarball_integrity(&url) {
+            let hash = Fetcher::sri_from_integrity(&integrity).map_err(|err| match err {
+                Error::MalformedIntegrity(_) => Error::UnexpectedHashFormat {
+                    package: name.clone(),
+                    hash: integrity.clone(),
+                },
+                err => err,
+            })?;
 
-        let name = format!("tarball:{}", url);
-        let fetcher = Fetcher::FetchTarball {
-            url,
-            hash: prefetch.hash,
+            return Ok(Package::new(
+                name,
+                Fetcher::new_tarball_with_integrity(url, hash),
+            ));
+        }
+
+        let is_zip = url
+            .split(['?', '#'])
+            .next()
+            .is_some_and(|path| path.to_ascii_lowercase().ends_with(".zip"));
+
+        let fetcher = if is_zip {
+            Fetcher::FetchZip {
+                url,
+                hash: String::new(),
+            }
+        } else {
+            Fetcher::FetchTarball {
+                url,
+                hash: String::new(),
+            }
         };
+        let (fetcher, sha256) = self.prefetch_fetcher(fetcher)?;
 
-        Ok(Package::new(name, fetcher))
+        Ok(Package::new(name, fetcher).with_sha256(sha256))
+    }
+
+    /// The integrity of a tarball and the url to fetch it from, if the
+    /// lockfile gives one, either after its metadata or as the fragment
+    /// of its url
+    fn tarball_integrity(&self, url: &str) -> Option<(String, String)> {
+        if let Some(integrity) = self
+            .values
+            .iter()
+            .filter_map(Value::as_str)
+            .find(|value| is_integrity(value))
+        {
+            return Some((url.to_owned(), integrity.to_owned()));
+        }
+
+        let (url, fragment) = url.rsplit_once('#')?;
+
+        is_integrity(fragment).then(|| (url.to_owned(), fragment.to_owned()))
     }
 
     /// # Deserialize a workspace package
     ///
     /// Deserialize a workspace package from it's bun lockfile representation
     ///
-    /// This is found in the source as a tuple of arity 2
+    /// This is found in the source as a tuple of arity 1
+    ///
+    /// Workspace entries are usually given the path of the workspace,
+    /// but may instead hold the range it was depended on with, such
+    /// as `workspace:*`, `workspace:^1.2.3` or `workspace:1.2.3`. These
+    /// are resolved to the lockfile's workspace of the same name, and
+    /// checked against its version.
+    ///
+    /// ```rust
+    /// use bun2nix::{Lockfile, Options, package::Fetcher};
+    ///
+    /// let lockfile = |range: &str| -> Lockfile {
+    ///     format!(r#"{{
+    ///       "lockfileVersion": 1,
+    ///       "workspaces": {{
+    ///         "": {{ "name": "app" }},
+    ///         "packages/lib": {{ "name": "@acme/lib", "version": "1.4.0" }},
+    ///       }},
+    ///       "packages": {{
+    ///         "@acme/lib": ["@acme/lib@workspace:{range}"],
+    ///       }}
+    ///     }}"#).parse().unwrap()
+    /// };
+    ///
+    /// let options = Options::default();
+    ///
+    /// for range in ["packages/lib", "*", "^1.2.3", "~1.4.0", "1.4.0"] {
+    ///     let packages = lockfile(range).packages(&options).unwrap();
+    ///
+    ///     assert!(matches!(
+    ///         &packages[0].fetcher,
+    ///         Fetcher::CopyToStore { path, .. } if path == "packages/lib"
+    ///     ));
+    /// }
+    ///
+    /// for range in ["^2.0.0", "~1.3.0", "1.2.3"] {
+    ///     assert!(lockfile(range).packages(&options).is_err());
+    /// }
+    /// ```
     pub fn deserialize_workspace_package(mut self) -> Result<Package> {
-        let id = swap_remove_value(&mut self.values, 0);
-        let path = Self::drain_after_substring(id, "workspace:")
-            .ok_or(Error::MissingWorkspaceSpecifier)?;
+        let id = swap_remove_value(&mut self.values, 0)?;
+        let specifier =
+            strip_name_prefix(&id).ok_or_else(|| Error::NoAtInPackageIdentifier(id.clone()))?;
+        let package_name = &id[..id.len() - specifier.len() - 1];
+        let specifier = specifier
+            .strip_prefix("workspace:")
+            .ok_or_else(|| Error::MissingWorkspaceSpecifier(id.clone()))?;
+
+        let is_path = self
+            .context
+            .workspaces
+            .values()
+            .any(|workspace| workspace.path == specifier);
+
+        let path = if is_path || !is_version_range(specifier) {
+            specifier
+        } else {
+            let workspace = self
+                .context
+                .workspaces
+                .get(package_name)
+                .ok_or_else(|| Error::UnknownWorkspacePackage(id.clone()))?;
+
+            if let Some(version) = &workspace.version
+                && !satisfies_range(specifier, version)
+            {
+                return Err(Error::UnsatisfiedWorkspaceRange {
+                    package: package_name.to_owned(),
+                    range: specifier.to_owned(),
+                    version: version.clone(),
+                });
+            }
+
+            &workspace.path
+        };
+
+        let path = self.context.resolve_path(path);
+
+        let fetcher = Fetcher::CopyToStore {
+            path,
+            exclude: self.context.copy_excludes.clone(),
+        };
 
-        Ok(Package::new(self.name, Fetcher::CopyToStore { path }))
+        Ok(Package::new(self.name, fetcher))
     }
 
-    fn drain_after_substring(mut input: String, sub: &str) -> Option<String> {
-        let pos = input.rfind(sub)? + sub.len();
+    /// # Prefetch Fetcher
+    ///
+    /// Fill in the hash of a fetcher by prefetching its source,
+    /// if it has one which must be prefetched, along with the hash
+    /// in nix's base-32 `sha256` form if `options.emit_sha256` is set
+    ///
+    /// Git sources locked to a commit, including the abbreviated shas bun
+    /// writes, are prefetched as with `prefetch`, reusing known hashes.
+    ///
+    /// Those locked to a branch or tag are always prefetched live, and
+    /// pinned to the commit the ref resolved to, so their hash stays
+    /// valid as the ref moves on. Refs which turn out to be branches are
+    /// warned about, as the next conversion may then resolve them to a
+    /// different commit.
+    ///
+    /// ```rust
+    /// use bun2nix::{
+    ///     Options, Result, generate_bun_nix_with_prefetcher,
+    ///     lockfile::{Prefetch, Prefetcher},
+    /// };
+    ///
+    /// const COMMIT: &str = "5bfc8f2a1b2c3d4e5f60718293a4b5c6d7e8f901";
+    ///
+    /// struct Resolving;
+    ///
+    /// impl Prefetcher for Resolving {
+    ///     fn prefetch(&self, url: &str, _options: &Options) -> Result<Prefetch> {
+    ///         assert_eq!(url, "github:colinhacks/zod?ref=v3.24.2");
+    ///
+    ///         Ok(Prefetch {
+    ///             hash: "sha256-AAAA".to_owned(),
+    ///             rev: Some(COMMIT.to_owned()),
+    ///             sha256: None,
+    ///         })
+    ///     }
+    /// }
+    ///
+    /// let options = Options::default();
+    ///
+    /// let lockfile = r#"{
+    ///   "lockfileVersion": 1,
+    ///   "packages": {
+    ///     "zod": ["zod@github:colinhacks/zod#v3.24.2", {}, "colinhacks-zod-v3.24.2"],
+    ///   }
+    /// }"#;
+    /// let nix = generate_bun_nix_with_prefetcher(lockfile, &options, &Resolving).unwrap();
+    ///
+    /// assert!(nix.contains(&format!(r#"rev = "{COMMIT}";"#)));
+    /// assert!(nix.contains(&format!(r#"name = "zod-{COMMIT}";"#)));
+    /// assert!(nix.contains(r#"hash = "sha256-AAAA";"#));
+    /// ```
+    pub fn prefetch_fetcher(&self, fetcher: Fetcher) -> Result<(Fetcher, Option<String>)> {
+        let Some(url) = fetcher.prefetch_url() else {
+            return Ok((fetcher, None));
+        };
+
+        let Some(git_ref) = fetcher.symbolic_ref() else {
+            let prefetch = self.prefetch(&url)?;
+            let sha256 = self.emitted_sha256(&prefetch);
+
+            return Ok((fetcher.with_hash(prefetch.hash), sha256));
+        };
+
+        let position = self.context.progress.resolve();
+        let prefetch = self.prefetch_live(&url, &position)?;
+        let sha256 = self.emitted_sha256(&prefetch);
+
+        let Some(rev) = prefetch.rev else {
+            if !self.context.options.dry_run {
+                warn!(
+                    "The prefetch of `{}` did not report the commit `{git_ref}` resolved to, so it is left unpinned",
+                    self.name
+                );
+            }
+
+            return Ok((fetcher.with_hash(prefetch.hash), sha256));
+        };
+
+        // Only known to be a branch once it didn't resolve to the commit it abbreviates
+        let is_abbreviated = rev.starts_with(&git_ref.to_ascii_lowercase());
+        if !is_abbreviated && !is_tag(git_ref) {
+            warn!(
+                "`{}` is locked to the branch `{git_ref}`, which may resolve to a different commit each time it is converted",
+                self.name
+            );
+        }
+
+        debug!("Resolved `{git_ref}` of `{}` to {rev}", self.name);
+
+        // Cached under the pinned url, so the hash is reused should
+        // the lockfile later be locked to the commit itself
+        let fetcher = fetcher.with_rev(rev).with_hash(prefetch.hash.clone());
+        if let Some(pinned_url) = fetcher.prefetch_url() {
+            self.context.cache.insert(&pinned_url, &prefetch.hash);
+        }
 
-        Some(input.drain(pos..).collect())
+        Ok((fetcher, sha256))
+    }
+
+    /// The `sha256` form of a prefetched hash, if it is to be written out
+    fn emitted_sha256(&self, prefetch: &Prefetch) -> Option<String> {
+        prefetch
+            .sha256
+            .clone()
+            .filter(|_| self.context.options.emit_sha256)
+    }
+
+    /// # Prefetch
+    ///
+    /// Prefetch a package url, reusing the hash from the previous
+    /// output or the cache if it has been prefetched before
+    ///
+    /// Failures are reported along with the package name and url,
+    /// so a single bad dependency is easy to find
+    ///
+    /// During a dry run, uncached urls are recorded and given
+    /// a placeholder hash instead of being prefetched.
+    ///
+    /// With `options.frozen`, an uncached url is an error instead, while
+    /// packages whose hash the lockfile gives are unaffected:
+    ///
+    /// ```rust
+    /// use bun2nix::{
+    ///     Error, Options, Result, generate_bun_nix_with_prefetcher,
+    ///     lockfile::{Prefetch, Prefetcher},
+    /// };
+    ///
+    /// struct Offline;
+    ///
+    /// impl Prefetcher for Offline {
+    ///     fn prefetch(&self, url: &str, _options: &Options) -> Result<Prefetch> {
+    ///         panic!("Nothing should be prefetched, but '{url}' was");
+    ///     }
+    /// }
+    ///
+    /// let npm = r#""is-number": ["is-number@7.0.0", "", {}, "sha512-41Cifkg6e8TylSpdtTpeLVMqvSBEVzTttHvERD741+pnZ8ANv0004MRL43QKPDlK9cGvNp6NZWZUBlbGXYxxng=="]"#;
+    /// let github = r#""zod": ["zod@github:colinhacks/zod#5bfc8f2", {}, "colinhacks-zod-5bfc8f2"]"#;
+    /// let lockfile = |entries: &[&str]| {
+    ///     format!(r#"{{ "lockfileVersion": 1, "packages": {{ {} }} }}"#, entries.join(", "))
+    /// };
+    ///
+    /// let options = Options {
+    ///     frozen: true,
+    ///     ..Default::default()
+    /// };
+    ///
+    /// assert!(generate_bun_nix_with_prefetcher(&lockfile(&[npm]), &options, &Offline).is_ok());
+    ///
+    /// let result = generate_bun_nix_with_prefetcher(&lockfile(&[npm, github]), &options, &Offline);
+    /// assert!(matches!(
+    ///     result,
+    ///     Err(Error::PrefetchRequiredButFrozen { package }) if package == "zod"
+    /// ));
+    ///
+    /// // Unless the hash is known, such as from the previous output
+    /// let previous = std::env::temp_dir().join("bun2nix-frozen-doctest.nix");
+    /// std::fs::write(&previous, r#"
+    ///   "zod" = fetchFromGitHub {
+    ///     owner = "colinhacks";
+    ///     repo = "zod";
+    ///     rev = "5bfc8f2";
+    ///     hash = "sha256-AAAA";
+    ///   };
+    /// "#).unwrap();
+    ///
+    /// let options = Options {
+    ///     previous_output: Some(previous),
+    ///     ..options
+    /// };
+    /// let nix = generate_bun_nix_with_prefetcher(&lockfile(&[npm, github]), &options, &Offline).unwrap();
+    /// assert!(nix.contains(r#"hash = "sha256-AAAA";"#));
+    /// ```
+    pub fn prefetch(&self, url: &str) -> Result<Prefetch> {
+        let position = self.context.progress.resolve();
+
+        if let Some(hash) = self.context.previous_output.get(url) {
+            debug!("Reusing the hash for '{url}' from the previous output");
+
+            return Ok(Prefetch::from_hash(hash));
+        }
+
+        if let Some(hash) = self.context.cache.get(url) {
+            debug!("Using the cached hash for '{url}'");
+
+            return Ok(Prefetch::from_hash(hash));
+        }
+
+        self.prefetch_live(url, &position)
+    }
+
+    /// Prefetch a package url without reusing a known hash, or record
+    /// it as needing a live prefetch during a dry run
+    fn prefetch_live(&self, url: &str, position: &str) -> Result<Prefetch> {
+        if self.context.options.dry_run {
+            self.context.record_live_prefetch(url);
+
+            return Ok(Prefetch::from_hash(DRY_RUN_HASH.to_owned()));
+        }
+
+        if self.context.options.frozen {
+            return Err(Error::PrefetchRequiredButFrozen {
+                package: self.name.clone(),
+            });
+        }
+
+        self.context.progress.prefetching(position, &self.name);
+
+        let start = Instant::now();
+        let mut prefetch = self
+            .context
+            .with_prefetch_slot(url, || {
+                self.context.prefetcher.prefetch(url, self.context.options)
+            })
+            .map_err(|source| match source {
+                Error::FetchingTimedOut(_) => Error::PrefetchTimeout {
+                    package: self.name.clone(),
+                    url: url.to_owned(),
+                },
+                source => Error::PrefetchFailed {
+                    package: self.name.clone(),
+                    url: url.to_owned(),
+                    source: Box::new(source),
+                },
+            })?;
+        prefetch.hash = Prefetch::normalize_hash(&prefetch.hash);
+        self.context.cache.insert(url, &prefetch.hash);
+
+        info!("Prefetched '{url}' in {:?}", start.elapsed());
+
+        Ok(prefetch.with_sha256())
     }
 }
 
@@ -256,21 +1426,265 @@ impl PackageDeserializer {
 /// ];
 ///
 /// assert_eq!(
-///     swap_remove_value(&mut values, 0),
+///     swap_remove_value(&mut values, 0).unwrap(),
 ///     "@types/bun@1.2.4"
 /// );
 /// assert_eq!(
-///     swap_remove_value(&mut values, 0),
+///     swap_remove_value(&mut values, 0).unwrap(),
 ///     "sha512-QtuV5OMR8/rdKJs213iwXDpfVvnskPXY/S0ZiFbsTjQZycuqPbMW8Gf/XhLfwE5njW8sxI2WjISURXPlHypMFA=="
 /// );
+///
+/// // Non string values are rejected
+/// assert!(swap_remove_value(&mut values, 0).is_err());
+///
+/// // Escape sequences are decoded rather than copied verbatim
+/// let mut values = vec![json!("pkg@https://example.com/\"quoted\"\\caf\u{e9}.tgz")];
+///
+/// assert_eq!(
+///     swap_remove_value(&mut values, 0).unwrap(),
+///     r#"pkg@https://example.com/"quoted"\café.tgz"#
+/// );
 /// ```
-pub fn swap_remove_value(values: &mut Values, index: usize) -> String {
-    let mut value = values.swap_remove(index).to_string();
+pub fn swap_remove_value(values: &mut Values, index: usize) -> Result<String> {
+    match values.swap_remove(index) {
+        Value::String(value) => Ok(value),
+        value => Err(Error::UnexpectedValueType(value.to_string())),
+    }
+}
 
-    debug_assert!(value.starts_with('"'), "Value should start with a quote");
-    debug_assert!(value.ends_with('"'), "Value should end with a quote");
+/// # Strip Name Prefix
+///
+/// Remove the `name@` or `@scope/name@` prefix from a lockfile identifier,
+/// returning the remaining source specifier.
+///
+/// Only the `@` directly after the package name is treated as the separator,
+/// so any `@` appearing later in the source (such as the user info in an SSH
+/// url) is left untouched. For scoped packages the separator is only looked
+/// for after the `/` ending the scope, and an identifier missing it has no
+/// source. Every kind of package, and npm identifiers in the fetcher, are
+/// split with this rather than by searching for `@`.
+///
+///```rust
+/// use bun2nix::lockfile::strip_name_prefix;
+///
+/// assert_eq!(
+///     strip_name_prefix("repo@git+ssh://git@github.com/org/repo.git#a1b2c3"),
+///     Some("git+ssh://git@github.com/org/repo.git#a1b2c3")
+/// );
+/// assert_eq!(
+///     strip_name_prefix("repo@git+https://github.com/org/repo.git#a1b2c3"),
+///     Some("git+https://github.com/org/repo.git#a1b2c3")
+/// );
+/// assert_eq!(
+///     strip_name_prefix("@org/repo@git+ssh://git@github.com/org/repo.git#a1b2c3"),
+///     Some("git+ssh://git@github.com/org/repo.git#a1b2c3")
+/// );
+/// assert_eq!(
+///     strip_name_prefix("@org/repo@github:org/repo#a1b2c3"),
+///     Some("github:org/repo#a1b2c3")
+/// );
+/// assert_eq!(
+///     strip_name_prefix("@org/repo@git+ssh://git@github.com:org/repo.git#v1@2"),
+///     Some("git+ssh://git@github.com:org/repo.git#v1@2")
+/// );
+///
+/// // Versions, including aliases and build metadata with an `@` in them
+/// assert_eq!(strip_name_prefix("is-number@7.0.0"), Some("7.0.0"));
+/// assert_eq!(strip_name_prefix("@types/node@22.13.5"), Some("22.13.5"));
+/// assert_eq!(strip_name_prefix("pkg@1.2.3+build@5"), Some("1.2.3+build@5"));
+/// assert_eq!(
+///     strip_name_prefix("node-types@npm:@types/node@22.13.5"),
+///     Some("npm:@types/node@22.13.5")
+/// );
+///
+/// // Other sources
+/// assert_eq!(
+///     strip_name_prefix("tiny@https://example.com/@scope/tiny.tgz"),
+///     Some("https://example.com/@scope/tiny.tgz")
+/// );
+/// assert_eq!(strip_name_prefix("@org/app@workspace:packages/app"), Some("workspace:packages/app"));
+/// assert_eq!(strip_name_prefix("lib@file:../lib"), Some("file:../lib"));
+///
+/// // Paths which themselves contain an `@`, `file:` or `workspace:`
+/// assert_eq!(
+///     strip_name_prefix("@org/app@workspace:packages/@org/app@2"),
+///     Some("workspace:packages/@org/app@2")
+/// );
+/// assert_eq!(
+///     strip_name_prefix("lib@file:../vendor/file:lib@1.0.0"),
+///     Some("file:../vendor/file:lib@1.0.0")
+/// );
+/// assert_eq!(
+///     strip_name_prefix("@org/lib@file:../workspace:@org/lib"),
+///     Some("file:../workspace:@org/lib")
+/// );
+///
+/// // Identifiers without a source, or with a scope but no name
+/// assert_eq!(strip_name_prefix("@org/repo"), None);
+/// assert_eq!(strip_name_prefix("repo"), None);
+/// assert_eq!(strip_name_prefix("@"), None);
+/// assert_eq!(strip_name_prefix("@org@1.0.0"), None);
+/// assert_eq!(strip_name_prefix(""), None);
+/// ```
+pub fn strip_name_prefix(id: &str) -> Option<&str> {
+    // A scoped name always has a `/` between it's scope and name
+    let scope_len = match id.strip_prefix('@') {
+        Some(scoped) => scoped.find('/')? + 2,
+        None => 0,
+    };
+    let at_pos = id[scope_len..].find('@')? + scope_len;
+
+    Some(&id[at_pos + 1..])
+}
+
+/// Whether a `workspace:` specifier is a version range, rather than a path
+fn is_version_range(specifier: &str) -> bool {
+    specifier.is_empty()
+        || specifier.starts_with(|c: char| c.is_ascii_digit() || ['*', '^', '~', '='].contains(&c))
+}
+
+/// # Satisfies Range
+///
+/// Whether a version satisfies a `workspace:` range, which is one of
+/// `*`, a caret or tilde range, or an exact version
+///
+/// Prerelease and build metadata are ignored, as only the ranges bun
+/// writes for local packages need to be understood.
+///
+/// ```rust
+/// use bun2nix::lockfile::satisfies_range;
+///
+/// assert!(satisfies_range("*", "0.0.1"));
+/// assert!(satisfies_range("", "3.1.4"));
+///
+/// assert!(satisfies_range("^1.2.3", "1.9.0"));
+/// assert!(!satisfies_range("^1.2.3", "1.2.2"));
+/// assert!(!satisfies_range("^1.2.3", "2.0.0"));
+/// assert!(satisfies_range("^0.2.3", "0.2.9"));
+/// assert!(!satisfies_range("^0.2.3", "0.3.0"));
+/// assert!(!satisfies_range("^0.0.3", "0.0.4"));
+///
+/// assert!(satisfies_range("~1.2.3", "1.2.9"));
+/// assert!(!satisfies_range("~1.2.3", "1.3.0"));
+/// assert!(satisfies_range("~1", "1.7.0"));
+///
+/// assert!(satisfies_range("1.2.3", "1.2.3"));
+/// assert!(satisfies_range("=1.2.3", "1.2.3-beta.1"));
+/// assert!(!satisfies_range("1.2.3", "1.2.4"));
+/// assert!(satisfies_range("1.2", "1.2.4"));
+/// ```
+pub fn satisfies_range(range: &str, version: &str) -> bool {
+    let parse = |version: &str| -> Vec<u64> {
+        version
+            .split(['-', '+'])
+            .next()
+            .unwrap_or_default()
+            .split('.')
+            .map_while(|part| part.parse().ok())
+            .collect()
+    };
+
+    let version = parse(version);
+    let part = |idx: usize| version.get(idx).copied().unwrap_or_default();
+
+    let (operator, bound) = match range.trim() {
+        "" | "*" => return true,
+        range => match range.strip_prefix(['^', '~', '=']) {
+            Some(bound) => (&range[..1], parse(bound)),
+            None => ("=", parse(range)),
+        },
+    };
+
+    // The number of leading parts which must match the bound exactly
+    let fixed = match operator {
+        "^" => bound
+            .iter()
+            .position(|part| *part != 0)
+            .map_or(bound.len(), |idx| idx + 1),
+        "~" => bound.len().min(2),
+        _ => bound.len(),
+    };
+    let fixed = fixed.min(bound.len());
+
+    let at_least = bound
+        .iter()
+        .enumerate()
+        .map(|(idx, bound)| part(idx).cmp(bound))
+        .find(|ordering| ordering.is_ne())
+        .is_none_or(|ordering| ordering.is_gt());
+
+    at_least && (0..fixed).all(|idx| part(idx) == bound[idx])
+}
+
+/// Whether a git ref looks like a release tag, such as `v1.2.3`, rather
+/// than a branch, as only the name of the ref is known
+fn is_tag(git_ref: &str) -> bool {
+    git_ref.starts_with("refs/tags/")
+        || git_ref
+            .trim_start_matches('v')
+            .starts_with(|c: char| c.is_ascii_digit())
+}
+
+/// Whether a lockfile value looks like an integrity hash, rather than a url or tag
+fn is_integrity(value: &str) -> bool {
+    ["sha1-", "sha256-", "sha512-"]
+        .iter()
+        .any(|algorithm| value.starts_with(algorithm))
+}
+
+/// # Strip NPM Alias
+///
+/// Resolve an `alias@npm:target@version` style lockfile identifier to the
+/// identifier of the package it aliases, leaving any other identifier as is.
+///
+///```rust
+/// use bun2nix::lockfile::strip_npm_alias;
+///
+/// assert_eq!(strip_npm_alias("foo@npm:bar@1.2.3"), "bar@1.2.3");
+/// assert_eq!(
+///     strip_npm_alias("@my/types@npm:@types/node@22.13.5"),
+///     "@types/node@22.13.5"
+/// );
+/// assert_eq!(strip_npm_alias("bar@1.2.3"), "bar@1.2.3");
+/// ```
+pub fn strip_npm_alias(id: &str) -> &str {
+    strip_name_prefix(id)
+        .and_then(|source| source.strip_prefix("npm:"))
+        .unwrap_or(id)
+}
+
+/// # Split Git Subdirectory
+///
+/// Split the fragment of a git dependency into the ref to fetch and,
+/// if the package lives in a subdirectory of the repo, that directory,
+/// as given by a `path:` part after `::` or `&`
+///
+///```rust
+/// use bun2nix::lockfile::split_git_subdirectory;
+///
+/// assert_eq!(
+///     split_git_subdirectory("a1b2c3::path:packages/thing".to_owned()),
+///     ("a1b2c3".to_owned(), Some("packages/thing".to_owned()))
+/// );
+/// assert_eq!(
+///     split_git_subdirectory("a1b2c3&path:/packages/thing/".to_owned()),
+///     ("a1b2c3".to_owned(), Some("packages/thing".to_owned()))
+/// );
+/// assert_eq!(split_git_subdirectory("a1b2c3".to_owned()), ("a1b2c3".to_owned(), None));
+/// ```
+pub fn split_git_subdirectory(fragment: String) -> (String, Option<String>) {
+    let mut rev = None;
+    let mut dir = None;
+
+    for part in fragment.split("::").flat_map(|part| part.split('&')) {
+        match part.strip_prefix("path:") {
+            Some(path) => dir = Some(path.trim_matches('/').to_owned()),
+            None if rev.is_none() => rev = Some(part.to_owned()),
+            None => {}
+        }
+    }
 
-    value.drain(1..value.len() - 1).collect()
+    (rev.unwrap_or_default(), dir.filter(|dir| !dir.is_empty()))
 }
 
 /// # Split Once (Owned)
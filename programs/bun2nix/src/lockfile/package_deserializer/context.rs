@@ -0,0 +1,484 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    env,
+    path::{self, Component, Path, PathBuf},
+    sync::{Condvar, Mutex},
+};
+
+use crate::{
+    Options,
+    error::Result,
+    lockfile::Workspace,
+    npmrc::Npmrc,
+    options::DEFAULT_COPY_EXCLUDES,
+    options::{DEFAULT_GITHUB_DOMAIN, DEFAULT_GITLAB_DOMAIN},
+    package::{DEFAULT_REGISTRY, Fetcher},
+};
+
+use super::{NixPrefetcher, PrefetchCache, Prefetcher, PreviousOutput, Progress, TargetSystem};
+
+/// # Deserializer Context
+///
+/// State shared between every package deserialized from
+/// a single lockfile
+#[derive(Debug)]
+pub struct DeserializerContext<'a> {
+    /// The options in use for this conversion
+    pub options: &'a Options,
+
+    /// Calculates the hashes of packages which must be prefetched
+    pub prefetcher: &'a dyn Prefetcher,
+
+    /// The cache of previously prefetched hashes
+    pub cache: PrefetchCache,
+
+    /// The hashes of the previous output, if one was given
+    pub previous_output: PreviousOutput,
+
+    /// Registry settings read from the `.npmrc` file, if one was given
+    pub npmrc: Npmrc,
+
+    /// The registry, with a trailing slash, for packages without a scope registry
+    pub default_registry: String,
+
+    /// Mirrors of the default registry, with trailing slashes, to try before it
+    pub registry_mirrors: Vec<String>,
+
+    /// Patch files to apply to packages, keyed by package identifier
+    pub patched_dependencies: BTreeMap<String, String>,
+
+    /// The names of packages bun is allowed to run lifecycle scripts for
+    pub trusted_dependencies: BTreeSet<String>,
+
+    /// The local workspace packages, keyed by package name
+    pub workspaces: BTreeMap<String, LocalWorkspace>,
+
+    /// The systems packages are being installed for, empty for all of them
+    pub target_systems: Vec<TargetSystem>,
+
+    /// The directory lockfile paths are relative to, from the output directory
+    pub root: PathBuf,
+
+    /// Globs of files and directories to leave out when copying local packages
+    pub copy_excludes: Vec<String>,
+
+    /// The urls which would have been prefetched, if this is a dry run
+    pub live_prefetches: Mutex<Vec<String>>,
+
+    /// Reports each package as it is prefetched, if enabled
+    pub progress: Progress,
+
+    /// The prefetches which may start, out of `options.prefetch_jobs`,
+    /// and those running against each host
+    prefetch_slots: Mutex<PrefetchSlots>,
+
+    /// Signalled whenever a prefetch finishes and frees its slot
+    prefetch_slot_freed: Condvar,
+}
+
+/// The prefetches running, overall and against each host
+#[derive(Debug, Default)]
+struct PrefetchSlots {
+    /// The number of further prefetches which may start
+    free: usize,
+
+    /// The number of prefetches running against each host
+    running: HashMap<String, usize>,
+}
+
+/// # Local Workspace
+///
+/// A workspace of the lockfile, as a package other packages may depend on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalWorkspace {
+    /// The lockfile relative path of the workspace
+    pub path: String,
+
+    /// The version its `package.json` declares, if any
+    pub version: Option<String>,
+}
+
+impl<'a> DeserializerContext<'a> {
+    /// # New Deserializer Context
+    ///
+    /// Load all the state needed to deserialize packages with the given options
+    pub fn new(
+        options: &'a Options,
+        patched_dependencies: BTreeMap<String, String>,
+        trusted_dependencies: BTreeSet<String>,
+    ) -> Result<Self> {
+        let npmrc = match &options.npmrc {
+            Some(path) => Npmrc::load(path)?,
+            None => Npmrc::default(),
+        };
+
+        let previous_output = match &options.previous_output {
+            Some(path) => PreviousOutput::load(path)?,
+            None => PreviousOutput::default(),
+        };
+
+        let default_registry = Self::with_trailing_slash(
+            options
+                .default_registry
+                .as_deref()
+                .unwrap_or(DEFAULT_REGISTRY),
+        );
+        let registry_mirrors = options
+            .registry_mirrors
+            .iter()
+            .map(|mirror| Self::with_trailing_slash(mirror))
+            .collect();
+
+        let target_systems = options
+            .target_systems
+            .iter()
+            .map(|system| system.parse())
+            .collect::<Result<_>>()?;
+
+        let root = match (&options.root, &options.output_dir) {
+            (None, None) => PathBuf::new(),
+            _ if cfg!(target_arch = "wasm32") => PathBuf::new(),
+            (root, output_dir) => {
+                let cwd = env::current_dir()?;
+                let root = root.as_deref().unwrap_or(&cwd);
+                let output_dir = output_dir.as_deref().unwrap_or(&cwd);
+
+                Self::relative_path(&path::absolute(output_dir)?, &path::absolute(root)?)
+            }
+        };
+
+        let copy_excludes = DEFAULT_COPY_EXCLUDES
+            .iter()
+            .map(|glob| (*glob).to_owned())
+            .chain(options.copy_excludes.iter().cloned())
+            .collect();
+
+        Ok(Self {
+            options,
+            prefetcher: &NixPrefetcher,
+            cache: PrefetchCache::load(options),
+            previous_output,
+            npmrc,
+            default_registry,
+            registry_mirrors,
+            patched_dependencies,
+            trusted_dependencies,
+            workspaces: BTreeMap::new(),
+            target_systems,
+            root,
+            copy_excludes,
+            live_prefetches: Mutex::default(),
+            progress: Progress::new(options.progress && !options.dry_run),
+            prefetch_slots: Mutex::new(PrefetchSlots {
+                free: options.prefetch_jobs.max(1),
+                running: HashMap::new(),
+            }),
+            prefetch_slot_freed: Condvar::new(),
+        })
+    }
+
+    /// # With Prefetcher
+    ///
+    /// Use the given prefetcher in place of `nix flake prefetch`
+    pub fn with_prefetcher(mut self, prefetcher: &'a dyn Prefetcher) -> Self {
+        self.prefetcher = prefetcher;
+        self
+    }
+
+    /// # With Known Hashes
+    ///
+    /// Reuse the hashes of fetchers which have already been prefetched,
+    /// such as by an earlier pass over the same lockfile
+    pub fn with_known_hashes(mut self, fetchers: impl IntoIterator<Item = Fetcher>) -> Self {
+        self.previous_output.extend(fetchers);
+        self
+    }
+
+    /// # With Workspaces
+    ///
+    /// Index the lockfile's named workspaces by package name, so
+    /// `workspace:` ranges can be resolved to their directories
+    pub fn with_workspaces(mut self, workspaces: &HashMap<String, Workspace>) -> Self {
+        self.workspaces = workspaces
+            .iter()
+            .filter_map(|(path, workspace)| {
+                let local = LocalWorkspace {
+                    path: path.clone(),
+                    version: workspace.version.clone(),
+                };
+
+                Some((workspace.name.clone()?, local))
+            })
+            .collect();
+        self
+    }
+
+    /// # Record Live Prefetch
+    ///
+    /// Note that a url would have been prefetched, were this not a dry run
+    pub fn record_live_prefetch(&self, url: &str) {
+        self.live_prefetches
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .push(url.to_owned());
+    }
+
+    /// # With Prefetch Slot
+    ///
+    /// Run a prefetch of the given url once fewer than `options.prefetch_jobs`
+    /// others are running, so packages which are deserialized locally can use
+    /// more threads than those which hit the network
+    ///
+    /// With `options.per_host_jobs`, the prefetch also waits until fewer
+    /// than that many others are running against the same host, so no
+    /// one registry or git host is hit hard enough to rate limit it:
+    ///
+    /// ```rust
+    /// use std::{collections::HashMap, sync::Mutex, thread, time::Duration};
+    ///
+    /// use bun2nix::{
+    ///     Options, Result, generate_bun_nix_with_prefetcher,
+    ///     lockfile::{Prefetch, Prefetcher, prefetch_host},
+    /// };
+    ///
+    /// /// Tracks the most prefetches running at once, against each host and overall
+    /// #[derive(Default)]
+    /// struct Counting {
+    ///     running: Mutex<HashMap<String, usize>>,
+    ///     most: Mutex<HashMap<String, usize>>,
+    /// }
+    ///
+    /// impl Prefetcher for Counting {
+    ///     fn prefetch(&self, url: &str, _options: &Options) -> Result<Prefetch> {
+    ///         let host = prefetch_host(url).to_owned();
+    ///         {
+    ///             let mut running = self.running.lock().unwrap();
+    ///             *running.entry(host.clone()).or_default() += 1;
+    ///
+    ///             let total = running.values().sum();
+    ///             let mut most = self.most.lock().unwrap();
+    ///             for (key, count) in [(host.clone(), running[&host]), ("total".to_owned(), total)] {
+    ///                 let most = most.entry(key).or_default();
+    ///                 *most = (*most).max(count);
+    ///             }
+    ///         }
+    ///
+    ///         thread::sleep(Duration::from_millis(50));
+    ///         *self.running.lock().unwrap().get_mut(&host).unwrap() -= 1;
+    ///
+    ///         Ok(Prefetch { hash: "sha256-AAAA".to_owned(), rev: None, sha256: None })
+    ///     }
+    /// }
+    ///
+    /// let packages: Vec<_> = (0..6)
+    ///     .flat_map(|i| [
+    ///         format!(r#""gh-{i}": ["gh-{i}@github:acme/gh-{i}#a1b2c3", {{}}, "acme-gh-{i}-a1b2c3"]"#),
+    ///         format!(r#""tar-{i}": ["tar-{i}@https://example.com/tar-{i}.tgz", {{}}]"#),
+    ///     ])
+    ///     .collect();
+    /// let lockfile = format!(
+    ///     r#"{{ "lockfileVersion": 1, "packages": {{ {} }} }}"#,
+    ///     packages.join(",")
+    /// );
+    ///
+    /// let options = Options {
+    ///     prefetch_jobs: 8,
+    ///     per_host_jobs: 2,
+    ///     ..Default::default()
+    /// };
+    /// let prefetcher = Counting::default();
+    /// generate_bun_nix_with_prefetcher(&lockfile, &options, &prefetcher).unwrap();
+    ///
+    /// let most = prefetcher.most.into_inner().unwrap();
+    /// assert!(most["github.com"] <= 2);
+    /// assert!(most["example.com"] <= 2);
+    /// assert!(most["total"] <= 4);
+    /// ```
+    pub fn with_prefetch_slot<T>(&self, url: &str, prefetch: impl FnOnce() -> T) -> T {
+        let host = prefetch_host(url);
+        let per_host_jobs = self.options.per_host_jobs;
+
+        let lock = || {
+            self.prefetch_slots
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+        };
+        let is_full = |slots: &PrefetchSlots| {
+            slots.free == 0
+                || (per_host_jobs > 0
+                    && slots
+                        .running
+                        .get(host)
+                        .is_some_and(|running| *running >= per_host_jobs))
+        };
+
+        let mut slots = lock();
+        while is_full(&slots) {
+            slots = self
+                .prefetch_slot_freed
+                .wait(slots)
+                .unwrap_or_else(|err| err.into_inner());
+        }
+        slots.free -= 1;
+        *slots.running.entry(host.to_owned()).or_default() += 1;
+        drop(slots);
+
+        let result = prefetch();
+
+        let mut slots = lock();
+        slots.free += 1;
+        if let Some(running) = slots.running.get_mut(host) {
+            *running -= 1;
+        }
+        drop(slots);
+
+        // Waiters may be held back by different hosts, so all are woken
+        self.prefetch_slot_freed.notify_all();
+
+        result
+    }
+
+    /// # Resolve Path
+    ///
+    /// Resolve a path found in the lockfile, such as that of a `file:`
+    /// or `workspace:` package, against `options.root`, so that it is
+    /// relative to `options.output_dir`
+    ///
+    /// ```rust
+    /// use bun2nix::{Options, lockfile::DeserializerContext};
+    ///
+    /// let options = Options {
+    ///     root: Some("frontend/../app".into()),
+    ///     ..Default::default()
+    /// };
+    /// let context = DeserializerContext::new(&options, Default::default(), Default::default()).unwrap();
+    ///
+    /// assert_eq!(context.resolve_path("packages/lib"), "app/packages/lib");
+    /// assert_eq!(context.resolve_path("../shared"), "shared");
+    /// assert_eq!(context.resolve_path(".."), ".");
+    ///
+    /// // Absolute directories give the same result from any working directory
+    /// let options = Options {
+    ///     root: Some("/srv/project".into()),
+    ///     output_dir: Some("/srv/project/nix".into()),
+    ///     ..Default::default()
+    /// };
+    /// let context = DeserializerContext::new(&options, Default::default(), Default::default()).unwrap();
+    ///
+    /// assert_eq!(context.resolve_path("packages/lib"), "../packages/lib");
+    /// ```
+    pub fn resolve_path(&self, path: &str) -> String {
+        let resolved = Self::normalize(&self.root.join(path));
+
+        match resolved.to_string_lossy() {
+            resolved if resolved.is_empty() => ".".to_owned(),
+            resolved => resolved.into_owned(),
+        }
+    }
+
+    /// # Absolute Path
+    ///
+    /// Resolve a path found in the lockfile against `options.root`, or
+    /// the current directory, to an absolute path on this machine
+    pub fn absolute_path(&self, path: &str) -> Result<PathBuf> {
+        let root = match &self.options.root {
+            Some(root) => root.clone(),
+            None => env::current_dir()?,
+        };
+
+        Ok(Self::normalize(&path::absolute(root.join(path))?))
+    }
+
+    /// Registry urls are joined with package paths, so must end with a slash
+    fn with_trailing_slash(registry: &str) -> String {
+        if registry.ends_with('/') {
+            registry.to_owned()
+        } else {
+            format!("{registry}/")
+        }
+    }
+
+    /// Lexically remove `.` and `..` components from a path
+    fn normalize(path: &Path) -> PathBuf {
+        let mut normalized = PathBuf::new();
+
+        for component in path.components() {
+            match component {
+                Component::CurDir => (),
+                Component::ParentDir
+                    if matches!(
+                        normalized.components().next_back(),
+                        Some(Component::Normal(_))
+                    ) =>
+                {
+                    normalized.pop();
+                }
+                component => normalized.push(component),
+            }
+        }
+
+        normalized
+    }
+
+    /// The path to `to` from the directory `from`, both of which are absolute
+    fn relative_path(from: &Path, to: &Path) -> PathBuf {
+        let from = Self::normalize(from);
+        let to = Self::normalize(to);
+
+        let common = from
+            .components()
+            .zip(to.components())
+            .take_while(|(a, b)| a == b)
+            .count();
+
+        from.components()
+            .skip(common)
+            .map(|_| Component::ParentDir)
+            .chain(to.components().skip(common))
+            .collect()
+    }
+}
+
+/// # Prefetch Host
+///
+/// The host a prefetch url fetches from, which per host limits on
+/// concurrent prefetches are keyed on
+///
+/// ```rust
+/// use bun2nix::lockfile::prefetch_host;
+///
+/// assert_eq!(prefetch_host("github:acme/ui?ref=a1b2c3"), "github.com");
+/// assert_eq!(prefetch_host("github:acme/ui?ref=a1b2c3&host=github.acme.com"), "github.acme.com");
+/// assert_eq!(prefetch_host("gitlab:acme%2Fweb/ui?ref=v1&host=gitlab.com"), "gitlab.com");
+/// assert_eq!(prefetch_host("tarball+https://example.com:8443/tiny.tgz"), "example.com");
+/// assert_eq!(prefetch_host("git+ssh://git@github.com/acme/ui.git?rev=a1b2c3"), "github.com");
+/// assert_eq!(prefetch_host("file+file:///srv/vendor/foo.tgz"), "");
+/// ```
+pub fn prefetch_host(url: &str) -> &str {
+    let flake = [
+        ("github:", DEFAULT_GITHUB_DOMAIN),
+        ("gitlab:", DEFAULT_GITLAB_DOMAIN),
+    ]
+    .into_iter()
+    .find_map(|(prefix, domain)| Some((url.strip_prefix(prefix)?, domain)));
+
+    if let Some((flake, domain)) = flake {
+        let host = flake.split_once('?').and_then(|(_, query)| {
+            query
+                .split('&')
+                .find_map(|param| param.strip_prefix("host="))
+        });
+
+        return host.unwrap_or(domain);
+    }
+
+    let Some((_, rest)) = url.split_once("://") else {
+        return url;
+    };
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    let host = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+
+    host.split(':').next().unwrap_or_default()
+}
@@ -0,0 +1,123 @@
+use std::{fs, io, path::Path};
+
+/// The round constants of sha256
+const ROUND_CONSTANTS: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// The initial state of sha256
+const INITIAL_STATE: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// The sha256 of the NAR serialisation of a single regular file, which
+/// is the hash `builtins.path` checks it against
+///
+/// Only the file's contents and whether it is executable are part of
+/// it, so it is the same wherever the file is.
+pub fn nar_sha256(path: &Path) -> io::Result<[u8; 32]> {
+    let contents = fs::read(path)?;
+
+    let mut nar = Vec::with_capacity(contents.len() + 160);
+    for field in ["nix-archive-1", "(", "type", "regular"] {
+        write_nar_str(&mut nar, field.as_bytes());
+    }
+    if is_executable(path)? {
+        write_nar_str(&mut nar, b"executable");
+        write_nar_str(&mut nar, b"");
+    }
+    write_nar_str(&mut nar, b"contents");
+    write_nar_str(&mut nar, &contents);
+    write_nar_str(&mut nar, b")");
+
+    Ok(sha256(&nar))
+}
+
+/// Write a NAR string, it's length then it's bytes padded to 8 byte alignment
+fn write_nar_str(nar: &mut Vec<u8>, bytes: &[u8]) {
+    nar.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+    nar.extend_from_slice(bytes);
+    nar.resize(nar.len().next_multiple_of(8), 0);
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> io::Result<bool> {
+    use std::os::unix::fs::PermissionsExt;
+
+    Ok(fs::metadata(path)?.permissions().mode() & 0o100 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(_path: &Path) -> io::Result<bool> {
+    Ok(false)
+}
+
+/// Hash bytes with sha256
+fn sha256(bytes: &[u8]) -> [u8; 32] {
+    let mut padded = bytes.to_vec();
+    padded.push(0x80);
+    padded.resize((padded.len() + 8).next_multiple_of(64) - 8, 0);
+    padded.extend_from_slice(&(bytes.len() as u64 * 8).to_be_bytes());
+
+    let mut state = INITIAL_STATE;
+
+    for block in padded.chunks_exact(64) {
+        let mut schedule = [0u32; 64];
+        for (word, chunk) in schedule.iter_mut().zip(block.chunks_exact(4)) {
+            *word = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        for idx in 16..64 {
+            let s0 = schedule[idx - 15].rotate_right(7)
+                ^ schedule[idx - 15].rotate_right(18)
+                ^ schedule[idx - 15] >> 3;
+            let s1 = schedule[idx - 2].rotate_right(17)
+                ^ schedule[idx - 2].rotate_right(19)
+                ^ schedule[idx - 2] >> 10;
+            schedule[idx] = schedule[idx - 16]
+                .wrapping_add(s0)
+                .wrapping_add(schedule[idx - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = state;
+        for (constant, word) in ROUND_CONSTANTS.iter().zip(schedule) {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let choice = (e & f) ^ (!e & g);
+            let temp1 = h
+                .wrapping_add(s1)
+                .wrapping_add(choice)
+                .wrapping_add(*constant)
+                .wrapping_add(word);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let majority = (a & b) ^ (a & c) ^ (b & c);
+            let temp2 = s0.wrapping_add(majority);
+
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(temp1);
+            d = c;
+            c = b;
+            b = a;
+            a = temp1.wrapping_add(temp2);
+        }
+
+        for (word, value) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *word = word.wrapping_add(value);
+        }
+    }
+
+    let mut digest = [0u8; 32];
+    for (chunk, word) in digest.chunks_exact_mut(4).zip(state) {
+        chunk.copy_from_slice(&word.to_be_bytes());
+    }
+
+    digest
+}
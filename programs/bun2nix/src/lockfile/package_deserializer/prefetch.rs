@@ -1,24 +1,408 @@
-use crate::error::{Error, Result};
+use crate::{
+    Options,
+    error::{Error, Result},
+};
+
+use super::{Proxy, nar_hash::nar_sha256};
 
 use log::warn;
 use serde::{Deserialize, Serialize};
-use std::process::Command;
+use serde_json::Value;
+use std::{
+    fmt,
+    io::Read,
+    path::Path,
+    process::{Command, Output, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// The default number of times a failed prefetch is retried
+pub const DEFAULT_PREFETCH_RETRIES: usize = 3;
+
+/// The default time a single prefetch may take before it is killed
+pub const DEFAULT_PREFETCH_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// The command run to prefetch a package, unless `options.prefetch_command` is given
+///
+/// `{url}` is replaced by the url to prefetch, which is in the form
+/// `nix flake prefetch` accepts, such as `github:owner/repo?ref=rev`
+/// or `git+https://host/repo.git?rev=rev`.
+pub const DEFAULT_PREFETCH_COMMAND: &str = "nix --extra-experimental-features nix-command --extra-experimental-features flakes flake prefetch --json {url}";
+
+/// The command run to prefetch a git repo which keeps it's `.git`
+/// directory, as `nix flake prefetch` always removes it
+///
+/// It is the same script `fetchgit` runs, so the `.git` directory is
+/// made reproducible in the same way.
+pub const NIX_PREFETCH_GIT_COMMAND: &str = "nix-prefetch-git --quiet --leave-dotGit";
+
+/// The digits of nix's base-32 encoding, which leaves out `e`, `o`, `u` and `t`
+const NIX_BASE32_DIGITS: &[u8] = b"0123456789abcdfghijklmnpqrsvwxyz";
+
+/// The delay before retrying a failed prefetch, doubled after each attempt
+pub const PREFETCH_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Parts of `nix` error output which mean a prefetch can never succeed,
+/// and so isn't worth retrying
+const DETERMINISTIC_FAILURES: &[&str] = &[
+    "not found",
+    "unauthorized",
+    "forbidden",
+    "does not exist",
+    "hash mismatch",
+    "unknown revision",
+    "couldn't find remote ref",
+    "not a tree object",
+    "is unsupported",
+    "unsupported input attribute",
+];
+
+/// What comes before the status of a failed download in the error output
+/// of `nix` and `git`, such as `HTTP error 404`
+const HTTP_STATUS_PREFIXES: &[&str] = &["http error ", "returned error: "];
 
 /// # Package Prefetch
 ///
 /// Represents the result of a `nix flake prefetch`
 /// for a given package we don't know the hash for
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Default, Deserialize, Serialize)]
 pub struct Prefetch {
+    /// The SRI hash of the prefetched package
     pub hash: String,
+
+    /// The commit a git branch or tag was resolved to, if
+    /// the prefetch command reported one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rev: Option<String>,
+
+    /// The hash in nix's base-32 `sha256` form, for tools which haven't
+    /// adopted SRI hashes, if it is a sha256 hash
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+/// # Prefetcher
+///
+/// Calculates the hash of a package's source from its prefetch url
+///
+/// This is the only side effect of deserializing a lockfile, so can be
+/// replaced, such as to avoid the network in tests:
+///
+/// ```rust
+/// use bun2nix::{
+///     Options, Result, generate_bun_nix_with_prefetcher,
+///     lockfile::{Prefetch, Prefetcher},
+/// };
+///
+/// struct FixedHash;
+///
+/// impl Prefetcher for FixedHash {
+///     fn prefetch(&self, url: &str, _options: &Options) -> Result<Prefetch> {
+///         assert_eq!(url, "github:colinhacks/zod?ref=5bfc8f2");
+///
+///         Ok(Prefetch { hash: "sha256-AAAA".to_owned(), rev: None, sha256: None })
+///     }
+/// }
+///
+/// let lockfile = r#"{
+///   "lockfileVersion": 1,
+///   "packages": {
+///     "zod": ["zod@github:colinhacks/zod#5bfc8f2", {}, "colinhacks-zod-5bfc8f2"],
+///   }
+/// }"#;
+///
+/// let options = Options::default();
+/// let nix = generate_bun_nix_with_prefetcher(lockfile, &options, &FixedHash).unwrap();
+///
+/// assert!(nix.contains(r#"hash = "sha256-AAAA";"#));
+/// ```
+pub trait Prefetcher: Sync {
+    /// # Prefetch
+    ///
+    /// Calculate the hash of the source at a prefetch url
+    fn prefetch(&self, url: &str, options: &Options) -> Result<Prefetch>;
+}
+
+impl fmt::Debug for dyn Prefetcher + '_ {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Prefetcher")
+    }
+}
+
+/// # Nix Prefetcher
+///
+/// The default prefetcher, which runs `nix flake prefetch`, or
+/// `options.prefetch_command`, retrying and timing out according
+/// to the options
+///
+/// Git repos which keep their `.git` directory are prefetched with
+/// `nix-prefetch-git` instead, unless a prefetch command is given.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NixPrefetcher;
+
+impl Prefetcher for NixPrefetcher {
+    fn prefetch(&self, url: &str, options: &Options) -> Result<Prefetch> {
+        let (command, url) = match &options.prefetch_command {
+            Some(command) => (command.clone(), url),
+            None => Self::leave_dot_git_command(url)
+                .unwrap_or_else(|| (DEFAULT_PREFETCH_COMMAND.to_owned(), url)),
+        };
+
+        Prefetch::prefetch_package_with_retries(
+            url,
+            &command,
+            &Proxy::from_options(options),
+            options.prefetch_retries,
+            options.prefetch_timeout,
+        )
+    }
+}
+
+impl NixPrefetcher {
+    /// # Leave Dot Git Command
+    ///
+    /// The `nix-prefetch-git` command, and the repo url to give it,
+    /// for a git prefetch url marked with `leaveDotGit=1`
+    ///
+    /// ```rust
+    /// use bun2nix::lockfile::NixPrefetcher;
+    ///
+    /// let (command, url) = NixPrefetcher::leave_dot_git_command(
+    ///     "git+https://example.com/repo.git?rev=5bfc8f2a1b2c3d4e5f60718293a4b5c6d7e8f901&submodules=1&leaveDotGit=1",
+    /// )
+    /// .unwrap();
+    ///
+    /// assert_eq!(
+    ///     command,
+    ///     "nix-prefetch-git --quiet --leave-dotGit --rev 5bfc8f2a1b2c3d4e5f60718293a4b5c6d7e8f901 --deepClone --fetch-submodules --url {url}"
+    /// );
+    /// assert_eq!(url, "https://example.com/repo.git");
+    ///
+    /// // Everything else is left to `nix flake prefetch`
+    /// assert!(
+    ///     NixPrefetcher::leave_dot_git_command(
+    ///         "git+https://example.com/repo.git?rev=5bfc8f2a1b2c3d4e5f60718293a4b5c6d7e8f901&shallow=1"
+    ///     )
+    ///     .is_none()
+    /// );
+    /// ```
+    pub fn leave_dot_git_command(url: &str) -> Option<(String, &str)> {
+        let (repo, query) = url.strip_prefix("git+")?.split_once('?')?;
+        let params: Vec<_> = query
+            .split('&')
+            .filter_map(|param| param.split_once('='))
+            .collect();
+        let param = |key| {
+            params
+                .iter()
+                .find(|(name, _)| *name == key)
+                .map(|(_, value)| *value)
+        };
+
+        param("leaveDotGit")?;
+        let rev = param("rev").or_else(|| param("ref"))?;
+
+        let mut command = format!("{NIX_PREFETCH_GIT_COMMAND} --rev {rev}");
+        if param("shallow").is_none() {
+            command.push_str(" --deepClone");
+        }
+        if param("submodules").is_some() {
+            command.push_str(" --fetch-submodules");
+        }
+        command.push_str(" --url {url}");
+
+        Some((command, repo))
+    }
 }
 
 impl Prefetch {
+    /// # Prefetch Package With Retries
+    ///
+    /// Prefetch a package as a url, retrying transient
+    /// failures up to `retries` times
+    pub fn prefetch_package_with_retries(
+        url: &str,
+        command: &str,
+        proxy: &Proxy,
+        retries: usize,
+        timeout: Duration,
+    ) -> Result<Self> {
+        Self::retry(retries, PREFETCH_RETRY_DELAY, || {
+            Self::prefetch_package(url, command, proxy, timeout)
+        })
+    }
+
+    /// # Retry
+    ///
+    /// Run a prefetch attempt, retrying up to `retries` more times
+    /// while it fails transiently, with the `delay` between attempts
+    /// doubling each time
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    ///
+    /// use bun2nix::{Error, lockfile::Prefetch};
+    ///
+    /// let mut attempts = 0;
+    /// let prefetch = Prefetch::retry(3, Duration::ZERO, || {
+    ///     attempts += 1;
+    ///     match attempts {
+    ///         1 | 2 => Err(Error::FetchingError("error: unable to download: Timeout was reached".to_owned())),
+    ///         _ => Ok(Prefetch { hash: "sha256-AAAA".to_owned(), rev: None, sha256: None }),
+    ///     }
+    /// });
+    ///
+    /// assert_eq!(prefetch.unwrap().hash, "sha256-AAAA");
+    /// assert_eq!(attempts, 3);
+    ///
+    /// // Failures which would happen every time are not retried
+    /// let mut attempts = 0;
+    /// let prefetch = Prefetch::retry(3, Duration::ZERO, || {
+    ///     attempts += 1;
+    ///     Err::<Prefetch, _>(Error::FetchingError("error: HTTP error 404".to_owned()))
+    /// });
+    ///
+    /// assert!(prefetch.is_err());
+    /// assert_eq!(attempts, 1);
+    /// ```
+    pub fn retry<T>(
+        retries: usize,
+        mut delay: Duration,
+        mut attempt: impl FnMut() -> Result<T>,
+    ) -> Result<T> {
+        for _ in 0..retries {
+            match attempt() {
+                Err(err) if Self::is_transient(&err) => {
+                    warn!("Prefetch failed, retrying in {delay:?}: {err}");
+
+                    thread::sleep(delay);
+                    delay *= 2;
+                }
+                result => return result,
+            }
+        }
+
+        attempt()
+    }
+
+    /// # Is Transient
+    ///
+    /// Check if a prefetch failure may succeed if tried again,
+    /// such as a network timeout, as opposed to a missing package
+    /// or bad git rev
+    ///
+    /// Downloads which failed with an HTTP client error are never
+    /// retried, bar timeouts and rate limiting.
+    ///
+    /// ```rust
+    /// use bun2nix::{Error, lockfile::Prefetch};
+    ///
+    /// let transient = |stderr: &str| Prefetch::is_transient(&Error::FetchingError(stderr.to_owned()));
+    ///
+    /// assert!(!transient("error: unable to download 'https://example.com/a.tgz': HTTP error 404"));
+    /// assert!(!transient("fatal: unable to access 'https://example.com/repo.git/': The requested URL returned error: 403"));
+    /// assert!(transient("error: unable to download 'https://example.com/a.tgz': HTTP error 503"));
+    /// assert!(transient("error: unable to download 'https://example.com/a.tgz': HTTP error 429"));
+    ///
+    /// // Numbers elsewhere in the output aren't taken as a status
+    /// assert!(transient("error: unable to download: Operation timed out after 30404 milliseconds"));
+    /// ```
+    pub fn is_transient(err: &Error) -> bool {
+        let Error::FetchingError(stderr) = err else {
+            return false;
+        };
+        let stderr = stderr.to_ascii_lowercase();
+
+        let is_client_error = Self::http_status(&stderr)
+            .is_some_and(|status| (400..500).contains(&status) && !matches!(status, 408 | 429));
+
+        !is_client_error
+            && !DETERMINISTIC_FAILURES
+                .iter()
+                .any(|failure| stderr.contains(failure))
+    }
+
+    /// The HTTP status a download failed with, from lowercased error output
+    fn http_status(stderr: &str) -> Option<u16> {
+        HTTP_STATUS_PREFIXES.iter().find_map(|prefix| {
+            let (_, rest) = stderr.split_once(prefix)?;
+
+            rest.get(..3)
+                .filter(|status| status.bytes().all(|byte| byte.is_ascii_digit()))?
+                .parse()
+                .ok()
+        })
+    }
+
     /// # Prefetch Package
     ///
     /// Prefetch a package as a url and calculate it's
-    /// sha256
-    pub fn prefetch_package(url: &str) -> Result<Self> {
+    /// sha256, giving up once `timeout` has passed
+    ///
+    /// The command is split on whitespace, with any `{url}` argument
+    /// replaced by the url, or the url appended if there is none. It
+    /// must print either JSON with a `hash` field, as the `--json`
+    /// output of `nix flake prefetch`, `nix store prefetch-file` and
+    /// `nix-prefetch-git` has, or the bare hash.
+    ///
+    /// Any proxy settings are set in its environment explicitly.
+    ///
+    /// ```rust
+    /// # #[cfg(unix)]
+    /// # {
+    /// use std::{fs, os::unix::fs::PermissionsExt, time::Duration};
+    ///
+    /// use bun2nix::lockfile::{Prefetch, Proxy};
+    ///
+    /// let dir = std::env::temp_dir().join("bun2nix-prefetch-command");
+    /// fs::create_dir_all(&dir).unwrap();
+    ///
+    /// // Records its arguments and proxy settings, then prints a hash
+    /// let script = dir.join("fake-prefetch");
+    /// let args = dir.join("args");
+    /// let env = dir.join("env");
+    /// fs::write(
+    ///     &script,
+    ///     format!(
+    ///         "#!/bin/sh\necho \"$@\" > {}\necho \"$https_proxy $NO_PROXY\" > {}\necho sha256-AAAA\n",
+    ///         args.display(),
+    ///         env.display(),
+    ///     ),
+    /// )
+    /// .unwrap();
+    /// fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    ///
+    /// let command = format!("{} --quiet {{url}} --json", script.display());
+    /// let proxy = Proxy {
+    ///     url: Some("http://proxy.acme.internal:3128".to_owned()),
+    ///     no_proxy: vec!["npm.acme.internal".to_owned()],
+    /// };
+    /// let prefetch = Prefetch::prefetch_package(
+    ///     "github:colinhacks/zod?ref=5bfc8f2",
+    ///     &command,
+    ///     &proxy,
+    ///     Duration::from_secs(10),
+    /// )
+    /// .unwrap();
+    ///
+    /// assert_eq!(prefetch.hash, "sha256-AAAA");
+    /// assert_eq!(
+    ///     fs::read_to_string(args).unwrap().trim(),
+    ///     "--quiet github:colinhacks/zod?ref=5bfc8f2 --json"
+    /// );
+    /// assert_eq!(
+    ///     fs::read_to_string(env).unwrap().trim(),
+    ///     "http://proxy.acme.internal:3128 npm.acme.internal"
+    /// );
+    /// # }
+    /// ```
+    pub fn prefetch_package(
+        url: &str,
+        command: &str,
+        proxy: &Proxy,
+        timeout: Duration,
+    ) -> Result<Self> {
         cfg_if::cfg_if! {
             if #[cfg(target_arch = "wasm32")] {
                 return Err(Error::UnsupportedWASMCliAction(url.to_owned()));
@@ -28,7 +412,7 @@ impl Prefetch {
 Hash was not already known for `{url}`.
 
 This must be prefetched and hashed by `bun2nix` via
-`nix flake prefetch`. While this does have some caching
+`nix flake prefetch`, or the `--prefetch-command`. While this does have some caching
 if you care about install speed, try looking for an alternative
 install for this package from npm.
 
@@ -36,21 +420,23 @@ See:
 - https://nix.dev/manual/nix/2.28/command-ref/new-cli/nix3-flake-prefetch.html
 - https://github.com/oven-sh/bun/issues/19519
 
-Disable these warnings with `RUST_LOG=error` or `RUST_LOG=off`
+Disable these warnings with `--log-level error` or `--log-level off`
             "
                     );
 
-                let cmd_res = Command::new("nix")
-                    .args([
-                        "--extra-experimental-features",
-                        "nix-command flakes",
-                        "flake",
-                        "prefetch",
-                        url,
-                        "--json",
-                    ])
-                    .output()
-                    .map_err(Error::FetchingFailed)?;
+                let mut args = command.split_whitespace();
+                let program = args.next().ok_or(Error::EmptyPrefetchCommand)?;
+                let args: Vec<_> = args.collect();
+
+                let mut command = Command::new(program);
+                if args.contains(&"{url}") {
+                    command.args(args.iter().map(|arg| if *arg == "{url}" { url } else { arg }));
+                } else {
+                    command.args(args).arg(url);
+                }
+                proxy.apply(&mut command);
+
+                let cmd_res = Self::output_with_timeout(command, timeout)?;
 
                 let stdout = str::from_utf8(&cmd_res.stdout).map_err(Error::InvalidUtf8String)?;
 
@@ -59,8 +445,379 @@ Disable these warnings with `RUST_LOG=error` or `RUST_LOG=off`
                     return Err(Error::FetchingError(stderr.to_string()));
                 }
 
-                Ok(serde_json::from_str(stdout)?)
+                Self::from_output(stdout)
+            }
+        }
+    }
+
+    /// # From Output
+    ///
+    /// Read the hash from the output of a prefetch command, which is
+    /// either JSON with a `hash` field or the bare hash
+    ///
+    /// The commit a git ref was resolved to is read from JSON too, from
+    /// `locked.rev` as `nix flake prefetch` gives it, or a `rev` field
+    /// as `nix-prefetch-git` does.
+    ///
+    /// ```rust
+    /// use bun2nix::lockfile::Prefetch;
+    ///
+    /// let json = r#"{ "hash": "sha256-AAAA", "locked": {} }"#;
+    /// assert_eq!(Prefetch::from_output(json).unwrap().hash, "sha256-AAAA");
+    ///
+    /// let json = r#"{ "hash": "sha256-AAAA", "locked": { "rev": "5bfc8f2a1b2c3d4e5f60718293a4b5c6d7e8f901" } }"#;
+    /// assert_eq!(
+    ///     Prefetch::from_output(json).unwrap().rev.as_deref(),
+    ///     Some("5bfc8f2a1b2c3d4e5f60718293a4b5c6d7e8f901")
+    /// );
+    ///
+    /// assert_eq!(Prefetch::from_output("sha256-BBBB\n").unwrap().hash, "sha256-BBBB");
+    /// ```
+    pub fn from_output(stdout: &str) -> Result<Self> {
+        if stdout.trim_start().starts_with('{') {
+            let output: Value = serde_json::from_str(stdout)?;
+            let locked_rev = output
+                .pointer("/locked/rev")
+                .and_then(Value::as_str)
+                .map(str::to_owned);
+
+            let mut prefetch: Self = serde_json::from_value(output)?;
+            prefetch.rev = prefetch.rev.or(locked_rev);
+            prefetch.hash = Self::normalize_hash(&prefetch.hash);
+
+            return Ok(prefetch.with_sha256());
+        }
+
+        match stdout.lines().map(str::trim).rfind(|line| !line.is_empty()) {
+            Some(hash) => Ok(Self::from_hash(hash.to_owned())),
+            None => Err(Error::FetchingError(
+                "The prefetch command printed no hash".to_owned(),
+            )),
+        }
+    }
+
+    /// # From Hash
+    ///
+    /// A prefetch of which only the hash is known, such as one
+    /// reused from the cache
+    pub fn from_hash(hash: String) -> Self {
+        Self {
+            hash: Self::normalize_hash(&hash),
+            ..Default::default()
+        }
+        .with_sha256()
+    }
+
+    /// # From Local File
+    ///
+    /// Hash a file on this machine as `builtins.path` does, without
+    /// running the prefetch command
+    ///
+    /// This is the sha256 of the file's NAR serialisation, which only
+    /// covers it's contents and whether it's executable, so is the same
+    /// wherever the file is.
+    ///
+    /// ```rust
+    /// use bun2nix::lockfile::Prefetch;
+    ///
+    /// let path = std::env::temp_dir().join("bun2nix-from-local-file");
+    /// std::fs::write(&path, "hello\n").unwrap();
+    ///
+    /// assert_eq!(
+    ///     Prefetch::from_local_file(&path).unwrap().hash,
+    ///     "sha256-HDfQGvQL4ugGkd48w99EN3ppmvuxfGjwgJZLL9Bx/BM="
+    /// );
+    /// ```
+    pub fn from_local_file(path: &Path) -> Result<Self> {
+        let digest = nar_sha256(path).map_err(|source| Error::ReadLocalTarballError {
+            path: path.display().to_string(),
+            source,
+        })?;
+
+        Ok(Self::from_hash(format!(
+            "sha256-{}",
+            encode_base64(&digest)
+        )))
+    }
+
+    /// # Normalize Hash
+    ///
+    /// Convert a hash to the SRI form written into `bun.nix`, whichever
+    /// form the prefetch command printed it in
+    ///
+    /// Hashes may be in nix's base-32, hex or base64, with either an
+    /// SRI `sha256-` or a `sha256:` prefix, or none at all for sha256
+    /// hashes. Anything else, such as a hash of the wrong length, is
+    /// left as it is.
+    ///
+    /// ```rust
+    /// use bun2nix::lockfile::Prefetch;
+    ///
+    /// let sri = "sha256-47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=";
+    ///
+    /// assert_eq!(Prefetch::normalize_hash(sri), sri);
+    /// assert_eq!(Prefetch::normalize_hash("0mdqa9w1p6cmli6976v4wi0sw9r4p5prkj7lzfd1877wk11c9c73"), sri);
+    /// assert_eq!(Prefetch::normalize_hash("sha256:0mdqa9w1p6cmli6976v4wi0sw9r4p5prkj7lzfd1877wk11c9c73"), sri);
+    /// assert_eq!(
+    ///     Prefetch::normalize_hash("sha256:e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"),
+    ///     sri
+    /// );
+    /// assert_eq!(Prefetch::normalize_hash("sha256:47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU="), sri);
+    /// assert_eq!(Prefetch::normalize_hash("47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU="), sri);
+    ///
+    /// // Other algorithms keep their own
+    /// assert_eq!(
+    ///     Prefetch::normalize_hash("sha1:da39a3ee5e6b4b0d3255bfef95601890afd80709"),
+    ///     "sha1-2jmj7l5rSw0yVb/vlWAYkK/YBwk="
+    /// );
+    ///
+    /// assert_eq!(Prefetch::normalize_hash("sha256-AAAA"), "sha256-AAAA");
+    /// assert_eq!(Prefetch::normalize_hash("md5:d41d8cd98f00b204e9800998ecf8427e"), "md5:d41d8cd98f00b204e9800998ecf8427e");
+    ///
+    /// // Hashes read back from the cache are normalized too
+    /// assert_eq!(
+    ///     Prefetch::from_hash("0mdqa9w1p6cmli6976v4wi0sw9r4p5prkj7lzfd1877wk11c9c73".to_owned()).hash,
+    ///     sri
+    /// );
+    /// ```
+    pub fn normalize_hash(hash: &str) -> String {
+        let (algorithm, digest) = hash.split_once(['-', ':']).unwrap_or(("sha256", hash));
+
+        let len = match algorithm {
+            "sha1" => 20,
+            "sha256" => 32,
+            "sha512" => 64,
+            _ => return hash.to_owned(),
+        };
+
+        let decoded = decode_nix_base32(digest, len)
+            .or_else(|| decode_base16(digest, len))
+            .or_else(|| decode_base64(digest).filter(|bytes| bytes.len() == len));
+
+        match decoded {
+            Some(bytes) => format!("{algorithm}-{}", encode_base64(&bytes)),
+            None => hash.to_owned(),
+        }
+    }
+
+    /// # With SHA256
+    ///
+    /// Record the hash in nix's base-32 `sha256` form as well, unless
+    /// the prefetch command already reported one
+    ///
+    /// Only sha256 hashes can be given in this form, so any other
+    /// hash is left without one.
+    ///
+    /// ```rust
+    /// use bun2nix::lockfile::Prefetch;
+    ///
+    /// let prefetch = Prefetch {
+    ///     hash: "sha256-47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=".to_owned(),
+    ///     ..Default::default()
+    /// };
+    ///
+    /// assert_eq!(
+    ///     prefetch.with_sha256().sha256.as_deref(),
+    ///     Some("0mdqa9w1p6cmli6976v4wi0sw9r4p5prkj7lzfd1877wk11c9c73")
+    /// );
+    ///
+    /// let prefetch = Prefetch {
+    ///     hash: "sha512-41Cifkg6e8TylSpdtTpeLVMqvSBEVzTttHvERD741+pnZ8ANv0004MRL43QKPDlK9cGvNp6NZWZUBlbGXYxxng==".to_owned(),
+    ///     ..Default::default()
+    /// };
+    ///
+    /// assert_eq!(prefetch.with_sha256().sha256, None);
+    /// ```
+    pub fn with_sha256(mut self) -> Self {
+        if self.sha256.is_none() {
+            self.sha256 = self
+                .hash
+                .strip_prefix("sha256-")
+                .and_then(decode_base64)
+                .filter(|digest| digest.len() == 32)
+                .map(|digest| encode_nix_base32(&digest));
+        }
+
+        self
+    }
+
+    /// # Output With Timeout
+    ///
+    /// Run a command to completion and collect it's output, killing
+    /// it if it is still running once `timeout` has passed
+    ///
+    /// ```rust
+    /// use std::{process::Command, time::Duration};
+    ///
+    /// use bun2nix::{Error, lockfile::Prefetch};
+    ///
+    /// let mut command = Command::new("sleep");
+    /// command.arg("10");
+    ///
+    /// let result = Prefetch::output_with_timeout(command, Duration::from_millis(100));
+    ///
+    /// assert!(matches!(result, Err(Error::FetchingTimedOut(_))));
+    /// ```
+    pub fn output_with_timeout(mut command: Command, timeout: Duration) -> Result<Output> {
+        let mut child = command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(Error::FetchingFailed)?;
+
+        // The pipes are drained as the command runs so it can't block on a full pipe
+        let drain = |pipe: Option<Box<dyn Read + Send>>| {
+            thread::spawn(move || {
+                let mut buf = Vec::new();
+                if let Some(mut pipe) = pipe {
+                    let _ = pipe.read_to_end(&mut buf);
+                }
+                buf
+            })
+        };
+        let stdout = drain(child.stdout.take().map(|pipe| Box::new(pipe) as _));
+        let stderr = drain(child.stderr.take().map(|pipe| Box::new(pipe) as _));
+
+        let deadline = Instant::now() + timeout;
+        let status = loop {
+            if let Some(status) = child.try_wait().map_err(Error::FetchingFailed)? {
+                break status;
             }
+
+            if Instant::now() >= deadline {
+                // Waiting after the kill reaps the child, so no zombie is left behind
+                let _ = child.kill();
+                let _ = child.wait();
+
+                return Err(Error::FetchingTimedOut(timeout));
+            }
+
+            thread::sleep(Duration::from_millis(50));
+        };
+
+        Ok(Output {
+            status,
+            stdout: stdout.join().unwrap_or_default(),
+            stderr: stderr.join().unwrap_or_default(),
+        })
+    }
+}
+
+/// Decode padded base64, or `None` if it isn't valid
+fn decode_base64(encoded: &str) -> Option<Vec<u8>> {
+    let value = |byte: u8| match byte {
+        b'A'..=b'Z' => Some(byte - b'A'),
+        b'a'..=b'z' => Some(byte - b'a' + 26),
+        b'0'..=b'9' => Some(byte - b'0' + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    };
+
+    if !encoded.len().is_multiple_of(4) {
+        return None;
+    }
+
+    let data = encoded.trim_end_matches('=');
+    if encoded.len() - data.len() > 2 {
+        return None;
+    }
+
+    let mut decoded = Vec::with_capacity(data.len() * 3 / 4);
+    let mut bits = 0u32;
+    let mut len = 0;
+
+    for byte in data.bytes() {
+        bits = bits << 6 | u32::from(value(byte)?);
+        len += 6;
+
+        if len >= 8 {
+            len -= 8;
+            decoded.push((bits >> len) as u8);
         }
     }
+
+    Some(decoded)
+}
+
+/// Encode bytes as padded base64
+fn encode_base64(bytes: &[u8]) -> String {
+    const DIGITS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+
+    for chunk in bytes.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (idx, byte)| {
+            bits | u32::from(*byte) << (16 - idx * 8)
+        });
+
+        for digit in 0..4 {
+            if digit <= chunk.len() {
+                encoded.push(char::from(
+                    DIGITS[(bits >> (18 - digit * 6) & 0x3f) as usize],
+                ));
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
+
+/// Decode exactly `len` bytes of hex, or `None` if it isn't valid
+fn decode_base16(encoded: &str, len: usize) -> Option<Vec<u8>> {
+    if encoded.len() != len * 2 {
+        return None;
+    }
+
+    (0..len)
+        .map(|idx| u8::from_str_radix(encoded.get(idx * 2..idx * 2 + 2)?, 16).ok())
+        .collect()
+}
+
+/// Decode exactly `len` bytes of nix's base-32, the inverse
+/// of `encode_nix_base32`, or `None` if it isn't valid
+fn decode_nix_base32(encoded: &str, len: usize) -> Option<Vec<u8>> {
+    if encoded.len() != (len * 8).div_ceil(5) {
+        return None;
+    }
+
+    let mut bytes = vec![0u8; len];
+
+    for (digit, char) in encoded.bytes().rev().enumerate() {
+        let value = NIX_BASE32_DIGITS.iter().position(|c| *c == char)?;
+        let bit = digit * 5;
+        let (idx, shift) = (bit / 8, bit % 8);
+        let word = (value as u16) << shift;
+
+        bytes[idx] |= word as u8;
+        match bytes.get_mut(idx + 1) {
+            Some(next) => *next |= (word >> 8) as u8,
+            // Bits past the end of the hash must be unset
+            None if word >> 8 != 0 => return None,
+            None => (),
+        }
+    }
+
+    Some(bytes)
+}
+
+/// Encode bytes in nix's base-32, which reads the bytes from
+/// the end, as `nix hash convert --to nix32` does
+fn encode_nix_base32(bytes: &[u8]) -> String {
+    let len = (bytes.len() * 8).div_ceil(5);
+
+    (0..len)
+        .rev()
+        .map(|digit| {
+            let bit = digit * 5;
+            let (idx, shift) = (bit / 8, bit % 8);
+
+            let next = bytes.get(idx + 1).copied().unwrap_or_default();
+            let word = u16::from(bytes[idx]) | u16::from(next) << 8;
+
+            char::from(NIX_BASE32_DIGITS[usize::from(word >> shift & 0x1f)])
+        })
+        .collect()
 }
@@ -0,0 +1,179 @@
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::{Path, PathBuf},
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::{Options, error::Result};
+
+/// The version of the on-disk cache format
+///
+/// Bump this whenever the format of stored hashes changes so
+/// that old entries are discarded rather than reused.
+pub const PREFETCH_CACHE_VERSION: u32 = 1;
+
+/// # Prefetch Cache File
+///
+/// The on-disk representation of the prefetch cache
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct PrefetchCacheFile {
+    version: u32,
+    hashes: BTreeMap<String, String>,
+}
+
+/// # Prefetch Cache
+///
+/// A persistent map of prefetch urls to their resolved hashes,
+/// used to avoid re-running `nix flake prefetch` for packages
+/// which have not changed since the last run.
+///
+/// Prefetch urls always contain the git ref for git based
+/// packages, so a change of rev is a change of key.
+///
+/// ```rust
+/// use bun2nix::lockfile::PrefetchCache;
+///
+/// let path = std::env::temp_dir().join("bun2nix-prefetch-cache-doctest.json");
+/// let _ = std::fs::remove_file(&path);
+///
+/// let cache = PrefetchCache::open(path.clone());
+/// assert_eq!(cache.get("github:oven-sh/bun?ref=main"), None);
+///
+/// cache.insert("github:oven-sh/bun?ref=main", "sha256-AAAA");
+/// cache.save();
+///
+/// // A warm cache answers without prefetching
+/// let warm = PrefetchCache::open(path);
+/// assert_eq!(
+///     warm.get("github:oven-sh/bun?ref=main").as_deref(),
+///     Some("sha256-AAAA")
+/// );
+/// ```
+#[derive(Debug, Default)]
+pub struct PrefetchCache {
+    path: Option<PathBuf>,
+    hashes: Mutex<BTreeMap<String, String>>,
+    dirty: AtomicBool,
+}
+
+impl PrefetchCache {
+    /// # Load Prefetch Cache
+    ///
+    /// Load the prefetch cache from the user's cache directory,
+    /// or produce a disabled cache unless `options.prefetch_cache`
+    /// is set and a cache directory can be found
+    pub fn load(options: &Options) -> Self {
+        if !options.prefetch_cache || cfg!(target_arch = "wasm32") {
+            return Self::default();
+        }
+
+        match Self::default_path() {
+            Some(path) => Self::open(path),
+            None => Self::default(),
+        }
+    }
+
+    /// # Open Prefetch Cache
+    ///
+    /// Open the prefetch cache stored at a given path, starting
+    /// empty if it does not exist or is from an incompatible version
+    pub fn open(path: PathBuf) -> Self {
+        let hashes = Self::read(&path).unwrap_or_default();
+
+        Self {
+            path: Some(path),
+            hashes: Mutex::new(hashes),
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    /// # Default Cache Path
+    ///
+    /// The default location of the cache, `$XDG_CACHE_HOME/bun2nix/prefetch.json`
+    /// falling back to `$HOME/.cache/bun2nix/prefetch.json`
+    pub fn default_path() -> Option<PathBuf> {
+        let cache_home = env::var_os("XDG_CACHE_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))?;
+
+        Some(cache_home.join("bun2nix").join("prefetch.json"))
+    }
+
+    /// # Get Cached Hash
+    ///
+    /// Look up the hash for a previously prefetched url
+    pub fn get(&self, url: &str) -> Option<String> {
+        self.path.as_ref()?;
+
+        self.lock().get(url).cloned()
+    }
+
+    /// # Insert Hash
+    ///
+    /// Record the hash for a newly prefetched url
+    pub fn insert(&self, url: &str, hash: &str) {
+        if self.path.is_none() {
+            return;
+        }
+
+        self.lock().insert(url.to_owned(), hash.to_owned());
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// # Save Prefetch Cache
+    ///
+    /// Write the cache back to disk if anything new was added
+    ///
+    /// Failing to write the cache is not fatal, and instead
+    /// only produces a warning.
+    pub fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+
+        if !self.dirty.load(Ordering::Relaxed) {
+            return;
+        }
+
+        if let Err(err) = self.write(path) {
+            warn!(
+                "Failed to write the prefetch cache to `{}`: {err}",
+                path.display()
+            );
+        }
+    }
+
+    fn read(path: &Path) -> Option<BTreeMap<String, String>> {
+        let contents = fs::read_to_string(path).ok()?;
+        let file: PrefetchCacheFile = serde_json::from_str(&contents).ok()?;
+
+        (file.version == PREFETCH_CACHE_VERSION).then_some(file.hashes)
+    }
+
+    fn write(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let file = PrefetchCacheFile {
+            version: PREFETCH_CACHE_VERSION,
+            hashes: self.lock().clone(),
+        };
+
+        fs::write(path, serde_json::to_string_pretty(&file)?)?;
+
+        Ok(())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<String, String>> {
+        self.hashes.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
@@ -0,0 +1,236 @@
+use std::{collections::BTreeMap, fs, path::Path};
+
+use serde_json::Value;
+
+use crate::{
+    error::{Error, Result},
+    options::DEFAULT_GITHUB_DOMAIN,
+    package::Fetcher,
+};
+
+/// # Previous Output
+///
+/// The hashes of a previously generated `bun.nix`, or of the JSON
+/// package list written alongside it, keyed by prefetch url
+///
+/// Prefetch urls contain the url and rev of each package, so only
+/// packages which have changed since the previous output are missing.
+///
+/// ```rust
+/// use bun2nix::lockfile::PreviousOutput;
+///
+/// let previous = PreviousOutput::parse(r#"
+///   "zod" = fetchFromGitHub {
+///     owner = "colinhacks";
+///     repo = "zod";
+///     rev = "5bfc8f2";
+///     hash = "sha256-AAAA";
+///   };
+///   "tiny" = builtins.fetchTarball {
+///     url = "https://example.com/tiny-1.0.0.tgz";
+///     sha256 = "sha256-BBBB";
+///   };
+/// "#).unwrap();
+///
+/// assert_eq!(
+///     previous.get("github:colinhacks/zod?ref=5bfc8f2").as_deref(),
+///     Some("sha256-AAAA")
+/// );
+/// assert_eq!(
+///     previous.get("tarball+https://example.com/tiny-1.0.0.tgz").as_deref(),
+///     Some("sha256-BBBB")
+/// );
+/// assert_eq!(previous.get("github:colinhacks/zod?ref=main"), None);
+/// ```
+///
+/// Given as `options.previous_output`, only changed packages are prefetched:
+///
+/// ```rust
+/// use bun2nix::{Options, dry_run_lockfile};
+///
+/// let previous = std::env::temp_dir().join("bun2nix-previous-output-doctest.nix");
+/// std::fs::write(&previous, r#"
+///   "zod" = fetchFromGitHub {
+///     owner = "colinhacks";
+///     repo = "zod";
+///     rev = "5bfc8f2";
+///     hash = "sha256-AAAA";
+///   };
+///   "bun" = fetchFromGitHub {
+///     owner = "oven-sh";
+///     repo = "bun";
+///     rev = "7ebfdf9";
+///     hash = "sha256-BBBB";
+///   };
+/// "#).unwrap();
+///
+/// // `bun` has moved on to a new commit since the previous output
+/// let lockfile = r#"{
+///   "lockfileVersion": 1,
+///   "packages": {
+///     "zod": ["zod@github:colinhacks/zod#5bfc8f2", {}, "colinhacks-zod-5bfc8f2"],
+///     "bun": ["bun@github:oven-sh/bun#a1b2c3d", {}, "oven-sh-bun-a1b2c3d"],
+///   }
+/// }"#;
+///
+/// let options = Options {
+///     dry_run: true,
+///     previous_output: Some(previous),
+///     ..Default::default()
+/// };
+/// let summary = dry_run_lockfile(lockfile.to_owned(), options).unwrap();
+///
+/// assert_eq!(summary.prefetch_urls, ["github:oven-sh/bun?ref=a1b2c3d"]);
+/// ```
+#[derive(Debug, Default)]
+pub struct PreviousOutput {
+    hashes: BTreeMap<String, String>,
+}
+
+impl PreviousOutput {
+    /// # Load Previous Output
+    ///
+    /// Read the hashes from a previously generated file
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path).map_err(Error::ReadPreviousOutputError)?;
+
+        Self::parse(&contents)
+    }
+
+    /// # Parse Previous Output
+    ///
+    /// Read the hashes from the contents of either a `bun.nix`
+    /// or a JSON package list
+    pub fn parse(contents: &str) -> Result<Self> {
+        let fetchers = if contents.trim_start().starts_with('[') {
+            Self::parse_json(contents)?
+        } else {
+            Self::parse_nix(contents)
+        };
+
+        let mut previous = Self::default();
+        previous.extend(fetchers);
+
+        Ok(previous)
+    }
+
+    /// # Extend Previous Output
+    ///
+    /// Add the hashes of the given fetchers, as if they were
+    /// in the previous output
+    pub fn extend(&mut self, fetchers: impl IntoIterator<Item = Fetcher>) {
+        self.hashes
+            .extend(fetchers.into_iter().filter_map(|fetcher| {
+                let url = fetcher.prefetch_url()?;
+                let hash = fetcher.source_hash()?;
+
+                Some((url, hash))
+            }));
+    }
+
+    /// # Get Previous Hash
+    ///
+    /// Get the previous hash for a prefetch url, if there was one
+    pub fn get(&self, url: &str) -> Option<String> {
+        self.hashes.get(url).cloned()
+    }
+
+    fn parse_json(contents: &str) -> Result<Vec<Fetcher>> {
+        let packages: Vec<Value> = serde_json::from_str(contents)?;
+
+        packages
+            .into_iter()
+            .filter_map(|mut package| package.get_mut("fetcher").map(Value::take))
+            .map(|fetcher| Ok(serde_json::from_value(fetcher)?))
+            .collect()
+    }
+
+    /// Collect the attributes of every prefetched fetcher call, which
+    /// are written one per line until the call's closing brace
+    fn parse_nix(contents: &str) -> Vec<Fetcher> {
+        let mut fetchers = Vec::new();
+        let mut current: Option<(&str, BTreeMap<&str, &str>)> = None;
+
+        for line in contents.lines() {
+            let line = line.trim();
+
+            if let Some((kind, attrs)) = current.as_mut() {
+                if line.starts_with('}') {
+                    fetchers.extend(Self::fetcher_from_attrs(kind, attrs));
+                    current = None;
+                } else if let Some((key, value)) = line.split_once(" = ") {
+                    let value = value.trim_end_matches(';');
+
+                    attrs.insert(key, value.trim_matches('"'));
+                }
+
+                continue;
+            }
+
+            current = [
+                "fetchgit",
+                "fetchFromGitHub",
+                "fetchFromGitLab",
+                "fetchFromBitbucket",
+                "builtins.fetchTarball",
+                "fetchzip",
+            ]
+            .into_iter()
+            .find(|kind| line.ends_with(&format!("= {kind} {{")))
+            .map(|kind| (kind, BTreeMap::new()));
+        }
+
+        fetchers
+    }
+
+    fn fetcher_from_attrs(kind: &str, attrs: &BTreeMap<&str, &str>) -> Option<Fetcher> {
+        let attr = |key| attrs.get(key).map(|value| (*value).to_owned());
+
+        let fetcher = match kind {
+            "fetchgit" => Fetcher::FetchGit {
+                url: attr("url")?,
+                rev: attr("rev")?,
+                hash: attr("hash")?,
+                fetch_submodules: attr("fetchSubmodules")? == "true",
+                deep_clone: attr("deepClone")? == "true",
+                all_refs: attr("allRefs").is_some_and(|all_refs| all_refs == "true"),
+                leave_dot_git: attr("leaveDotGit").is_some_and(|leave| leave == "true"),
+                dir: None,
+                name: attr("name"),
+            },
+            "fetchFromGitHub" => Fetcher::FetchGitHub {
+                owner: attr("owner")?,
+                repo: attr("repo")?,
+                rev: attr("rev")?,
+                hash: attr("hash")?,
+                domain: attr("githubBase").unwrap_or_else(|| DEFAULT_GITHUB_DOMAIN.to_owned()),
+                dir: None,
+                name: attr("name"),
+            },
+            "fetchFromGitLab" => Fetcher::FetchFromGitLab {
+                owner: attr("owner")?,
+                repo: attr("repo")?,
+                rev: attr("rev")?,
+                hash: attr("hash")?,
+                domain: attr("domain")?,
+            },
+            "fetchFromBitbucket" => Fetcher::FetchFromBitbucket {
+                owner: attr("owner")?,
+                repo: attr("repo")?,
+                rev: attr("rev")?,
+                hash: attr("hash")?,
+            },
+            "builtins.fetchTarball" => Fetcher::FetchTarball {
+                url: attr("url")?,
+                hash: attr("sha256")?,
+            },
+            "fetchzip" => Fetcher::FetchZip {
+                url: attr("url")?,
+                hash: attr("hash")?,
+            },
+            _ => return None,
+        };
+
+        Some(fetcher)
+    }
+}
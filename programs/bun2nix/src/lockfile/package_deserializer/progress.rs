@@ -0,0 +1,79 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// # Progress
+///
+/// Reports each package as it is prefetched to stderr, as
+/// `[n/total] prefetching <package>`, so a long prefetch
+/// doesn't look like a hang
+///
+/// Packages resolved from the previous output or the cache are
+/// counted, but not reported. Nothing is written to stdout, so
+/// the generated expression is unaffected.
+///
+/// ```rust
+/// use bun2nix::lockfile::Progress;
+///
+/// let progress = Progress::new(false);
+/// progress.start(3);
+///
+/// assert_eq!(progress.resolve(), "[1/3]");
+/// assert_eq!(progress.resolve(), "[2/3]");
+/// ```
+#[derive(Debug, Default)]
+pub struct Progress {
+    /// Whether to write anything at all
+    enabled: bool,
+
+    /// The number of packages which may need prefetching
+    total: AtomicUsize,
+
+    /// The number of those resolved so far
+    resolved: AtomicUsize,
+}
+
+impl Progress {
+    /// # New Progress
+    ///
+    /// Create a progress reporter, which is silent unless enabled
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            ..Default::default()
+        }
+    }
+
+    /// # Start Progress
+    ///
+    /// Begin counting towards the given number of packages
+    pub fn start(&self, total: usize) {
+        self.total.store(total, Ordering::Relaxed);
+        self.resolved.store(0, Ordering::Relaxed);
+    }
+
+    /// # Add To Progress
+    ///
+    /// Count more packages towards the total, for when they are
+    /// only found as the lockfile is read
+    pub fn add(&self, count: usize) {
+        self.total.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// # Resolve
+    ///
+    /// Count another package as resolved, returning its `[n/total]` position
+    pub fn resolve(&self) -> String {
+        let resolved = self.resolved.fetch_add(1, Ordering::Relaxed) + 1;
+        let total = self.total.load(Ordering::Relaxed).max(resolved);
+
+        format!("[{resolved}/{total}]")
+    }
+
+    /// # Report Prefetch
+    ///
+    /// Write that a package is being prefetched, at the given position
+    pub fn prefetching(&self, position: &str, package: &str) {
+        if self.enabled {
+            eprintln!("{position} prefetching {package}");
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use std::{env, process::Command};
+
+use crate::Options;
+
+/// The variables a proxy url is given to prefetch commands in, as
+/// tools differ in which case they read
+const PROXY_VARIABLES: &[&str] = &["https_proxy", "HTTPS_PROXY", "http_proxy", "HTTP_PROXY"];
+
+/// The variables the hosts which bypass the proxy are given in
+const NO_PROXY_VARIABLES: &[&str] = &["no_proxy", "NO_PROXY"];
+
+/// # Proxy
+///
+/// The proxy settings passed explicitly to each prefetch command,
+/// as the environment it would otherwise inherit them from may be
+/// stripped, such as in the nix sandbox
+///
+/// ```rust
+/// use std::process::Command;
+///
+/// use bun2nix::{Options, lockfile::Proxy};
+///
+/// let options = Options {
+///     proxy: Some("http://proxy.acme.internal:3128".to_owned()),
+///     no_proxy: vec!["npm.acme.internal".to_owned(), ".corp".to_owned()],
+///     ..Default::default()
+/// };
+/// let proxy = Proxy::from_options(&options);
+///
+/// let mut command = Command::new("env");
+/// proxy.apply(&mut command);
+///
+/// let envs: Vec<_> = command
+///     .get_envs()
+///     .map(|(key, value)| (key.to_str().unwrap(), value.unwrap().to_str().unwrap()))
+///     .collect();
+///
+/// assert!(envs.contains(&("https_proxy", "http://proxy.acme.internal:3128")));
+/// assert!(envs.contains(&("HTTP_PROXY", "http://proxy.acme.internal:3128")));
+/// assert!(envs.contains(&("no_proxy", "npm.acme.internal,.corp")));
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Proxy {
+    /// The proxy url, if any
+    pub url: Option<String>,
+
+    /// The hosts which bypass the proxy
+    pub no_proxy: Vec<String>,
+}
+
+impl Proxy {
+    /// # Proxy From Options
+    ///
+    /// Read the proxy settings from the options, falling back
+    /// to those in the environment of this process
+    pub fn from_options(options: &Options) -> Self {
+        let url = options
+            .proxy
+            .clone()
+            .or_else(|| Self::from_env(PROXY_VARIABLES));
+
+        let no_proxy = if options.no_proxy.is_empty() {
+            Self::from_env(NO_PROXY_VARIABLES)
+                .map(|hosts| {
+                    hosts
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|host| !host.is_empty())
+                        .map(str::to_owned)
+                        .collect()
+                })
+                .unwrap_or_default()
+        } else {
+            options.no_proxy.clone()
+        };
+
+        Self { url, no_proxy }
+    }
+
+    /// # Apply Proxy
+    ///
+    /// Set the proxy variables in a command's environment
+    pub fn apply(&self, command: &mut Command) {
+        if let Some(url) = &self.url {
+            for variable in PROXY_VARIABLES {
+                command.env(variable, url);
+            }
+        }
+
+        if !self.no_proxy.is_empty() {
+            let no_proxy = self.no_proxy.join(",");
+
+            for variable in NO_PROXY_VARIABLES {
+                command.env(variable, &no_proxy);
+            }
+        }
+    }
+
+    /// The first of the given variables which is set and non-empty
+    fn from_env(variables: &[&str]) -> Option<String> {
+        variables
+            .iter()
+            .filter_map(|variable| env::var(variable).ok())
+            .find(|value| !value.is_empty())
+    }
+}
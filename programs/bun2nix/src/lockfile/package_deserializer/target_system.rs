@@ -0,0 +1,98 @@
+use std::str::FromStr;
+
+use serde_json::Value;
+
+use crate::error::Error;
+
+/// # Target System
+///
+/// A nix system double, such as `x86_64-linux`, translated into
+/// the names npm uses for a package's `cpu` and `os` constraints
+///
+/// ```rust
+/// use bun2nix::lockfile::TargetSystem;
+/// use serde_json::json;
+///
+/// let system: TargetSystem = "x86_64-linux".parse().unwrap();
+///
+/// assert!(system.accepts(&json!({ "os": "linux", "cpu": "x64" })));
+/// assert!(system.accepts(&json!({ "os": ["linux", "darwin"] })));
+/// assert!(system.accepts(&json!({ "os": "!win32" })));
+/// assert!(!system.accepts(&json!({ "os": "darwin", "cpu": "arm64" })));
+/// assert!(!system.accepts(&json!({ "cpu": ["!x64"] })));
+///
+/// // Packages without platform metadata are always accepted
+/// assert!(system.accepts(&json!({})));
+///
+/// assert!("x86_64-plan9".parse::<TargetSystem>().is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TargetSystem {
+    /// The npm `cpu` name of the system's architecture
+    pub cpu: &'static str,
+
+    /// The npm `os` name of the system's kernel
+    pub os: &'static str,
+}
+
+impl TargetSystem {
+    /// # Accepts
+    ///
+    /// Check if a package's metadata allows it to be installed on this system
+    pub fn accepts(&self, meta: &Value) -> bool {
+        Self::allows(meta.get("cpu"), self.cpu) && Self::allows(meta.get("os"), self.os)
+    }
+
+    fn allows(constraint: Option<&Value>, value: &str) -> bool {
+        let entries: Vec<&str> = match constraint {
+            Some(Value::String(entry)) => vec![entry],
+            Some(Value::Array(entries)) => entries.iter().filter_map(Value::as_str).collect(),
+            _ => return true,
+        };
+
+        if entries
+            .iter()
+            .any(|entry| entry.strip_prefix('!') == Some(value))
+        {
+            return false;
+        }
+
+        let mut allowed = entries.iter().filter(|entry| !entry.starts_with('!'));
+
+        allowed.clone().next().is_none() || allowed.any(|entry| *entry == value || *entry == "any")
+    }
+}
+
+impl FromStr for TargetSystem {
+    type Err = Error;
+
+    fn from_str(system: &str) -> std::result::Result<Self, Self::Err> {
+        let unknown = || Error::UnknownTargetSystem(system.to_owned());
+
+        let (arch, kernel) = system.split_once('-').ok_or_else(unknown)?;
+
+        let cpu = match arch {
+            "x86_64" => "x64",
+            "aarch64" => "arm64",
+            "i686" => "ia32",
+            "armv6l" | "armv7l" => "arm",
+            "powerpc64le" => "ppc64",
+            "riscv64" => "riscv64",
+            "s390x" => "s390x",
+            "loongarch64" => "loong64",
+            _ => return Err(unknown()),
+        };
+
+        let os = match kernel {
+            "linux" => "linux",
+            "darwin" => "darwin",
+            "freebsd" => "freebsd",
+            "openbsd" => "openbsd",
+            "netbsd" => "netbsd",
+            "windows" => "win32",
+            _ => return Err(unknown()),
+        };
+
+        Ok(Self { cpu, os })
+    }
+}
@@ -0,0 +1,355 @@
+//! This module holds the serde visitors which read a bun lockfile in a single pass, either
+//! collecting it's packages or deserializing each of them as soon as it is read
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt,
+    sync::mpsc,
+    thread,
+};
+
+use serde::de::{self, DeserializeSeed, IgnoredAny, MapAccess, Visitor};
+
+use crate::{Options, Package, error::Error, error::Result};
+
+use super::{DeserializerContext, Lockfile, PackageDeserializer, Prefetcher, Values, Workspace};
+
+/// The results of the packages deserialized so far, keyed by lockfile key
+pub(super) type Results = Vec<(String, Result<Option<Package>>)>;
+
+/// # Read Packages
+///
+/// How the `packages` of a lockfile are read, once every field
+/// written before them has been
+pub(super) trait ReadPackages {
+    /// Read the `packages` map, returning the entries to keep in the lockfile
+    fn read_packages<'de, A: MapAccess<'de>>(
+        &mut self,
+        header: &Lockfile,
+        map: &mut A,
+        error: &mut Option<Error>,
+    ) -> std::result::Result<BTreeMap<String, Values>, A::Error>;
+
+    /// Whether a field the packages depend on may come after them
+    fn allows_late_header(&mut self) -> bool {
+        true
+    }
+}
+
+/// # Collect Packages
+///
+/// Read every entry of `packages` into the lockfile, as they are
+pub(super) struct CollectPackages;
+
+impl ReadPackages for CollectPackages {
+    fn read_packages<'de, A: MapAccess<'de>>(
+        &mut self,
+        _header: &Lockfile,
+        map: &mut A,
+        _error: &mut Option<Error>,
+    ) -> std::result::Result<BTreeMap<String, Values>, A::Error> {
+        map.next_value()
+    }
+}
+
+/// # Stream Packages
+///
+/// Deserialize each entry of `packages` into a `Package` as soon as it
+/// is read, handing it to a pool of workers, so that only the entries
+/// still being deserialized are ever held at once
+///
+/// Entries which depend on the rest of the lockfile, i.e. those for an
+/// overridden package, are kept in the lockfile instead, to be
+/// deserialized once it has been read. Every other entry is left in the
+/// lockfile as an empty tuple, so passes which only need to know which
+/// entries exist still can.
+pub(super) struct StreamPackages<'a> {
+    /// The options given to the conversion
+    pub options: &'a Options,
+
+    /// The prefetcher for packages which must be prefetched
+    pub prefetcher: &'a dyn Prefetcher,
+
+    /// The number of workers to deserialize with
+    pub jobs: usize,
+
+    /// The context the packages were deserialized with, once they're reached
+    pub context: Option<DeserializerContext<'a>>,
+
+    /// The deserialized entries, in the order they finished
+    pub results: Results,
+
+    /// Whether a field the packages depend on was found after them
+    pub out_of_order: bool,
+}
+
+impl<'a> StreamPackages<'a> {
+    /// # New Stream Packages
+    ///
+    /// Stream packages with the given options and prefetcher
+    pub fn new(options: &'a Options, prefetcher: &'a dyn Prefetcher, jobs: usize) -> Self {
+        Self {
+            options,
+            prefetcher,
+            jobs,
+            context: None,
+            results: Vec::new(),
+            out_of_order: false,
+        }
+    }
+}
+
+impl ReadPackages for StreamPackages<'_> {
+    fn read_packages<'de, A: MapAccess<'de>>(
+        &mut self,
+        header: &Lockfile,
+        map: &mut A,
+        error: &mut Option<Error>,
+    ) -> std::result::Result<BTreeMap<String, Values>, A::Error> {
+        let context = DeserializerContext::new(
+            self.options,
+            header.patched_dependencies.clone(),
+            header.trusted_dependencies.clone(),
+        )
+        .map_err(|err| stash(error, err))?
+        .with_workspaces(&header.workspaces)
+        .with_prefetcher(self.prefetcher);
+        context.progress.start(0);
+
+        let context = self.context.insert(context);
+        let options = self.options;
+
+        // Which entries are for an overridden package can be told from
+        // their key, as the overrides are written before the packages
+        let is_deferred = |key: &str| {
+            header
+                .overrides
+                .contains_key(Lockfile::key_package_name(key))
+        };
+        let is_kept = |key: &str| Lockfile::is_kept(key, &options.only, &options.exclude);
+
+        // Threads can't be spawned from wasm
+        if self.jobs == 1 || cfg!(target_arch = "wasm32") {
+            let results = &mut self.results;
+            let mut failed = false;
+
+            return map.next_value_seed(EntrySeed {
+                is_deferred: &is_deferred,
+                dispatch: &mut |key, values| {
+                    // Don't prefetch the rest once the outcome is decided
+                    if failed || !is_kept(&key) {
+                        return;
+                    }
+                    if PackageDeserializer::is_prefetched(&values) {
+                        context.progress.add(1);
+                    }
+
+                    let pkg =
+                        PackageDeserializer::deserialize_package(key.clone(), values, context);
+                    failed = pkg.is_err() && !options.collect_all_errors;
+                    results.push((key, pkg));
+                },
+            });
+        }
+
+        let context = &*context;
+        let jobs = self.jobs;
+
+        let (packages, results) = thread::scope(|scope| {
+            // Bounded, so the entries are never read far ahead of the workers
+            let (sender, receiver) = mpsc::sync_channel(jobs * 2);
+            let workers = scope
+                .spawn(move || Lockfile::deserialize_entries(receiver.into_iter(), context, jobs));
+
+            let packages = map.next_value_seed(EntrySeed {
+                is_deferred: &is_deferred,
+                dispatch: &mut |key, values| {
+                    if !is_kept(&key) {
+                        return;
+                    }
+                    if PackageDeserializer::is_prefetched(&values) {
+                        context.progress.add(1);
+                    }
+
+                    // The workers only stop early if this thread has panicked
+                    let _ = sender.send((key, values));
+                },
+            });
+            drop(sender);
+
+            let results = workers
+                .join()
+                .unwrap_or_else(|err| std::panic::resume_unwind(err));
+
+            (packages, results)
+        });
+
+        self.results = results;
+
+        packages
+    }
+
+    fn allows_late_header(&mut self) -> bool {
+        self.out_of_order = true;
+
+        false
+    }
+}
+
+/// # Entry Seed
+///
+/// Reads the `packages` map entry by entry, handing each to `dispatch`
+/// unless it is deferred until the rest of the lockfile has been read
+struct EntrySeed<'s> {
+    /// Whether an entry is kept in the lockfile, rather than dispatched
+    is_deferred: &'s dyn Fn(&str) -> bool,
+
+    /// Called with each entry which isn't deferred
+    dispatch: &'s mut dyn FnMut(String, Values),
+}
+
+impl<'de> DeserializeSeed<'de> for EntrySeed<'_> {
+    type Value = BTreeMap<String, Values>;
+
+    fn deserialize<D>(self, deserializer: D) -> std::result::Result<Self::Value, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for EntrySeed<'_> {
+    type Value = BTreeMap<String, Values>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a map of lockfile keys to package tuples")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(
+        self,
+        mut map: A,
+    ) -> std::result::Result<Self::Value, A::Error> {
+        let mut packages = BTreeMap::new();
+
+        while let Some(key) = map.next_key::<String>()? {
+            let values: Values = map.next_value()?;
+
+            if (self.is_deferred)(&key) {
+                packages.insert(key, values);
+            } else {
+                packages.insert(key.clone(), Values::new());
+                (self.dispatch)(key, values);
+            }
+        }
+
+        Ok(packages)
+    }
+}
+
+/// # Lockfile Seed
+///
+/// Reads a bun lockfile field by field, checking it's `lockfileVersion`
+/// as soon as it is read, and reading it's `packages` as `R` does
+///
+/// Errors from outside serde, such as an unsupported version, are kept
+/// in `error`, so they can be returned as they are.
+pub(super) struct LockfileSeed<'r, R> {
+    /// How the packages are read
+    pub packages: &'r mut R,
+
+    /// The error which stopped the lockfile being read, if it wasn't serde's
+    pub error: &'r mut Option<Error>,
+}
+
+impl<'de, R: ReadPackages> DeserializeSeed<'de> for LockfileSeed<'_, R> {
+    type Value = Lockfile;
+
+    fn deserialize<D>(self, deserializer: D) -> std::result::Result<Self::Value, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, R: ReadPackages> Visitor<'de> for LockfileSeed<'_, R> {
+    type Value = Lockfile;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a bun lockfile")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(
+        self,
+        mut map: A,
+    ) -> std::result::Result<Self::Value, A::Error> {
+        let mut lockfile = Lockfile {
+            lockfile_version: 0,
+            workspaces: HashMap::new(),
+            packages: BTreeMap::new(),
+            patched_dependencies: BTreeMap::new(),
+            trusted_dependencies: BTreeSet::new(),
+            catalog: HashMap::new(),
+            catalogs: HashMap::new(),
+            overrides: HashMap::new(),
+        };
+        let mut lockfile_version = None;
+        let mut has_packages = false;
+
+        while let Some(field) = map.next_key::<String>()? {
+            // The packages are read with what's written before them
+            let is_header = matches!(
+                field.as_str(),
+                "workspaces" | "patchedDependencies" | "trustedDependencies" | "overrides"
+            );
+            if is_header && has_packages && !self.packages.allows_late_header() {
+                return Err(de::Error::custom(format!(
+                    "`{field}` must come before `packages`"
+                )));
+            }
+
+            match field.as_str() {
+                "lockfileVersion" => {
+                    let version: u64 = map.next_value()?;
+                    Lockfile::check_version(version).map_err(|err| stash(self.error, err))?;
+
+                    lockfile_version = u8::try_from(version).ok();
+                }
+                "workspaces" => {
+                    lockfile.workspaces = map.next_value::<HashMap<String, Workspace>>()?;
+                }
+                "packages" => {
+                    lockfile.packages = self
+                        .packages
+                        .read_packages(&lockfile, &mut map, self.error)?;
+                    has_packages = true;
+                }
+                "patchedDependencies" => lockfile.patched_dependencies = map.next_value()?,
+                "trustedDependencies" => lockfile.trusted_dependencies = map.next_value()?,
+                "catalog" => lockfile.catalog = map.next_value()?,
+                "catalogs" => lockfile.catalogs = map.next_value()?,
+                "overrides" => lockfile.overrides = map.next_value()?,
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+
+        lockfile.lockfile_version =
+            lockfile_version.ok_or_else(|| de::Error::missing_field("lockfileVersion"))?;
+        if !has_packages {
+            return Err(de::Error::missing_field("packages"));
+        }
+
+        Ok(lockfile)
+    }
+}
+
+/// Keep an error from outside serde, to be returned once serde gives up
+fn stash<E: de::Error>(slot: &mut Option<Error>, err: Error) -> E {
+    let message = err.to_string();
+    *slot = Some(err);
+
+    E::custom(message)
+}
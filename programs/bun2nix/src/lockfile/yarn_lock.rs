@@ -0,0 +1,216 @@
+use std::collections::BTreeMap;
+
+use serde_json::json;
+
+use crate::error::{Error, Result};
+
+use super::{Values, strip_name_prefix};
+
+/// The registries which `yarn` writes npm tarball urls for, both of which
+/// serve the same packages as the default registry
+const YARN_DEFAULT_REGISTRIES: &[&str] = &[
+    "https://registry.yarnpkg.com/",
+    "https://registry.npmjs.org/",
+];
+
+/// # Is Yarn Lock
+///
+/// Check if the given lockfile contents are a `yarn.lock`, which unlike a
+/// bun lockfile always begins with a `#` comment
+///
+/// ```rust
+/// use bun2nix::lockfile::is_yarn_lock;
+///
+/// assert!(is_yarn_lock("# THIS IS AN AUTOGENERATED FILE. DO NOT EDIT THIS FILE DIRECTLY.\n# yarn lockfile v1\n"));
+/// assert!(!is_yarn_lock(r#"{ "lockfileVersion": 1 }"#));
+/// ```
+pub fn is_yarn_lock(contents: &str) -> bool {
+    contents.trim_start().starts_with('#')
+}
+
+/// # Parse Yarn Lock
+///
+/// Parse the entries of a Yarn v1 `yarn.lock` into the equivalent bun
+/// lockfile package tuples, keyed by package name, or by name and
+/// version for any further versions of the same package
+///
+/// Only npm, git, github and tarball resolutions are supported.
+///
+/// ```rust
+/// use bun2nix::lockfile::parse_yarn_lock;
+///
+/// let yarn_lock = r#"# yarn lockfile v1
+///
+/// "@types/node@^22.0.0", "@types/node@^22.13.0":
+///   version "22.13.5"
+///   resolved "https://registry.yarnpkg.com/@types/node/-/node-22.13.5.tgz#a1b2c3"
+///   integrity sha512-+lTU0PxZXn0Dr1NBtC7Y8cR21AJr87dLLU953CWA6pMxxv/UDc7jYAY90upcrie1nRcD6XNG5HOYEDtgW5TxAg==
+///   dependencies:
+///     undici-types "~6.20.0"
+///
+/// zod@colinhacks/zod#main:
+///   version "3.24.2"
+///   resolved "https://codeload.github.com/colinhacks/zod/tar.gz/5bfc8f2"
+///
+/// tiny@https://example.com/tiny-1.0.0.tgz:
+///   version "1.0.0"
+///   resolved "https://example.com/tiny-1.0.0.tgz#a1b2c3"
+/// "#;
+///
+/// let packages = parse_yarn_lock(yarn_lock).unwrap();
+///
+/// assert_eq!(packages["@types/node"][0], "@types/node@22.13.5");
+/// assert_eq!(packages["@types/node"][1], "");
+/// assert_eq!(packages["zod"][0], "zod@github:colinhacks/zod#5bfc8f2");
+/// assert_eq!(packages["tiny"][0], "tiny@https://example.com/tiny-1.0.0.tgz");
+/// ```
+pub fn parse_yarn_lock(contents: &str) -> Result<BTreeMap<String, Values>> {
+    let mut packages = BTreeMap::new();
+
+    for entry in parse_entries(contents)? {
+        let version = entry.fields.get("version").cloned().unwrap_or_default();
+        let (name, values) = entry.into_values()?;
+
+        let key = if packages.contains_key(&name) {
+            format!("{name}@{version}")
+        } else {
+            name
+        };
+
+        packages.insert(key, values);
+    }
+
+    Ok(packages)
+}
+
+/// A single entry of a `yarn.lock`, which may be shared by several specifiers
+#[derive(Debug, Default)]
+struct YarnEntry {
+    specifiers: Vec<String>,
+    fields: BTreeMap<String, String>,
+}
+
+impl YarnEntry {
+    /// Convert the entry into it's package name and bun lockfile tuple
+    fn into_values(self) -> Result<(String, Values)> {
+        let specifier = self.specifiers.first().cloned().unwrap_or_default();
+        let unsupported = || Error::UnsupportedYarnResolution(specifier.clone());
+
+        let spec = strip_name_prefix(&specifier).ok_or_else(unsupported)?;
+        let name = specifier[..specifier.len() - spec.len() - 1].to_owned();
+
+        let version = self.fields.get("version").ok_or_else(unsupported)?;
+        let resolved = self.fields.get("resolved").ok_or_else(unsupported)?;
+        let (url, _) = resolved.split_once('#').unwrap_or((resolved, ""));
+
+        let values = if let Some(path) = url.strip_prefix("https://codeload.github.com/") {
+            let (repo, rev) = path.split_once("/tar.gz/").ok_or_else(unsupported)?;
+
+            vec![
+                json!(format!("{name}@github:{repo}#{rev}")),
+                json!({}),
+                json!(""),
+            ]
+        } else if url.starts_with("git") || url.ends_with(".git") {
+            let prefix = if resolved.starts_with("git") {
+                ""
+            } else {
+                "git+"
+            };
+
+            vec![
+                json!(format!("{name}@{prefix}{resolved}")),
+                json!({}),
+                json!(""),
+            ]
+        } else if url.starts_with("http") && url.contains("/-/") {
+            let integrity = self
+                .fields
+                .get("integrity")
+                .ok_or_else(|| Error::MissingYarnIntegrity(specifier.clone()))?;
+
+            let id = match spec.strip_prefix("npm:") {
+                Some(alias) => {
+                    let target = strip_name_prefix(alias)
+                        .map_or(alias, |range| &alias[..alias.len() - range.len() - 1]);
+
+                    format!("{name}@npm:{target}@{version}")
+                }
+                None => format!("{name}@{version}"),
+            };
+
+            let is_default_registry = YARN_DEFAULT_REGISTRIES
+                .iter()
+                .any(|registry| url.starts_with(registry));
+            let tarball_url = if is_default_registry { "" } else { url };
+
+            vec![json!(id), json!(tarball_url), json!({}), json!(integrity)]
+        } else if url.starts_with("http") {
+            vec![json!(format!("{name}@{url}")), json!({})]
+        } else {
+            return Err(unsupported());
+        };
+
+        Ok((name, values))
+    }
+}
+
+/// Split a `yarn.lock` into it's entries, keeping only the top level
+/// fields of each, as nested blocks such as `dependencies` aren't needed
+fn parse_entries(contents: &str) -> Result<Vec<YarnEntry>> {
+    let mut entries: Vec<YarnEntry> = Vec::new();
+
+    for line in contents.lines() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if line.starts_with("__metadata:") {
+            return Err(Error::UnsupportedYarnLockVersion);
+        }
+
+        if !line.starts_with(' ') {
+            let specifiers = line
+                .strip_suffix(':')
+                .ok_or_else(|| Error::MalformedYarnLock(line.to_owned()))?
+                .split(", ")
+                .map(|specifier| unquote(specifier).to_owned())
+                .collect();
+
+            entries.push(YarnEntry {
+                specifiers,
+                ..Default::default()
+            });
+
+            continue;
+        }
+
+        // Only fields directly within an entry are indented by two spaces
+        let Some(field) = line
+            .strip_prefix("  ")
+            .filter(|field| !field.starts_with(' '))
+        else {
+            continue;
+        };
+        let Some(entry) = entries.last_mut() else {
+            return Err(Error::MalformedYarnLock(line.to_owned()));
+        };
+
+        if let Some((key, value)) = field.split_once(' ') {
+            entry
+                .fields
+                .insert(unquote(key).to_owned(), unquote(value).to_owned());
+        }
+    }
+
+    Ok(entries)
+}
+
+fn unquote(value: &str) -> &str {
+    let value = value.trim();
+
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value)
+}
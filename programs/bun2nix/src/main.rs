@@ -3,68 +3,454 @@
 
 #![warn(missing_docs)]
 
-use bun2nix::{Options, Result, convert_lockfile_to_nix_expression};
-use log::error;
+use bun2nix::{
+    Error, Options, Result,
+    config::{CONFIG_FILE_NAME, Config},
+    convert_lockfile_to_nix_expression, diff_lockfiles, dry_run_lockfile,
+    lockfile::{
+        DEFAULT_PREFETCH_RETRIES, DEFAULT_PREFETCH_TIMEOUT, is_lockb, lockb_format_version,
+    },
+    options::{DEFAULT_GITHUB_DOMAIN, DEFAULT_GITLAB_DOMAIN, Linker, default_prefetch_jobs},
+};
+use log::{LevelFilter, error};
 
 use std::{
-    fs::{self, File},
-    io::Write,
-    path::PathBuf,
+    fs,
+    io::{self, IsTerminal, Read},
+    path::{Path, PathBuf},
+    time::Duration,
 };
 
-use clap::Parser;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, parser::ValueSource};
 use env_logger::Env;
 
 /// Convert Bun (v1.2+) packages to Nix expressions
 #[derive(Debug, Parser)]
 #[command(version, about, long_about = None)]
 pub struct Cli {
-    /// The Bun (v1.2+) lockfile to use to produce the Nix expression.
+    /// The Bun (v1.2+) lockfile, or a Yarn v1 `yarn.lock`, to use to produce
+    /// the Nix expression.
     #[arg(short, long, default_value = "./bun.lock")]
     lock_file: PathBuf,
 
-    /// The output file to write to -
-    /// if no file location is provided, print to stdout instead.
-    #[arg(short, long)]
-    output_file: Option<PathBuf>,
+    /// Read the lockfile from standard input instead of `--lock-file`.
+    #[arg(long, conflicts_with = "lock_file")]
+    stdin: bool,
+
+    /// The directory which `file:` and `workspace:` package paths are
+    /// relative to, wherever the lockfile is - defaults to the lockfile's
+    /// directory, or the current directory with `--stdin`.
+    #[arg(long)]
+    root: Option<PathBuf>,
+
+    /// A `bun2nix.toml` file to read options from - defaults to the one
+    /// next to the lockfile, if any. Flags given here take precedence.
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// The output file to write to, replaced atomically and only if its
+    /// contents change - if no file location is provided, print to stdout instead.
+    #[arg(short, long, visible_alias = "output-file")]
+    output: Option<PathBuf>,
+
+    /// Check the output file is up to date with the lockfile, failing
+    /// with a summary of the changed lines if not, rather than writing it.
+    #[arg(long, requires = "output")]
+    check: bool,
+
+    /// Parse the generated expression with `nix-instantiate --parse`
+    /// before writing it, failing if it is not valid nix. Requires nix
+    /// to be installed.
+    #[arg(long)]
+    validate: bool,
+
+    /// A file to also write every resolved package and it's fetcher to,
+    /// as JSON.
+    #[arg(long)]
+    json_output: Option<PathBuf>,
+
+    /// A file to also write a report to, mapping each lockfile entry
+    /// to the fetcher and source it was converted to, one per line.
+    #[arg(long, value_name = "FILE")]
+    report: Option<PathBuf>,
+
+    /// Also write the nix base-32 `sha256` of each prefetched git, github,
+    /// gitlab, bitbucket or zip package as `passthru.sha256`, for tools
+    /// which expect one rather than an SRI hash.
+    #[arg(long)]
+    emit_sha256: bool,
+
+    /// Once converted, write the number of packages using each fetcher,
+    /// how many were prefetched and the time taken to stderr, for
+    /// comparing one run against another.
+    #[arg(long)]
+    stats: bool,
+
+    /// Never run any package's lifecycle scripts, even those of trusted
+    /// dependencies. Packages which build native code as they are
+    /// installed may then not work.
+    #[arg(long)]
+    disable_lifecycle_scripts: bool,
+
+    /// Write each package's fetcher on a single line, rather than as an
+    /// indented attribute set, for output which is reformatted anyway.
+    #[arg(long)]
+    compact: bool,
+
+    /// A directory of `*.nix_template` files to render each kind of
+    /// fetcher with, in place of the built in templates. Every template
+    /// must be present, and may only use `{{ variable }}` placeholders.
+    #[arg(long, value_name = "DIR")]
+    template_dir: Option<PathBuf>,
+
+    /// The `node_modules` layout to declare the packages are installed
+    /// with - either `isolated` or `hoisted`. Read from the `install.linker`
+    /// setting of the `bunfig.toml` next to the lockfile if not given.
+    #[arg(long)]
+    linker: Option<Linker>,
 
     /// The prefix to use when copying workspace or file packages
     #[arg(short, long, default_value = "./")]
     copy_prefix: String,
+
+    /// A glob of files to leave out when copying workspace or file packages,
+    /// on top of `.git` and `node_modules` - may be repeated.
+    #[arg(long = "copy-exclude", value_name = "GLOB")]
+    copy_excludes: Vec<String>,
+
+    /// The domain of the GitLab instance to fetch `gitlab:` packages from
+    #[arg(long, default_value = DEFAULT_GITLAB_DOMAIN)]
+    gitlab_domain: String,
+
+    /// The domain of the GitHub instance, such as a GitHub Enterprise
+    /// server, to fetch `github:` packages from.
+    #[arg(long, default_value = DEFAULT_GITHUB_DOMAIN)]
+    github_domain: String,
+
+    /// A registry, such as a mirror, to fetch packages from in place of the
+    /// npm registry - packages with a scope registry or explicit tarball url
+    /// are unaffected.
+    #[arg(long)]
+    registry: Option<String>,
+
+    /// A mirror of the registry to try fetching packages from before
+    /// falling back to it - may be repeated, and is tried in order.
+    #[arg(long = "registry-mirror", value_name = "URL")]
+    registry_mirrors: Vec<String>,
+
+    /// The maximum number of packages to prefetch at once -
+    /// defaults to the number of available CPUs.
+    #[arg(short = 'j', long)]
+    prefetch_jobs: Option<usize>,
+
+    /// The maximum number of packages to prefetch at once from any one
+    /// host, such as `github.com`, to avoid being rate limited - no limit
+    /// beyond `--prefetch-jobs` by default.
+    #[arg(
+        long,
+        value_name = "JOBS",
+        default_value_t = 0,
+        hide_default_value = true
+    )]
+    per_host_jobs: usize,
+
+    /// The number of times to retry a prefetch which failed from a
+    /// network error, waiting longer between each attempt.
+    #[arg(long, default_value_t = DEFAULT_PREFETCH_RETRIES)]
+    prefetch_retries: usize,
+
+    /// The number of seconds a single prefetch may take before it is
+    /// killed.
+    #[arg(long, value_name = "SECONDS", default_value_t = DEFAULT_PREFETCH_TIMEOUT.as_secs())]
+    prefetch_timeout: u64,
+
+    /// The command to prefetch packages with, in place of `nix flake
+    /// prefetch`. Any `{url}` argument is replaced by the url to
+    /// prefetch, which is otherwise appended, and the command must
+    /// print the hash, or JSON with a `hash` field. Git urls marked
+    /// `leaveDotGit=1` must be hashed with their `.git` directory, as
+    /// `nix-prefetch-git --leave-dotGit` does.
+    #[arg(long, value_name = "COMMAND")]
+    prefetch_command: Option<String>,
+
+    /// A proxy to prefetch packages through - defaults to the one in
+    /// `https_proxy` or `http_proxy`, if any, which is otherwise left
+    /// to be inherited.
+    #[arg(long, value_name = "URL")]
+    proxy: Option<String>,
+
+    /// A host, such as an internal registry, to prefetch packages from
+    /// without the proxy - may be repeated, defaults to those in `no_proxy`.
+    #[arg(long = "no-proxy", value_name = "HOST")]
+    no_proxy: Vec<String>,
+
+    /// Skip the on-disk cache of previously prefetched hashes.
+    #[arg(long)]
+    no_prefetch_cache: bool,
+
+    /// A previously generated `bun.nix`, or JSON package list, to reuse
+    /// the hashes of unchanged packages from.
+    #[arg(long, value_name = "FILE")]
+    previous_output: Option<PathBuf>,
+
+    /// Fail on any git, github or tarball package whose hash isn't
+    /// already known, from the prefetch cache or `--previous-output`,
+    /// rather than prefetch it over the network.
+    #[arg(long)]
+    frozen: bool,
+
+    /// Report every package which failed to convert, instead of
+    /// stopping at the first.
+    #[arg(long)]
+    collect_all_errors: bool,
+
+    /// Error, rather than warn, when two packages would be written
+    /// under the same attribute name.
+    #[arg(long)]
+    strict: bool,
+
+    /// The most verbose level of log messages to write to stderr - one of
+    /// `off`, `error`, `warn`, `info`, `debug` or `trace`. `RUST_LOG`, if
+    /// set, takes precedence.
+    #[arg(long, value_name = "LEVEL", default_value = "warn")]
+    log_level: LevelFilter,
+
+    /// Print a summary of the fetchers in use and the urls which need a
+    /// live prefetch, without prefetching or writing any output.
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Compare the packages of an old and a new lockfile, printing each
+    /// one added, removed or fetched differently along with it's source
+    /// and hash before and after, one per line, rather than converting.
+    /// The new lockfile stands in for `--lock-file`.
+    #[arg(
+        long,
+        num_args = 2,
+        value_names = ["OLD", "NEW"],
+        conflicts_with_all = ["lock_file", "stdin", "output", "dry_run"]
+    )]
+    diff: Vec<PathBuf>,
+
+    /// Report each package to stderr as it is prefetched - on by
+    /// default when stderr is a terminal.
+    #[arg(long, conflicts_with = "no_progress")]
+    progress: bool,
+
+    /// Don't report packages as they are prefetched, even when
+    /// stderr is a terminal.
+    #[arg(long)]
+    no_progress: bool,
+
+    /// An `.npmrc` file to read scope registries and auth tokens from -
+    /// tokens must be given as a single `${VAR}`, which is read when each
+    /// package is fetched, to keep them out of the output and the store.
+    /// The variable must be set for the nix daemon, as it is passed in as
+    /// an impure environment variable.
+    #[arg(long)]
+    npmrc: Option<PathBuf>,
+
+    /// Leave out the packages which are only installed for the
+    /// `devDependencies` of the workspaces, for a production build.
+    /// Packages shared with other dependencies are kept.
+    #[arg(long)]
+    production: bool,
+
+    /// A git dependency, by it's name in `package.json`, which should be
+    /// fetched along with it's submodules - may be repeated.
+    #[arg(long = "fetch-submodules", value_name = "PACKAGE")]
+    fetch_submodules: Vec<String>,
+
+    /// A git dependency, by it's name in `package.json`, which should be
+    /// cloned with it's full history rather than only the locked commit,
+    /// keeping it's `.git` directory - may be repeated. These are
+    /// prefetched with `nix-prefetch-git`, which must be on the `PATH`.
+    #[arg(long = "deep-clone", value_name = "PACKAGE")]
+    deep_clone: Vec<String>,
+
+    /// A git dependency, by it's name in `package.json`, which should be
+    /// fetched with every ref, for a locked commit which can't be reached
+    /// from the default branch - may be repeated.
+    #[arg(long = "all-refs", value_name = "PACKAGE")]
+    all_refs: Vec<String>,
+
+    /// A git dependency, by it's name in `package.json`, which should
+    /// keep it's `.git` directory, for packages which read it when
+    /// built - may be repeated. These are prefetched with
+    /// `nix-prefetch-git`, which must be on the `PATH`.
+    #[arg(long = "leave-dot-git", value_name = "PACKAGE")]
+    leave_dot_git: Vec<String>,
+
+    /// A nix system, such as `x86_64-linux`, to keep platform specific
+    /// packages for - may be repeated, defaults to all systems.
+    #[arg(long = "target-system", value_name = "SYSTEM")]
+    target_systems: Vec<String>,
+
+    /// A glob of the lockfile entries to convert, such as `@types/*`, leaving
+    /// out the rest - may be repeated. For debugging a few packages, as the
+    /// output is then only part of a `bun.nix`.
+    #[arg(long, value_name = "GLOB", conflicts_with = "check")]
+    only: Vec<String>,
+
+    /// A glob of lockfile entries to leave out - may be repeated. Like
+    /// `--only`, for debugging rather than producing a complete `bun.nix`.
+    #[arg(long, value_name = "GLOB", conflicts_with = "check")]
+    exclude: Vec<String>,
 }
 
 fn main() {
-    let log_env = Env::default().default_filter_or("warn");
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+
+    let log_env = Env::default().default_filter_or(cli.log_level.as_str());
     env_logger::Builder::from_env(log_env).init();
 
-    match run() {
+    match run(cli, &matches) {
         Ok(()) => (),
         Err(err) => {
             error!("\n{err}\n");
 
-            std::process::exit(1)
+            std::process::exit(err.exit_code().into())
         }
     }
 }
 
-fn run() -> Result<()> {
-    let cli = Cli::parse();
-
-    let lockfile = fs::read_to_string(&cli.lock_file)?;
+fn run(cli: Cli, matches: &ArgMatches) -> Result<()> {
+    // When diffing, the new lockfile is the one converted as usual
+    let (old_lock_file, lock_file) = match <[PathBuf; 2]>::try_from(cli.diff) {
+        Ok([old, new]) => (Some(old), new),
+        Err(_) => (None, cli.lock_file),
+    };
 
-    let nix = convert_lockfile_to_nix_expression(
-        lockfile,
-        Options {
-            copy_prefix: cli.copy_prefix,
-        },
-    )?;
+    let lockfile = if cli.stdin {
+        let mut lockfile = Vec::new();
+        io::stdin().read_to_end(&mut lockfile)?;
 
-    if let Some(output_file) = cli.output_file {
-        let mut output = File::create(output_file)?;
-        write!(output, "{nix}")?;
+        lockfile
     } else {
+        fs::read(&lock_file)?
+    };
+
+    let root = match cli.root {
+        Some(root) => Some(root),
+        None if cli.stdin => None,
+        None => Some(parent_dir(&lock_file)),
+    };
+
+    // Local paths are written relative to the output file, which is
+    // assumed to sit next to the lockfile when printing to stdout,
+    // even when `--root` places the packages elsewhere
+    let output_dir = match &cli.output {
+        Some(output) => Some(parent_dir(output)),
+        None if cli.stdin => None,
+        None => Some(parent_dir(&lock_file)),
+    };
+
+    let print_to_stdout = cli.output.is_none();
+
+    // An explicit config file must exist, while the default is optional
+    let config = match &cli.config {
+        Some(config) => Config::load(config)?,
+        None if cli.stdin => Config::default(),
+        None => {
+            let path = parent_dir(&lock_file).join(CONFIG_FILE_NAME);
+
+            if path.is_file() {
+                Config::load(&path)?
+            } else {
+                Config::default()
+            }
+        }
+    };
+
+    let options = Options {
+        copy_prefix: cli.copy_prefix,
+        copy_excludes: cli.copy_excludes,
+        gitlab_domain: cli.gitlab_domain,
+        github_domain: cli.github_domain,
+        default_registry: cli.registry,
+        registry_mirrors: cli.registry_mirrors,
+        prefetch_jobs: cli.prefetch_jobs.unwrap_or_else(default_prefetch_jobs),
+        per_host_jobs: cli.per_host_jobs,
+        prefetch_retries: cli.prefetch_retries,
+        prefetch_timeout: Duration::from_secs(cli.prefetch_timeout),
+        prefetch_command: cli.prefetch_command,
+        proxy: cli.proxy,
+        no_proxy: cli.no_proxy,
+        prefetch_cache: !cli.no_prefetch_cache,
+        previous_output: cli.previous_output,
+        frozen: cli.frozen,
+        collect_all_errors: cli.collect_all_errors,
+        strict: cli.strict,
+        log_level: cli.log_level,
+        dry_run: cli.dry_run,
+        progress: !cli.no_progress && (cli.progress || io::stderr().is_terminal()),
+        npmrc: cli.npmrc,
+        production: cli.production,
+        fetch_submodules: cli.fetch_submodules,
+        deep_clone: cli.deep_clone,
+        all_refs: cli.all_refs,
+        leave_dot_git: cli.leave_dot_git,
+        target_systems: cli.target_systems,
+        only: cli.only,
+        exclude: cli.exclude,
+        root,
+        output: cli.output,
+        check: cli.check,
+        validate: cli.validate,
+        json_output: cli.json_output,
+        report: cli.report,
+        emit_sha256: cli.emit_sha256,
+        stats: cli.stats,
+        disable_lifecycle_scripts: cli.disable_lifecycle_scripts,
+        pretty: !cli.compact,
+        template_dir: cli.template_dir,
+        linker: cli.linker,
+        output_dir,
+    };
+
+    // Flags are named after their fields, with dashes for underscores
+    let options = config.apply(options, |flag| {
+        matches.value_source(&flag.replace('-', "_")) == Some(ValueSource::CommandLine)
+    });
+
+    if let Some(old_lock_file) = old_lock_file {
+        let old = fs::read_to_string(old_lock_file)?;
+        let new = String::from_utf8(lockfile)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+        print!("{}", diff_lockfiles(&old, &new, &options)?);
+
+        return Ok(());
+    }
+
+    // Binary lockfiles can't be read, so point to the textual one instead
+    if is_lockb(&lockfile) {
+        return Err(Error::UndecodableLockb(lockb_format_version(&lockfile)?));
+    }
+
+    let contents = String::from_utf8(lockfile)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+    if options.dry_run {
+        print!("{}", dry_run_lockfile(contents, options)?);
+
+        return Ok(());
+    }
+
+    let nix = convert_lockfile_to_nix_expression(contents, options)?;
+
+    // The library writes the output file itself, so only stdout is left
+    if print_to_stdout {
         println!("{nix}");
     }
 
     Ok(())
 }
+
+fn parent_dir(path: &Path) -> PathBuf {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_owned(),
+        _ => PathBuf::from("."),
+    }
+}
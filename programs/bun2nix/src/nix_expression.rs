@@ -1,14 +1,24 @@
 //! This module handles construction of the rendered nix code as the output
 
 mod nix_escaper;
+mod template_dir;
 
 pub use nix_escaper::NixEscaper;
+pub use template_dir::TemplateDir;
 
-use crate::{Options, error::Result};
-use askama::Template;
-use std::{any::Any, collections::HashMap};
+use crate::{
+    Options,
+    error::{Error, Result},
+};
+use askama::{FastWritable, Template, Values};
+use log::warn;
+use std::{
+    any::Any,
+    collections::{BTreeMap, HashMap},
+    fmt,
+};
 
-use crate::Package;
+use crate::{Package, package::Fetcher};
 
 /// # Nix Expression
 ///
@@ -16,24 +26,455 @@ use crate::Package;
 #[derive(Template)]
 #[template(path = "output.nix_template")]
 pub struct NixExpression {
-    packages: Vec<Package>,
+    packages: BTreeMap<String, Package>,
+
+    /// The version of `bun2nix` producing the expression
+    version: &'static str,
+
+    /// The format version of the lockfile the packages were read from
+    lockfile_version: u8,
+
+    /// Packages whose fetch is shared with others, bound once in a `let` block
+    shared: Vec<Package>,
+
+    /// The index into `shared` of each package name using a shared fetch
+    shared_ids: HashMap<String, usize>,
 }
 
 impl NixExpression {
     /// # New Nix Expression
     ///
-    /// Produce a new, ready to render, nix expression from a package list
-    pub fn new(packages: Vec<Package>) -> Result<Self> {
-        Ok(Self { packages })
+    /// Produce a new, ready to render, nix expression from a set of
+    /// packages keyed by their attribute name, and the format version
+    /// of the lockfile they came from
+    ///
+    /// Each package is checked with `Package::validate` first, so
+    /// none are rendered into broken nix.
+    ///
+    /// Packages which resolve to an identical fetch are only
+    /// fetched once, and referenced by each of their names:
+    ///
+    /// ```rust
+    /// use std::collections::BTreeMap;
+    ///
+    /// use bun2nix::{Options, Package, nix_expression::NixExpression, package::Fetcher};
+    ///
+    /// let fetcher = Fetcher::FetchUrl {
+    ///     url: "https://registry.npmjs.org/is-number/-/is-number-7.0.0.tgz".to_owned(),
+    ///     urls: Vec::new(),
+    ///     hash: "sha512-41Cifkg6e8TylSpdtTpeLVMqvSBEVzTttHvERD741+pnZ8ANv0004MRL43QKPDlK9cGvNp6NZWZUBlbGXYxxng==".to_owned(),
+    ///     name: None,
+    ///     auth_token_var: None,
+    /// };
+    ///
+    /// let packages: BTreeMap<_, _> = ["is-number@7.0.0", "is-num@7.0.0"]
+    ///     .into_iter()
+    ///     .map(|name| (name.to_owned(), Package::new(name.to_owned(), fetcher.clone())))
+    ///     .collect();
+    ///
+    /// let nix = NixExpression::new(packages, 1)
+    ///     .unwrap()
+    ///     .render_with_options(Options::default())
+    ///     .unwrap();
+    ///
+    /// assert_eq!(nix.matches("fetchurl {").count(), 1);
+    /// assert!(nix.contains(r#""is-num@7.0.0" = shared-0;"#));
+    /// assert!(nix.contains(r#""is-number@7.0.0" = shared-0;"#));
+    ///
+    /// // The header only depends on the inputs, so output is reproducible
+    /// assert!(nix.contains(&format!(
+    ///     "# Generated by bun2nix v{} from a version 1 lockfile",
+    ///     env!("CARGO_PKG_VERSION")
+    /// )));
+    /// ```
+    pub fn new(packages: BTreeMap<String, Package>, lockfile_version: u8) -> Result<Self> {
+        type Source<'a> = (
+            &'a Fetcher,
+            &'a Option<String>,
+            (&'a Option<String>, &'a Option<String>),
+            &'a Option<String>,
+            &'a Option<String>,
+            bool,
+            &'a Vec<String>,
+            &'a BTreeMap<String, String>,
+            &'a Vec<String>,
+            &'a BTreeMap<String, String>,
+            &'a Vec<String>,
+            &'a Vec<String>,
+        );
+
+        for package in packages.values() {
+            package.validate()?;
+        }
+
+        // Group packages whose rendered fetches would be identical
+        let mut groups: Vec<Vec<(&String, &Package)>> = Vec::new();
+        let mut group_ids: HashMap<Source, usize> = HashMap::new();
+
+        for (name, package) in &packages {
+            let source = (
+                &package.fetcher,
+                &package.license,
+                (&package.homepage, &package.repository),
+                &package.sha256,
+                &package.patch,
+                package.runs_scripts,
+                &package.lifecycle_scripts,
+                &package.bin,
+                &package.dependencies,
+                &package.peer_dependencies,
+                &package.os,
+                &package.cpu,
+            );
+            let id = *group_ids.entry(source).or_insert_with(|| {
+                groups.push(Vec::new());
+                groups.len() - 1
+            });
+
+            groups[id].push((name, package));
+        }
+
+        let mut shared = Vec::new();
+        let mut shared_ids = HashMap::new();
+
+        for group in groups.into_iter().filter(|group| group.len() > 1) {
+            for (name, _) in &group {
+                shared_ids.insert((*name).clone(), shared.len());
+            }
+
+            shared.push(group[0].1.clone());
+        }
+
+        Ok(Self {
+            packages,
+            version: env!("CARGO_PKG_VERSION"),
+            lockfile_version,
+            shared,
+            shared_ids,
+        })
+    }
+
+    /// # Key Packages
+    ///
+    /// Key packages by their `Package::nix_attr_name`, so the output is sorted
+    /// and packages sharing a source are only written once
+    ///
+    /// Packages with the same attribute name but different sources would
+    /// clobber each other, so only the first is kept, with a warning, or an
+    /// error is returned if `strict` is set:
+    ///
+    /// ```rust
+    /// use bun2nix::{Error, Package, nix_expression::NixExpression, package::Fetcher};
+    ///
+    /// let git_package = |url: &str| {
+    ///     let fetcher = Fetcher::FetchGit {
+    ///         url: url.to_owned(),
+    ///         rev: "a1b2c3".to_owned(),
+    ///         hash: "sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=".to_owned(),
+    ///         fetch_submodules: false,
+    ///         deep_clone: false,
+    ///         all_refs: false,
+    ///         leave_dot_git: false,
+    ///         dir: None,
+    ///         name: None,
+    ///     };
+    ///
+    ///     Package::new("git:a1b2c3".to_owned(), fetcher)
+    /// };
+    /// let packages = || {
+    ///     vec![
+    ///         git_package("https://example.com/first.git"),
+    ///         git_package("https://example.com/second.git"),
+    ///     ]
+    /// };
+    ///
+    /// let keyed = NixExpression::key_packages(packages(), false).unwrap();
+    /// assert_eq!(keyed.len(), 1);
+    /// assert_eq!(keyed["git:a1b2c3"], packages()[0]);
+    ///
+    /// assert!(matches!(
+    ///     NixExpression::key_packages(packages(), true),
+    ///     Err(Error::DuplicateAttributeName { name, first, second })
+    ///         if name == "git:a1b2c3"
+    ///             && first == "https://example.com/first.git#a1b2c3"
+    ///             && second == "https://example.com/second.git#a1b2c3"
+    /// ));
+    /// ```
+    pub fn key_packages(packages: Vec<Package>, strict: bool) -> Result<BTreeMap<String, Package>> {
+        let mut keyed: BTreeMap<String, Package> = BTreeMap::new();
+
+        for package in packages {
+            let name = package.nix_attr_name();
+
+            let Some(existing) = keyed.get(&name) else {
+                keyed.insert(name, package);
+                continue;
+            };
+
+            if existing.fetcher == package.fetcher {
+                continue;
+            }
+
+            let first = existing.fetcher.source();
+            let second = package.fetcher.source();
+
+            if strict {
+                return Err(Error::DuplicateAttributeName {
+                    name,
+                    first,
+                    second,
+                });
+            }
+
+            warn!(
+                "Two packages would be written under the attribute name `{name}`, keeping the one fetched from '{first}' over '{second}'"
+            );
+        }
+
+        Ok(keyed)
     }
 
     /// # Render with options
     ///
     /// Renders a `NixExpression` with the supplied config options
+    ///
+    /// With `options.pretty` set, as it is by default, each fetcher is
+    /// written as an indented attribute set, otherwise it is compacted
+    /// onto the line of it's attribute:
+    ///
+    /// ```rust
+    /// use std::collections::BTreeMap;
+    ///
+    /// use bun2nix::{Options, Package, nix_expression::NixExpression, package::Fetcher};
+    ///
+    /// let fetcher = Fetcher::FetchGit {
+    ///     url: "https://example.com/repo.git".to_owned(),
+    ///     rev: "a1b2c3".to_owned(),
+    ///     hash: "sha256-AAAA".to_owned(),
+    ///     fetch_submodules: false,
+    ///     deep_clone: false,
+    ///     all_refs: false,
+    ///     leave_dot_git: false,
+    ///     dir: None,
+    ///     name: None,
+    /// };
+    /// let render = |options: Options| {
+    ///     let package = Package::new("git:a1b2c3".to_owned(), fetcher.clone());
+    ///     let packages = BTreeMap::from([(package.nix_attr_name(), package)]);
+    ///
+    ///     NixExpression::new(packages, 1)
+    ///         .unwrap()
+    ///         .render_with_options(options)
+    ///         .unwrap()
+    /// };
+    ///
+    /// let pretty = render(Options::default());
+    /// assert!(pretty.contains(
+    ///     r#"
+    ///   "git:a1b2c3" = fetchgit {
+    ///     url = "https://example.com/repo.git";
+    ///     rev = "a1b2c3";
+    ///     hash = "sha256-AAAA";
+    ///     fetchSubmodules = false;
+    ///     deepClone = false;
+    ///     leaveDotGit = false;
+    ///   };
+    /// "#
+    /// ));
+    ///
+    /// let compact = render(Options {
+    ///     pretty: false,
+    ///     ..Default::default()
+    /// });
+    /// assert!(compact.contains(
+    ///     r#"
+    ///   "git:a1b2c3" = fetchgit { url = "https://example.com/repo.git"; rev = "a1b2c3"; hash = "sha256-AAAA"; fetchSubmodules = false; deepClone = false; leaveDotGit = false; };
+    /// "#
+    /// ));
+    ///
+    /// // Both forms are the same on every render
+    /// assert_eq!(pretty, render(Options::default()));
+    /// assert_eq!(compact, render(Options { pretty: false, ..Default::default() }));
+    /// ```
     pub fn render_with_options(self, options: Options) -> Result<String> {
         let mut values: HashMap<&str, Box<dyn Any>> = HashMap::new();
+        if let Some(template_dir) = &options.template_dir {
+            values.insert("template_dir", Box::new(TemplateDir::load(template_dir)?));
+        }
         values.insert("options", Box::new(options));
 
         Ok(self.render_with_values(&values)?)
     }
 }
+
+/// # Attribute Name
+///
+/// An identifier escaped to be placed within a quoted attribute name,
+/// following the rules documented on `Package::nix_attr_name`
+///
+/// ```rust
+/// use bun2nix::nix_expression::attribute_name;
+///
+/// assert_eq!(attribute_name("@types/node@22.13.5"), "@types/node@22.13.5");
+/// assert_eq!(attribute_name(r#"odd"name${x}"#), r#"odd\"name\${x}"#);
+/// ```
+pub fn attribute_name(identifier: &str) -> String {
+    identifier
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace("${", "\\${")
+}
+
+/// # Escape Nix Attribute
+///
+/// A package name written as an attribute key, left bare when it is a
+/// valid nix identifier, and otherwise quoted with `attribute_name`
+///
+/// Scoped names, versions with dots, and keywords such as `if` must all
+/// be quoted, while dashes and `'` are allowed in bare identifiers.
+///
+/// ```rust
+/// use bun2nix::nix_expression::escape_nix_attr;
+///
+/// assert_eq!(escape_nix_attr("lodash"), "lodash");
+/// assert_eq!(escape_nix_attr("left-pad_2'"), "left-pad_2'");
+///
+/// assert_eq!(escape_nix_attr("@types/node@22.13.5"), r#""@types/node@22.13.5""#);
+/// assert_eq!(escape_nix_attr("lodash.merge"), r#""lodash.merge""#);
+/// assert_eq!(escape_nix_attr("-leading-dash"), r#""-leading-dash""#);
+/// assert_eq!(escape_nix_attr("2d"), r#""2d""#);
+/// assert_eq!(escape_nix_attr("inherit"), r#""inherit""#);
+/// assert_eq!(escape_nix_attr(""), r#""""#);
+/// assert_eq!(escape_nix_attr(r#"odd"name${x}"#), r#""odd\"name\${x}""#);
+/// ```
+///
+/// Which is how every package is keyed when rendered:
+///
+/// ```rust
+/// use std::collections::BTreeMap;
+///
+/// use bun2nix::{Options, Package, nix_expression::NixExpression, package::Fetcher};
+///
+/// let package = |name: &str, path: &str| {
+///     let fetcher = Fetcher::CopyToStore {
+///         path: path.to_owned(),
+///         exclude: Vec::new(),
+///     };
+///     let package = Package::new(name.to_owned(), fetcher);
+///
+///     (package.nix_attr_name(), package)
+/// };
+/// let packages = BTreeMap::from([
+///     package("@acme/lib@1.0.0", "packages/lib"),
+///     package("app", "packages/app"),
+/// ]);
+///
+/// let nix = NixExpression::new(packages, 1)
+///     .unwrap()
+///     .render_with_options(Options::default())
+///     .unwrap();
+///
+/// assert!(nix.contains(r#"  "@acme/lib@1.0.0" = copyPathToStore ./packages/lib;"#));
+/// assert!(nix.contains("  app = copyPathToStore ./packages/app;"));
+/// ```
+pub fn escape_nix_attr(identifier: &str) -> String {
+    const KEYWORDS: &[&str] = &[
+        "assert", "else", "if", "in", "inherit", "let", "or", "rec", "then", "with",
+    ];
+
+    let mut chars = identifier.chars();
+    let is_identifier = chars
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|char| char.is_ascii_alphanumeric() || matches!(char, '_' | '-' | '\''));
+
+    if is_identifier && !KEYWORDS.contains(&identifier) {
+        identifier.to_owned()
+    } else {
+        format!("\"{}\"", attribute_name(identifier))
+    }
+}
+
+/// # Package Values
+///
+/// The template values for rendering a single package's fetcher,
+/// falling back to those of the whole expression
+struct PackageValues<'a> {
+    package: &'a Package,
+    parent: &'a dyn Values,
+}
+
+impl Values for PackageValues<'_> {
+    fn get_value<'a>(&'a self, key: &str) -> Option<&'a dyn Any> {
+        let value = match key {
+            "license" => &self.package.license,
+            "homepage" => &self.package.homepage,
+            "repository" => &self.package.repository,
+            "sha256" => &self.package.sha256,
+            "integrity" => &self.package.integrity,
+            "patch" => &self.package.patch,
+            "runs_scripts" => return Some(&self.package.runs_scripts),
+            "lifecycle_scripts" if !self.package.lifecycle_scripts.is_empty() => {
+                return Some(&self.package.lifecycle_scripts);
+            }
+            "bin" if !self.package.bin.is_empty() => return Some(&self.package.bin),
+            "dependencies" if !self.package.dependencies.is_empty() => {
+                return Some(&self.package.dependencies);
+            }
+            "peer_dependencies" if !self.package.peer_dependencies.is_empty() => {
+                return Some(&self.package.peer_dependencies);
+            }
+            "os" if !self.package.os.is_empty() => return Some(&self.package.os),
+            "cpu" if !self.package.cpu.is_empty() => return Some(&self.package.cpu),
+            _ => return self.parent.get_value(key),
+        };
+
+        value.as_ref().map(|value| value as &dyn Any)
+    }
+}
+
+impl FastWritable for Package {
+    fn write_into<W: fmt::Write + ?Sized>(
+        &self,
+        dest: &mut W,
+        values: &dyn Values,
+    ) -> askama::Result<()> {
+        let values = PackageValues {
+            package: self,
+            parent: values,
+        };
+
+        let pretty =
+            askama::get_value::<Options>(&values, "options").map_or(true, |options| options.pretty);
+        let template_dir = askama::get_value::<TemplateDir>(&values, "template_dir").ok();
+
+        let rendered = match template_dir {
+            Some(template_dir) => template_dir
+                .render(self, &values)
+                .map_err(askama::Error::custom)?,
+            None if pretty => return self.fetcher.render_into_with_values(dest, &values),
+            None => self.fetcher.render_with_values(&values)?,
+        };
+        if pretty {
+            return Ok(dest.write_str(&rendered)?);
+        }
+
+        // Nothing rendered spans lines within a string, so each line's
+        // indentation can be dropped
+        let compact = rendered
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        Ok(dest.write_str(&compact)?)
+    }
+}
+
+impl fmt::Display for Package {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write_into(f, askama::NO_VALUES)
+            .map_err(|_| fmt::Error)
+    }
+}
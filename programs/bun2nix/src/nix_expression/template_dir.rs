@@ -0,0 +1,224 @@
+use std::{collections::BTreeMap, fs, path::Path};
+
+use askama::{Template, Values};
+use serde_json::Value;
+
+use crate::{
+    Options, Package,
+    error::{Error, Result},
+    package::FetcherKind,
+};
+
+/// # Template Directory
+///
+/// Templates loaded from disk to render each kind of fetcher with, in
+/// place of the built in ones, as given by `options.template_dir`
+///
+/// The directory must hold one template for every kind of fetcher,
+/// named as returned by `FetcherKind::template_name`. Templates are
+/// plain text with `{{ variable }}` placeholders, rather than full
+/// askama templates, as they are read at runtime. Their variables are
+/// the fields of the fetcher being rendered, along with:
+/// - `package_attrs`, the package's `meta` and `passthru` attributes,
+///   as written by the built in templates, each on it's own line
+/// - `copy_prefix`, the prefix local paths are written relative to
+///
+/// Lists are written as the items of a nix list, and missing values
+/// as an empty string.
+///
+/// ```rust
+/// use std::{collections::BTreeMap, fs};
+///
+/// use bun2nix::{Options, Package, nix_expression::NixExpression, package::{Fetcher, FetcherKind}};
+///
+/// let template_dir = std::env::temp_dir().join("bun2nix-template-dir");
+/// fs::create_dir_all(&template_dir).unwrap();
+/// for kind in FetcherKind::ALL {
+///     fs::write(template_dir.join(kind.template_name()), "throw \"unused\"").unwrap();
+/// }
+/// fs::write(
+///     template_dir.join("fetchurl.nix_template"),
+///     "fetchurl {\n    url = \"{{ url }}\";\n    hash = \"{{ hash }}\";\n    passthru.custom = true;{{ package_attrs }}\n  }",
+/// )
+/// .unwrap();
+///
+/// let fetcher = Fetcher::FetchUrl {
+///     url: "https://registry.npmjs.org/is-number/-/is-number-7.0.0.tgz".to_owned(),
+///     urls: Vec::new(),
+///     hash: "sha512-AAAA".to_owned(),
+///     name: None,
+///     auth_token_var: None,
+/// };
+/// let package = Package::new("is-number@7.0.0".to_owned(), fetcher)
+///     .with_license(Some("MIT".to_owned()));
+/// let packages = BTreeMap::from([(package.nix_attr_name(), package)]);
+///
+/// let options = Options {
+///     template_dir: Some(template_dir.clone()),
+///     ..Default::default()
+/// };
+/// let nix = NixExpression::new(packages, 1)
+///     .unwrap()
+///     .render_with_options(options)
+///     .unwrap();
+///
+/// assert!(nix.contains(
+///     r#"
+///   "is-number@7.0.0" = fetchurl {
+///     url = "https://registry.npmjs.org/is-number/-/is-number-7.0.0.tgz";
+///     hash = "sha512-AAAA";
+///     passthru.custom = true;
+///     meta.license = "MIT";
+///   };
+/// "#
+/// ));
+/// ```
+///
+/// Every template must be present, and only use `{{ variable }}`
+/// placeholders:
+///
+/// ```rust
+/// use std::fs;
+///
+/// use bun2nix::{Error, nix_expression::TemplateDir, package::FetcherKind};
+///
+/// let template_dir = std::env::temp_dir().join("bun2nix-template-dir-invalid");
+/// let _ = fs::remove_dir_all(&template_dir);
+/// fs::create_dir_all(&template_dir).unwrap();
+///
+/// assert!(matches!(TemplateDir::load(&template_dir), Err(Error::ReadTemplateError { .. })));
+///
+/// for kind in FetcherKind::ALL {
+///     fs::write(template_dir.join(kind.template_name()), "{{ hash }}").unwrap();
+/// }
+/// assert!(TemplateDir::load(&template_dir).is_ok());
+///
+/// fs::write(template_dir.join("fetchgit.nix_template"), "{% if true %}{% endif %}").unwrap();
+/// assert!(matches!(
+///     TemplateDir::load(&template_dir),
+///     Err(Error::MalformedTemplate { template, .. }) if template == "fetchgit.nix_template"
+/// ));
+/// ```
+#[derive(Debug, Clone)]
+pub struct TemplateDir {
+    templates: BTreeMap<FetcherKind, String>,
+}
+
+/// The built in `meta` and `passthru` attributes of a package,
+/// rendered on their own for templates to include
+#[derive(Template)]
+#[template(path = "package-attrs.nix_template")]
+struct PackageAttrs;
+
+impl TemplateDir {
+    /// # Load Template Directory
+    ///
+    /// Read the template for every kind of fetcher from a directory,
+    /// checking each is well formed
+    pub fn load(dir: &Path) -> Result<Self> {
+        let mut templates = BTreeMap::new();
+
+        for kind in FetcherKind::ALL {
+            let path = dir.join(kind.template_name());
+            let template =
+                fs::read_to_string(&path).map_err(|source| Error::ReadTemplateError {
+                    path: path.display().to_string(),
+                    source,
+                })?;
+
+            substitute(&template, |_| Some(String::new())).map_err(|reason| {
+                Error::MalformedTemplate {
+                    template: kind.template_name().to_owned(),
+                    reason,
+                }
+            })?;
+
+            templates.insert(kind, template);
+        }
+
+        Ok(Self { templates })
+    }
+
+    /// # Render
+    ///
+    /// Render a package's fetcher with the template for it's kind
+    pub fn render(&self, package: &Package, values: &dyn Values) -> Result<String> {
+        let kind = package.fetcher.kind();
+        let template = &self.templates[&kind];
+
+        // Fetchers serialize as `{ "Variant": { "field": value, .. } }`
+        let fields = match serde_json::to_value(&package.fetcher)? {
+            Value::Object(variant) => variant.into_iter().next().map(|(_, fields)| fields),
+            _ => None,
+        };
+        let package_attrs = PackageAttrs.render_with_values(values)?;
+        let copy_prefix = askama::get_value::<Options>(values, "options")
+            .map_or("./", |options| options.copy_prefix.as_str())
+            .to_owned();
+
+        substitute(template, |name| match name {
+            "package_attrs" => Some(package_attrs.clone()),
+            "copy_prefix" => Some(copy_prefix.clone()),
+            name => fields.as_ref()?.get(name).map(nix_value),
+        })
+        .map_err(|reason| Error::MalformedTemplate {
+            template: kind.template_name().to_owned(),
+            reason,
+        })
+    }
+}
+
+/// A fetcher field as it is written into a template
+fn nix_value(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(string) => string.clone(),
+        Value::Array(items) => items
+            .iter()
+            .map(|item| format!("\"{}\"", nix_value(item)))
+            .collect::<Vec<_>>()
+            .join(" "),
+        value => value.to_string(),
+    }
+}
+
+/// Replace each `{{ variable }}` in a template with it's value,
+/// describing why if the template can't be rendered
+fn substitute(
+    template: &str,
+    value: impl Fn(&str) -> Option<String>,
+) -> std::result::Result<String, String> {
+    if let Some(tag) = ["{%", "{#"].into_iter().find(|tag| template.contains(tag)) {
+        return Err(format!(
+            "`{tag}` is not supported, only `{{{{ variable }}}}` placeholders are"
+        ));
+    }
+
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+
+        let (name, after) = rest[start + 2..]
+            .split_once("}}")
+            .ok_or_else(|| "a `{{` is never closed".to_owned())?;
+        let name = name.trim();
+
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|char| char.is_ascii_alphanumeric() || char == '_')
+        {
+            return Err(format!("`{{{{ {name} }}}}` is not a variable"));
+        }
+
+        let value = value(name).ok_or_else(|| format!("there is no variable `{name}`"))?;
+        rendered.push_str(&value);
+        rest = after;
+    }
+
+    rendered.push_str(rest);
+
+    Ok(rendered)
+}
@@ -0,0 +1,225 @@
+//! This module holds everything related to reading registry configuration from an `.npmrc` file
+
+use std::{collections::BTreeMap, env, fs, path::Path, str::FromStr};
+
+use log::warn;
+
+use crate::error::{Error, Result};
+
+/// # Npmrc
+///
+/// The subset of settings from an `.npmrc` file which affect how
+/// packages are fetched
+///
+/// ## Usage
+///
+///```rust
+/// use bun2nix::npmrc::Npmrc;
+///
+/// let npmrc: Npmrc = r#"
+/// # Private GitHub packages
+/// //npm.pkg.github.com/:_authToken=ghp_secret
+///
+/// # Internal registry
+/// @acme:registry=https://npm.acme.internal
+/// "#.parse().unwrap();
+///
+/// assert_eq!(
+///     npmrc.registry_for("@acme/lib@1.0.0"),
+///     Some("https://npm.acme.internal/")
+/// );
+/// assert_eq!(npmrc.registry_for("lib@1.0.0"), None);
+///
+/// assert_eq!(
+///     npmrc.auth_token_for("https://npm.pkg.github.com/@acme/lib/-/lib-1.0.0.tgz"),
+///     Some("ghp_secret")
+/// );
+/// assert_eq!(
+///     npmrc.auth_token_for("https://registry.npmjs.org/lib/-/lib-1.0.0.tgz"),
+///     None
+/// );
+/// ```
+///
+/// Tokens given as a single environment variable remember it, so it
+/// can be read when the package is fetched, rather than written out:
+///
+///```rust
+/// use bun2nix::npmrc::Npmrc;
+///
+/// let npmrc: Npmrc = r#"
+/// //npm.acme.internal/:_authToken=${ACME_NPM_TOKEN}
+/// //npm.pkg.github.com/:_authToken=ghp_${GITHUB_SUFFIX}
+/// "#.parse().unwrap();
+///
+/// assert_eq!(
+///     npmrc.auth_token_var_for("https://npm.acme.internal/lib/-/lib-1.0.0.tgz"),
+///     Some("ACME_NPM_TOKEN")
+/// );
+/// assert_eq!(
+///     npmrc.auth_token_var_for("https://npm.pkg.github.com/@acme/lib/-/lib-1.0.0.tgz"),
+///     None
+/// );
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct Npmrc {
+    /// Auth tokens keyed by their "nerfed" registry url, i.e. `//host/path/`
+    pub auth_tokens: BTreeMap<String, String>,
+
+    /// The environment variable of each auth token given as a single
+    /// `${VAR}` reference, keyed by "nerfed" registry url
+    pub auth_token_vars: BTreeMap<String, String>,
+
+    /// Registry urls, with a trailing slash, keyed by the scope they serve, i.e. `@acme`
+    pub scope_registries: BTreeMap<String, String>,
+}
+
+impl Npmrc {
+    /// # Load Npmrc
+    ///
+    /// Read and parse an `.npmrc` file from disk
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path).map_err(Error::ReadNpmrcError)?;
+
+        contents.parse()
+    }
+
+    /// # Registry For
+    ///
+    /// Find the registry configured for a package identifier's scope
+    pub fn registry_for(&self, ident: &str) -> Option<&str> {
+        let (scope, _) = ident.split_once('/').filter(|_| ident.starts_with('@'))?;
+
+        self.scope_registries.get(scope).map(String::as_str)
+    }
+
+    /// # Auth Token For
+    ///
+    /// Find the auth token to use for a given url, picking the most
+    /// specific registry entry which prefixes it
+    pub fn auth_token_for(&self, url: &str) -> Option<&str> {
+        Self::most_specific(&self.auth_tokens, url)
+    }
+
+    /// # Auth Registry For
+    ///
+    /// Find the "nerfed" registry url of the auth token to use for a
+    /// given url, however the token was given
+    pub fn auth_registry_for(&self, url: &str) -> Option<&str> {
+        let nerfed = Self::nerf(url)?;
+
+        self.auth_tokens
+            .keys()
+            .chain(self.auth_token_vars.keys())
+            .filter(|registry| nerfed.starts_with(registry.as_str()))
+            .max_by_key(|registry| registry.len())
+            .map(String::as_str)
+    }
+
+    /// # Auth Token Var For
+    ///
+    /// Find the environment variable the auth token to use for a given
+    /// url is read from, if it was given as a single `${VAR}` reference
+    pub fn auth_token_var_for(&self, url: &str) -> Option<&str> {
+        Self::most_specific(&self.auth_token_vars, url)
+    }
+
+    /// The value of the most specific registry entry which prefixes a url
+    fn most_specific<'a>(registries: &'a BTreeMap<String, String>, url: &str) -> Option<&'a str> {
+        let nerfed = Self::nerf(url)?;
+
+        registries
+            .iter()
+            .filter(|(registry, _)| nerfed.starts_with(registry.as_str()))
+            .max_by_key(|(registry, _)| registry.len())
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// A url without it's scheme, as registries are keyed by
+    fn nerf(url: &str) -> Option<&str> {
+        url.strip_prefix("https:")
+            .or_else(|| url.strip_prefix("http:"))
+    }
+
+    fn with_trailing_slash(url: &str) -> String {
+        let mut url = url.to_owned();
+        if !url.ends_with('/') {
+            url.push('/');
+        }
+
+        url
+    }
+
+    /// The variable a value consists only of a `${VAR}` reference to
+    fn env_var_reference(value: &str) -> Option<&str> {
+        let var = value.strip_prefix("${")?.strip_suffix('}')?;
+
+        let is_name = var.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && var.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+        is_name.then_some(var)
+    }
+
+    /// Expand `${VAR}` references in a value using the environment
+    fn expand_env(value: &str) -> Option<String> {
+        let mut expanded = String::with_capacity(value.len());
+        let mut rest = value;
+
+        while let Some(start) = rest.find("${") {
+            let end = rest[start..].find('}')? + start;
+            let var = &rest[start + 2..end];
+
+            let Ok(var_value) = env::var(var) else {
+                warn!("Environment variable `{var}` referenced in `.npmrc` is not set");
+                return None;
+            };
+
+            expanded.push_str(&rest[..start]);
+            expanded.push_str(&var_value);
+            rest = &rest[end + 1..];
+        }
+
+        expanded.push_str(rest);
+
+        Some(expanded)
+    }
+}
+
+impl FromStr for Npmrc {
+    type Err = Error;
+
+    fn from_str(contents: &str) -> std::result::Result<Self, Self::Err> {
+        let mut npmrc = Self::default();
+
+        let settings = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with(['#', ';']))
+            .filter_map(|line| line.split_once('='))
+            .map(|(key, value)| (key.trim(), value.trim()));
+
+        for (key, value) in settings {
+            if let Some(registry) = key.strip_suffix(":_authToken")
+                && registry.starts_with("//")
+            {
+                let registry = Self::with_trailing_slash(registry);
+
+                if let Some(var) = Self::env_var_reference(value) {
+                    npmrc
+                        .auth_token_vars
+                        .insert(registry.clone(), var.to_owned());
+                }
+                if let Some(token) = Self::expand_env(value) {
+                    npmrc.auth_tokens.insert(registry, token);
+                }
+            } else if let Some(scope) = key.strip_suffix(":registry")
+                && scope.starts_with('@')
+            {
+                npmrc
+                    .scope_registries
+                    .insert(scope.to_owned(), Self::with_trailing_slash(value));
+            }
+        }
+
+        Ok(npmrc)
+    }
+}
@@ -1,7 +1,26 @@
 //! This module holds everything related to configuring the output of bun2nix
+use std::{fmt, num::NonZero, path::PathBuf, str::FromStr, thread, time::Duration};
+
+use log::LevelFilter;
+use serde::Deserialize;
+
+use crate::{
+    error::Error,
+    lockfile::{DEFAULT_PREFETCH_RETRIES, DEFAULT_PREFETCH_TIMEOUT},
+};
+
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 
+/// The default domain used for `gitlab:` packages
+pub const DEFAULT_GITLAB_DOMAIN: &str = "gitlab.com";
+
+/// The default domain used for `github:` packages
+pub const DEFAULT_GITHUB_DOMAIN: &str = "github.com";
+
+/// The globs always left out when copying workspace or file packages
+pub const DEFAULT_COPY_EXCLUDES: &[&str] = &[".git", "node_modules"];
+
 /// # Lockfile conversion options
 ///
 /// Config options for generating a bun.nix file
@@ -10,6 +29,304 @@ use wasm_bindgen::prelude::*;
 pub struct Options {
     /// The prefix to use when copying workspace or file packages
     pub copy_prefix: String,
+
+    /// Globs of files to leave out when copying workspace or file packages,
+    /// on top of `DEFAULT_COPY_EXCLUDES`
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen(skip))]
+    pub copy_excludes: Vec<String>,
+
+    /// The domain to fetch `gitlab:` packages from
+    pub gitlab_domain: String,
+
+    /// The domain to fetch `github:` packages from, such
+    /// as that of a GitHub Enterprise instance
+    pub github_domain: String,
+
+    /// A registry, such as a mirror, to fetch packages from in place of the npm registry
+    pub default_registry: Option<String>,
+
+    /// Mirrors of the default registry to try fetching packages from, in
+    /// order, before falling back to it
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen(skip))]
+    pub registry_mirrors: Vec<String>,
+
+    /// The maximum number of packages to deserialize (and hence prefetch) at once
+    pub prefetch_jobs: usize,
+
+    /// The maximum number of packages to prefetch at once from any one
+    /// host, such as `github.com`, or `0` for no limit beyond `prefetch_jobs`
+    pub per_host_jobs: usize,
+
+    /// The number of times to retry a prefetch which failed transiently
+    pub prefetch_retries: usize,
+
+    /// The time a single prefetch may take before it is killed
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen(skip))]
+    pub prefetch_timeout: Duration,
+
+    /// The command to prefetch packages with, in place of `nix flake prefetch`
+    ///
+    /// See `DEFAULT_PREFETCH_COMMAND` for how the url is passed to it.
+    pub prefetch_command: Option<String>,
+
+    /// A proxy to prefetch packages through, set explicitly in the prefetch
+    /// command's environment, or that of this process if not given
+    pub proxy: Option<String>,
+
+    /// Hosts, such as internal registries, to prefetch packages from without
+    /// the proxy, or those in the environment of this process if empty
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen(skip))]
+    pub no_proxy: Vec<String>,
+
+    /// Reuse and record prefetched hashes in the on-disk cache
+    ///
+    /// This is off by default, so that using the library doesn't write
+    /// to the user's cache directory, but the command line tool turns it
+    /// on unless given `--no-prefetch-cache`.
+    pub prefetch_cache: bool,
+
+    /// A previously generated `bun.nix`, or JSON package list, to reuse the
+    /// hashes of unchanged packages from instead of prefetching them again
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen(skip))]
+    pub previous_output: Option<PathBuf>,
+
+    /// Fail on any package which would need a live prefetch, rather
+    /// than reach the network, for reproducible offline generation
+    ///
+    /// Hashes from the lockfile, the prefetch cache and
+    /// `previous_output` are still used.
+    pub frozen: bool,
+
+    /// Report every package which failed to deserialize, instead of only the first
+    pub collect_all_errors: bool,
+
+    /// Error, rather than warn, when two packages would be written
+    /// under the same attribute name
+    pub strict: bool,
+
+    /// The most verbose level of log messages to write to stderr,
+    /// for whichever logger the caller sets up
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen(skip))]
+    pub log_level: LevelFilter,
+
+    /// Only report which packages would need a live prefetch, without prefetching any
+    pub dry_run: bool,
+
+    /// Write `[n/total] prefetching <package>` to stderr as each
+    /// package is prefetched
+    pub progress: bool,
+
+    /// An `.npmrc` file to read scope registries and private registry auth tokens from
+    ///
+    /// Auth tokens must be given as a single `${VAR}`, which packages
+    /// read as they are fetched, rather than writing the token into the
+    /// generated file. This keeps the token out of the nix store, at the
+    /// cost of purity: the variable is passed into the fetch as one of
+    /// fetchurl's `netrcImpureEnvVars`, so must be set in the
+    /// environment of the nix daemon, or of nix itself for a
+    /// single user install. The hash still pins the fetched contents.
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen(skip))]
+    pub npmrc: Option<PathBuf>,
+
+    /// Leave out the packages which are only installed for the
+    /// `devDependencies` of the workspaces, for a production build
+    pub production: bool,
+
+    /// The names of git dependencies which should be fetched with their submodules
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen(skip))]
+    pub fetch_submodules: Vec<String>,
+
+    /// The names of git dependencies which should be cloned with their full history
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen(skip))]
+    pub deep_clone: Vec<String>,
+
+    /// The names of git dependencies which should be fetched with every
+    /// ref, for commits not reachable from the default branch
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen(skip))]
+    pub all_refs: Vec<String>,
+
+    /// The names of git dependencies which should keep their `.git` directory
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen(skip))]
+    pub leave_dot_git: Vec<String>,
+
+    /// The nix systems to keep platform specific packages for, or all if empty
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen(skip))]
+    pub target_systems: Vec<String>,
+
+    /// Globs of the only lockfile entries to convert, or all if empty
+    ///
+    /// This is meant for debugging, as the output is then only part of a `bun.nix`.
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen(skip))]
+    pub only: Vec<String>,
+
+    /// Globs of lockfile entries to leave out, also meant for debugging
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen(skip))]
+    pub exclude: Vec<String>,
+
+    /// The directory which `file:` and `workspace:` paths are relative to,
+    /// if it is not the current directory, wherever the lockfile is
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen(skip))]
+    pub root: Option<PathBuf>,
+
+    /// A file to write the generated nix expression to
+    ///
+    /// The file is replaced atomically, and left untouched if its
+    /// contents would not change.
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen(skip))]
+    pub output: Option<PathBuf>,
+
+    /// Compare the generated expression against `output`, failing
+    /// if they differ, rather than writing it
+    pub check: bool,
+
+    /// Parse the generated expression with `nix-instantiate --parse`,
+    /// failing if it is not valid nix, before it is written
+    pub validate: bool,
+
+    /// A file to also write every resolved package to, as JSON
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen(skip))]
+    pub json_output: Option<PathBuf>,
+
+    /// A file to also write a plain text report to, mapping each
+    /// lockfile entry to the fetcher and source it was converted to
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen(skip))]
+    pub report: Option<PathBuf>,
+
+    /// Also write the nix base-32 `sha256` of each prefetched package
+    /// as `passthru.sha256`, for tools which haven't adopted SRI hashes
+    ///
+    /// Packages fetched by nix builtins, which take no passthru
+    /// attributes, are left without one.
+    pub emit_sha256: bool,
+
+    /// Write the number of packages by fetcher, the number of live
+    /// prefetches and the time taken to stderr once converted
+    pub stats: bool,
+
+    /// Never run any package's lifecycle scripts, even those of
+    /// `trustedDependencies`, for a fully script free install
+    ///
+    /// Packages which build native code as they are installed
+    /// may then not work.
+    pub disable_lifecycle_scripts: bool,
+
+    /// Write each fetcher as an indented attribute set over several
+    /// lines, rather than compacted onto a single line
+    pub pretty: bool,
+
+    /// A directory of `*.nix_template` files to render each kind of
+    /// fetcher with, in place of the built in templates
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen(skip))]
+    pub template_dir: Option<PathBuf>,
+
+    /// The `node_modules` layout the packages should be installed with,
+    /// read from the project's `bunfig.toml` if not given
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen(skip))]
+    pub linker: Option<Linker>,
+
+    /// The directory the generated file will be written to, which `file:` and
+    /// `workspace:` paths are made relative to, if it is not the current directory
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen(skip))]
+    pub output_dir: Option<PathBuf>,
+}
+
+/// # Linker
+///
+/// The `node_modules` layout `bun install` creates, as set by
+/// `--linker` or the `install.linker` setting of `bunfig.toml`
+///
+/// ```rust
+/// use bun2nix::options::Linker;
+///
+/// assert_eq!("isolated".parse::<Linker>().unwrap(), Linker::Isolated);
+/// assert_eq!(Linker::Hoisted.to_string(), "hoisted");
+/// assert!("pnpm".parse::<Linker>().is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Linker {
+    /// Each package only sees its own dependencies, linked from a shared store
+    Isolated,
+    /// Every package is placed in a single, flat, `node_modules`
+    Hoisted,
+}
+
+impl FromStr for Linker {
+    type Err = Error;
+
+    fn from_str(linker: &str) -> Result<Self, Error> {
+        match linker {
+            "isolated" => Ok(Self::Isolated),
+            "hoisted" => Ok(Self::Hoisted),
+            linker => Err(Error::UnsupportedLinker(linker.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for Linker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Isolated => "isolated",
+            Self::Hoisted => "hoisted",
+        })
+    }
+}
+
+/// # Default Prefetch Jobs
+///
+/// The default number of prefetch workers, one per available CPU
+pub fn default_prefetch_jobs() -> usize {
+    thread::available_parallelism().map_or(1, NonZero::get)
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            copy_prefix: "./".to_owned(),
+            copy_excludes: Vec::new(),
+            gitlab_domain: DEFAULT_GITLAB_DOMAIN.to_owned(),
+            github_domain: DEFAULT_GITHUB_DOMAIN.to_owned(),
+            default_registry: None,
+            registry_mirrors: Vec::new(),
+            prefetch_jobs: default_prefetch_jobs(),
+            per_host_jobs: 0,
+            prefetch_retries: DEFAULT_PREFETCH_RETRIES,
+            prefetch_timeout: DEFAULT_PREFETCH_TIMEOUT,
+            prefetch_command: None,
+            proxy: None,
+            no_proxy: Vec::new(),
+            prefetch_cache: false,
+            previous_output: None,
+            frozen: false,
+            collect_all_errors: false,
+            strict: false,
+            log_level: LevelFilter::Warn,
+            dry_run: false,
+            progress: false,
+            npmrc: None,
+            production: false,
+            fetch_submodules: Vec::new(),
+            deep_clone: Vec::new(),
+            all_refs: Vec::new(),
+            leave_dot_git: Vec::new(),
+            target_systems: Vec::new(),
+            only: Vec::new(),
+            exclude: Vec::new(),
+            root: None,
+            output: None,
+            check: false,
+            validate: false,
+            json_output: None,
+            report: None,
+            emit_sha256: false,
+            stats: false,
+            disable_lifecycle_scripts: false,
+            pretty: true,
+            template_dir: None,
+            linker: None,
+            output_dir: None,
+        }
+    }
 }
 
 #[cfg(target_arch = "wasm32")]
@@ -20,6 +337,9 @@ impl Options {
     /// Constructor for `bun2nix` options
     #[wasm_bindgen(constructor)]
     pub fn new(copy_prefix: String) -> Self {
-        Self { copy_prefix }
+        Self {
+            copy_prefix,
+            ..Default::default()
+        }
     }
 }
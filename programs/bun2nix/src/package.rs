@@ -1,15 +1,21 @@
 //! This module holds the core implementation for the package type and related methods
 
 use std::{
+    collections::BTreeMap,
     fmt::Debug,
     hash::{Hash, Hasher},
 };
 
 use serde::Serialize;
 
+use crate::{
+    error::{Error, Result},
+    nix_expression::attribute_name,
+};
+
 mod fetcher;
 
-pub use fetcher::Fetcher;
+pub use fetcher::{DEFAULT_REGISTRY, Fetcher, FetcherKind};
 
 #[derive(Debug, Serialize, Clone)]
 #[serde(rename_all = "camelCase", default)]
@@ -23,6 +29,60 @@ pub struct Package {
 
     /// The fetch method to use for the package
     pub fetcher: Fetcher,
+
+    /// The SPDX license of the package, if it's metadata declares one
+    pub license: Option<String>,
+
+    /// The homepage of the package, if it's metadata declares one
+    pub homepage: Option<String>,
+
+    /// The source repository of the package, if it's metadata
+    /// declares one
+    pub repository: Option<String>,
+
+    /// The hash of a prefetched package in nix's base-32 `sha256`
+    /// form, if `options.emit_sha256` is set
+    pub sha256: Option<String>,
+
+    /// The integrity hash of an npm package, exactly as it is
+    /// written in the lockfile
+    pub integrity: Option<String>,
+
+    /// The lockfile relative path of a patch to apply to the package,
+    /// as listed in the lockfile's `patchedDependencies`
+    pub patch: Option<String>,
+
+    /// Whether bun runs the package's lifecycle scripts, as it
+    /// is listed in the lockfile's `trustedDependencies`
+    pub runs_scripts: bool,
+
+    /// The install time lifecycle scripts the package's metadata
+    /// declares, in the order bun runs them
+    ///
+    /// Bun's lockfiles don't record a package's scripts at present, so
+    /// this is empty unless the metadata has them, and is only written
+    /// out for reference. Whether any scripts run isn't decided by it.
+    pub lifecycle_scripts: Vec<String>,
+
+    /// The executables the package provides, keyed by the name
+    /// they are linked as in `node_modules/.bin`
+    pub bin: BTreeMap<String, String>,
+
+    /// The attribute names of the packages this package directly
+    /// depends on, sorted
+    pub dependencies: Vec<String>,
+
+    /// The peer dependencies the package declares, keyed by name,
+    /// with the version range each must satisfy
+    pub peer_dependencies: BTreeMap<String, String>,
+
+    /// The operating systems the package may be installed on,
+    /// as npm names them, or any if empty
+    pub os: Vec<String>,
+
+    /// The cpu architectures the package may be installed on,
+    /// as npm names them, or any if empty
+    pub cpu: Vec<String>,
 }
 
 impl Package {
@@ -31,7 +91,477 @@ impl Package {
     /// Creates a given package using it's name
     /// and fetcher information
     pub fn new(name: String, fetcher: Fetcher) -> Self {
-        Self { name, fetcher }
+        Self {
+            name,
+            fetcher,
+            license: None,
+            homepage: None,
+            repository: None,
+            sha256: None,
+            integrity: None,
+            patch: None,
+            runs_scripts: false,
+            lifecycle_scripts: Vec::new(),
+            bin: BTreeMap::new(),
+            dependencies: Vec::new(),
+            peer_dependencies: BTreeMap::new(),
+            os: Vec::new(),
+            cpu: Vec::new(),
+        }
+    }
+
+    /// # Nix Attribute Name
+    ///
+    /// The name this package is written under in `bun.nix`, which
+    /// is also the name its dependents refer to it by
+    ///
+    /// The name is quoted in `bun.nix` unless it is a valid bare nix
+    /// identifier, as decided by `nix_expression::escape_nix_attr`, so
+    /// the rules are kept minimal, and depend on nothing but the
+    /// package's name:
+    /// - The scope, `@` and `/` are kept as they are
+    /// - The version is kept as it is, dots, `+` build metadata and all
+    /// - `\`, `"` and `${` are escaped, so no name can end the quoted
+    ///   string early or start an interpolation
+    ///
+    /// Two packages therefore only share an attribute name when
+    /// they share a name, whatever order they are read in.
+    ///
+    /// ```rust
+    /// use bun2nix::{Package, package::Fetcher};
+    ///
+    /// let package = |name: &str| {
+    ///     let fetcher = Fetcher::CopyToStore {
+    ///         path: "packages/lib".to_owned(),
+    ///         exclude: Vec::new(),
+    ///     };
+    ///
+    ///     Package::new(name.to_owned(), fetcher)
+    /// };
+    ///
+    /// // Scoped names
+    /// assert_eq!(package("@types/node@22.13.5").nix_attr_name(), "@types/node@22.13.5");
+    ///
+    /// // Versions with dots and build metadata
+    /// assert_eq!(package("is-number@7.0.0").nix_attr_name(), "is-number@7.0.0");
+    /// assert_eq!(package("pkg@1.2.3+build.5").nix_attr_name(), "pkg@1.2.3+build.5");
+    ///
+    /// // Names which are only valid in nix when quoted, or need escaping within quotes
+    /// assert_eq!(package("github:colinhacks-zod-5bfc8f2").nix_attr_name(), "github:colinhacks-zod-5bfc8f2");
+    /// assert_eq!(package("tarball:https://example.com/a b.tgz").nix_attr_name(), "tarball:https://example.com/a b.tgz");
+    /// assert_eq!(package(r#"odd"name${x}\"#).nix_attr_name(), r#"odd\"name\${x}\\"#);
+    /// ```
+    pub fn nix_attr_name(&self) -> String {
+        attribute_name(&self.name)
+    }
+
+    /// # Validate
+    ///
+    /// Check the package can be written out as valid nix, so a broken
+    /// package fails with a description of what is wrong with it, rather
+    /// than as a nix evaluation error
+    ///
+    /// Every package needs a name, and a fetcher with the url, path or
+    /// repository to fetch from. Fetchers with a hash must have one in
+    /// SRI form, such as `sha512-...`.
+    ///
+    /// ```rust
+    /// use bun2nix::{Error, Package, package::Fetcher};
+    ///
+    /// let package = |name: &str, url: &str, hash: &str| {
+    ///     let fetcher = Fetcher::FetchUrl {
+    ///         url: url.to_owned(),
+    ///         urls: Vec::new(),
+    ///         hash: hash.to_owned(),
+    ///         name: None,
+    ///         auth_token_var: None,
+    ///     };
+    ///
+    ///     Package::new(name.to_owned(), fetcher)
+    /// };
+    /// let url = "https://registry.npmjs.org/is-number/-/is-number-7.0.0.tgz";
+    ///
+    /// assert!(package("is-number@7.0.0", url, "sha512-AAAA").validate().is_ok());
+    ///
+    /// let invalid = [
+    ///     package("", url, "sha512-AAAA"),
+    ///     package("is-number@7.0.0", "", "sha512-AAAA"),
+    ///     package("is-number@7.0.0", "is-number-7.0.0.tgz", "sha512-AAAA"),
+    ///     package("is-number@7.0.0", url, ""),
+    ///     package("is-number@7.0.0", url, "0mdqa9w1p6cmli6976v4wi0sw9r4p5prkj7lzfd1877wk11c9c73"),
+    ///     package("is-number@7.0.0", url, "md5-AAAA"),
+    /// ];
+    ///
+    /// for package in invalid {
+    ///     assert!(matches!(package.validate(), Err(Error::InvalidPackage { .. })));
+    /// }
+    ///
+    /// let err = package("is-number@7.0.0", url, "").validate().unwrap_err();
+    /// assert_eq!(
+    ///     err.to_string(),
+    ///     "`is-number@7.0.0` can't be written out, as it's hash '' is not an SRI hash, such as `sha512-...`"
+    /// );
+    /// ```
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |reason: String| Error::InvalidPackage {
+            name: self.name.clone(),
+            reason,
+        };
+
+        if self.name.is_empty() {
+            return Err(invalid("it has no name".to_owned()));
+        }
+
+        let required = match &self.fetcher {
+            Fetcher::FetchUrl { url, .. }
+            | Fetcher::FetchTarball { url, .. }
+            | Fetcher::FetchZip { url, .. } => vec![("url", url)],
+            Fetcher::FetchGit { url, rev, .. } => vec![("url", url), ("rev", rev)],
+            Fetcher::FetchGitHub {
+                owner, repo, rev, ..
+            }
+            | Fetcher::FetchFromGitLab {
+                owner, repo, rev, ..
+            }
+            | Fetcher::FetchFromBitbucket {
+                owner, repo, rev, ..
+            } => vec![("owner", owner), ("repo", repo), ("rev", rev)],
+            Fetcher::CopyToStore { path, .. } | Fetcher::SymlinkIntoStore { path } => {
+                vec![("path", path)]
+            }
+            Fetcher::LocalTarball { path, name, .. } => vec![("path", path), ("name", name)],
+        };
+
+        if let Some((field, _)) = required.iter().find(|(_, value)| value.is_empty()) {
+            return Err(invalid(format!("it's `{field}` is empty")));
+        }
+
+        // Urls fetched by nix, rather than git, must be absolute
+        if let Fetcher::FetchUrl { url, .. }
+        | Fetcher::FetchTarball { url, .. }
+        | Fetcher::FetchZip { url, .. } = &self.fetcher
+            && !url.contains("://")
+        {
+            return Err(invalid(format!("it's url '{url}' is not absolute")));
+        }
+
+        if let Some(hash) = self.fetcher.source_hash()
+            && !is_sri_hash(&hash)
+        {
+            return Err(invalid(format!(
+                "it's hash '{hash}' is not an SRI hash, such as `sha512-...`"
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// # With License
+    ///
+    /// Attach the package's SPDX license, to be written
+    /// out as `meta.license`
+    pub fn with_license(mut self, license: Option<String>) -> Self {
+        self.license = license;
+        self
+    }
+
+    /// # With Homepage
+    ///
+    /// Attach the package's homepage, to be written
+    /// out as `meta.homepage`
+    pub fn with_homepage(mut self, homepage: Option<String>) -> Self {
+        self.homepage = homepage;
+        self
+    }
+
+    /// # With Repository
+    ///
+    /// Attach the package's source repository, to be written
+    /// out as `passthru.repository`
+    pub fn with_repository(mut self, repository: Option<String>) -> Self {
+        self.repository = repository;
+        self
+    }
+
+    /// # With SHA256
+    ///
+    /// Attach the package's hash in nix's base-32 `sha256` form,
+    /// to be written out as `passthru.sha256`
+    ///
+    /// Packages fetched by `builtins.fetchTarball` or `builtins.path`
+    /// can't be given passthru attributes, so are left without one.
+    ///
+    /// ```rust
+    /// use bun2nix::{
+    ///     Options, Result, generate_bun_nix_with_prefetcher,
+    ///     lockfile::{Prefetch, Prefetcher},
+    /// };
+    ///
+    /// struct EmptyArchive;
+    ///
+    /// impl Prefetcher for EmptyArchive {
+    ///     fn prefetch(&self, _url: &str, _options: &Options) -> Result<Prefetch> {
+    ///         Ok(Prefetch::from_hash("sha256-47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=".to_owned()))
+    ///     }
+    /// }
+    ///
+    /// let lockfile = r#"{
+    ///   "lockfileVersion": 1,
+    ///   "packages": {
+    ///     "is-number": ["is-number@7.0.0", "", {}, "sha512-41Cifkg6e8TylSpdtTpeLVMqvSBEVzTttHvERD741+pnZ8ANv0004MRL43QKPDlK9cGvNp6NZWZUBlbGXYxxng=="],
+    ///     "zod": ["zod@github:colinhacks/zod#5bfc8f2", {}, "colinhacks-zod-5bfc8f2"],
+    ///   }
+    /// }"#;
+    ///
+    /// let options = Options {
+    ///     emit_sha256: true,
+    ///     ..Default::default()
+    /// };
+    /// let nix = generate_bun_nix_with_prefetcher(lockfile, &options, &EmptyArchive).unwrap();
+    ///
+    /// // Only prefetched packages have a sha256 hash to give
+    /// assert!(nix.contains(r#"passthru.sha256 = "0mdqa9w1p6cmli6976v4wi0sw9r4p5prkj7lzfd1877wk11c9c73";"#));
+    /// assert_eq!(nix.matches("passthru.sha256").count(), 1);
+    ///
+    /// let options = Options {
+    ///     emit_sha256: false,
+    ///     ..options
+    /// };
+    /// let nix = generate_bun_nix_with_prefetcher(lockfile, &options, &EmptyArchive).unwrap();
+    ///
+    /// assert!(!nix.contains("passthru.sha256"));
+    /// ```
+    pub fn with_sha256(mut self, sha256: Option<String>) -> Self {
+        self.sha256 = sha256;
+        self
+    }
+
+    /// # With Integrity
+    ///
+    /// Attach the integrity hash of an npm package, as it is written in
+    /// the lockfile, to be written out as `passthru.integrity` for tools
+    /// which cross check packages against their registry
+    ///
+    /// This is kept apart from the SRI hash the package is fetched with.
+    ///
+    /// ```rust
+    /// use bun2nix::{Lockfile, Options};
+    ///
+    /// let lockfile: Lockfile = r#"{
+    ///   "lockfileVersion": 1,
+    ///   "packages": {
+    ///     "is-number": ["is-number@7.0.0", "", {}, "sha512-41Cifkg6e8TylSpdtTpeLVMqvSBEVzTttHvERD741+pnZ8ANv0004MRL43QKPDlK9cGvNp6NZWZUBlbGXYxxng=="],
+    ///     "zod": ["zod@github:colinhacks/zod#5bfc8f2", {}, "colinhacks-zod-5bfc8f2"],
+    ///   }
+    /// }"#.parse().unwrap();
+    ///
+    /// let options = Options {
+    ///     dry_run: true,
+    ///     ..Default::default()
+    /// };
+    /// let packages = lockfile.packages(&options).unwrap();
+    /// let nix = packages[0].to_string();
+    ///
+    /// assert!(nix.contains(r#"hash = "sha512-41Cifkg6e8TylSpdtTpeLVMqvSBEVzTttHvERD741+pnZ8ANv0004MRL43QKPDlK9cGvNp6NZWZUBlbGXYxxng==";"#));
+    /// assert!(nix.contains(r#"passthru.integrity = "sha512-41Cifkg6e8TylSpdtTpeLVMqvSBEVzTttHvERD741+pnZ8ANv0004MRL43QKPDlK9cGvNp6NZWZUBlbGXYxxng==";"#));
+    ///
+    /// // Only npm packages have an integrity hash in the lockfile
+    /// assert!(!packages[1].to_string().contains("passthru.integrity"));
+    /// ```
+    pub fn with_integrity(mut self, integrity: Option<String>) -> Self {
+        self.integrity = integrity;
+        self
+    }
+
+    /// # With Patch
+    ///
+    /// Attach a patch file to be applied to the package
+    /// before it is placed in bun's cache
+    pub fn with_patch(mut self, patch: Option<String>) -> Self {
+        self.patch = patch;
+        self
+    }
+
+    /// # With Runs Scripts
+    ///
+    /// Mark whether bun will run the package's lifecycle
+    /// scripts, to be written out as `passthru.runsScripts`
+    ///
+    /// ```rust
+    /// use bun2nix::{Options, convert_lockfile_to_nix_expression};
+    ///
+    /// let lockfile = r#"{
+    ///   "lockfileVersion": 1,
+    ///   "trustedDependencies": ["esbuild"],
+    ///   "packages": {
+    ///     "esbuild": ["esbuild@0.25.0", "", {}, "sha512-41Cifkg6e8TylSpdtTpeLVMqvSBEVzTttHvERD741+pnZ8ANv0004MRL43QKPDlK9cGvNp6NZWZUBlbGXYxxng=="],
+    ///     "is-number": ["is-number@7.0.0", "", {}, "sha512-41Cifkg6e8TylSpdtTpeLVMqvSBEVzTttHvERD741+pnZ8ANv0004MRL43QKPDlK9cGvNp6NZWZUBlbGXYxxng=="],
+    ///   }
+    /// }"#;
+    ///
+    /// let options = Options::default();
+    /// let nix = convert_lockfile_to_nix_expression(lockfile.to_owned(), options).unwrap();
+    ///
+    /// assert_eq!(nix.matches("passthru.runsScripts = true;").count(), 1);
+    ///
+    /// // Unless lifecycle scripts are disabled altogether
+    /// let options = Options {
+    ///     disable_lifecycle_scripts: true,
+    ///     ..Default::default()
+    /// };
+    /// let nix = convert_lockfile_to_nix_expression(lockfile.to_owned(), options).unwrap();
+    ///
+    /// assert!(!nix.contains("passthru.runsScripts"));
+    /// assert!(nix.contains("disableLifecycleScripts = true;"));
+    /// ```
+    pub fn with_runs_scripts(mut self, runs_scripts: bool) -> Self {
+        self.runs_scripts = runs_scripts;
+        self
+    }
+
+    /// # With Lifecycle Scripts
+    ///
+    /// Attach the install time lifecycle scripts the package
+    /// declares, to be written out as `passthru.lifecycleScripts`
+    ///
+    /// These are only found in metadata which lists the package's
+    /// `scripts`, which bun doesn't write into `bun.lock` itself.
+    ///
+    /// ```rust
+    /// use bun2nix::{Options, convert_lockfile_to_nix_expression};
+    ///
+    /// let lockfile = r#"{
+    ///   "lockfileVersion": 1,
+    ///   "trustedDependencies": ["esbuild"],
+    ///   "packages": {
+    ///     "esbuild": ["esbuild@0.25.0", "", { "scripts": { "test": "node test.js", "postinstall": "node install.js", "preinstall": "node check.js" } }, "sha512-41Cifkg6e8TylSpdtTpeLVMqvSBEVzTttHvERD741+pnZ8ANv0004MRL43QKPDlK9cGvNp6NZWZUBlbGXYxxng=="],
+    ///     "is-number": ["is-number@7.0.0", "", {}, "sha512-41Cifkg6e8TylSpdtTpeLVMqvSBEVzTttHvERD741+pnZ8ANv0004MRL43QKPDlK9cGvNp6NZWZUBlbGXYxxng=="],
+    ///   }
+    /// }"#;
+    ///
+    /// let options = Options::default();
+    /// let nix = convert_lockfile_to_nix_expression(lockfile.to_owned(), options).unwrap();
+    ///
+    /// assert!(nix.contains(r#"passthru.lifecycleScripts = [ "preinstall" "postinstall" ];"#));
+    /// assert_eq!(nix.matches("passthru.lifecycleScripts").count(), 1);
+    /// ```
+    pub fn with_lifecycle_scripts(mut self, lifecycle_scripts: Vec<String>) -> Self {
+        self.lifecycle_scripts = lifecycle_scripts;
+        self
+    }
+
+    /// # With Bin
+    ///
+    /// Attach the executables the package provides, to be
+    /// written out as `passthru.bin`
+    ///
+    /// ```rust
+    /// use bun2nix::{Options, convert_lockfile_to_nix_expression};
+    ///
+    /// let lockfile = r#"{
+    ///   "lockfileVersion": 1,
+    ///   "packages": {
+    ///     "typescript": ["typescript@5.9.3", "", { "bin": { "tsc": "bin/tsc", "tsserver": "bin/tsserver" } }, "sha512-jl1vZzPDinLr9eUt3J/t7V6FgNEw9QjvBPdysz9KfQDD41fQrC2Y4vKQdiaUpFT4bXlb1RHhLpp8wtm6M5TgSw=="],
+    ///   }
+    /// }"#;
+    ///
+    /// let options = Options::default();
+    /// let nix = convert_lockfile_to_nix_expression(lockfile.to_owned(), options).unwrap();
+    ///
+    /// assert!(nix.contains(r#""tsc" = "bin/tsc";"#));
+    /// assert!(nix.contains(r#""tsserver" = "bin/tsserver";"#));
+    /// ```
+    pub fn with_bin(mut self, bin: BTreeMap<String, String>) -> Self {
+        self.bin = bin;
+        self
+    }
+
+    /// # With Dependencies
+    ///
+    /// Attach the packages this package directly depends on, to be
+    /// written out as `passthru.dependencies`
+    ///
+    /// When deserializing a lockfile, these begin as the names of the
+    /// dependencies and are replaced with the attribute names of the
+    /// packages they resolve to once every package is known.
+    ///
+    /// ```rust
+    /// use bun2nix::{Options, convert_lockfile_to_nix_expression};
+    ///
+    /// let lockfile = r#"{
+    ///   "lockfileVersion": 1,
+    ///   "packages": {
+    ///     "@types/node": ["@types/node@22.13.5", "", { "dependencies": { "undici-types": "~6.20.0" } }, "sha512-+lTU0PxZXn0Dr1NBtC7Y8cR21AJr87dLLU953CWA6pMxxv/UDc7jYAY90upcrie1nRcD6XNG5HOYEDtgW5TxAg=="],
+    ///     "undici-types": ["undici-types@6.21.0", "", {}, "sha512-iwDZqg0QAGrg9Rav5H4n0M64c3mkR59cJ6wQp+7C4nI0gsmExaedaYLNO44eT4AtBBwjbTiGPMlt2Md0T9H9JQ=="],
+    ///     "@types/node/undici-types": ["undici-types@6.20.0", "", {}, "sha512-Ny6QZ2Nju20vw1SRHe3d9jVu6gJ+4e3+MMpqu7pqE5HT6WsTSlce++GQmK5UXS8mzV8DSYHrQH+Xrf2jVcuKNg=="],
+    ///   }
+    /// }"#;
+    ///
+    /// let options = Options::default();
+    /// let nix = convert_lockfile_to_nix_expression(lockfile.to_owned(), options).unwrap();
+    ///
+    /// // The nested version is the one `@types/node` resolves to
+    /// assert!(nix.contains(r#"passthru.dependencies = [ "undici-types@6.20.0" ];"#));
+    /// ```
+    pub fn with_dependencies(mut self, dependencies: Vec<String>) -> Self {
+        self.dependencies = dependencies;
+        self
+    }
+
+    /// # With Peer Dependencies
+    ///
+    /// Attach the peer dependencies the package declares, to be
+    /// written out as `passthru.peerDependencies`, so a peer which
+    /// wasn't installed alongside it is easier to track down
+    ///
+    /// ```rust
+    /// use bun2nix::{Options, convert_lockfile_to_nix_expression};
+    ///
+    /// let lockfile = r#"{
+    ///   "lockfileVersion": 1,
+    ///   "packages": {
+    ///     "react-dom": ["react-dom@19.0.0", "", { "peerDependencies": { "react": "^19.0.0" } }, "sha512-4GV5sHFG0e/0AD4X+ySy6UJd3jVl1iNsNHdpad0qhABJ11twS3TTBnseqsKurKcsNqCEFeGL3uLpVChpIO3QfQ=="],
+    ///     "react": ["react@19.0.0", "", {}, "sha512-V8AVnmPIICiWpGfm6GLzCR/W5FXLchHop40W4nXBmdlEceh16rCN8O8LNWm5bh5XUX91fh7KpA+W0TgMKmgTpQ=="],
+    ///   }
+    /// }"#;
+    ///
+    /// let options = Options::default();
+    /// let nix = convert_lockfile_to_nix_expression(lockfile.to_owned(), options).unwrap();
+    ///
+    /// assert!(nix.contains(r#""react" = "^19.0.0";"#));
+    /// assert_eq!(nix.matches("passthru.peerDependencies").count(), 1);
+    /// ```
+    pub fn with_peer_dependencies(mut self, peer_dependencies: BTreeMap<String, String>) -> Self {
+        self.peer_dependencies = peer_dependencies;
+        self
+    }
+
+    /// # With Platforms
+    ///
+    /// Attach the package's `os` and `cpu` constraints, to be written
+    /// out as `passthru.os` and `passthru.cpu` when present
+    ///
+    /// ```rust
+    /// use bun2nix::{Options, convert_lockfile_to_nix_expression};
+    ///
+    /// let lockfile = r#"{
+    ///   "lockfileVersion": 1,
+    ///   "packages": {
+    ///     "@esbuild/linux-x64": ["@esbuild/linux-x64@0.25.0", "", { "os": "linux", "cpu": "x64" }, "sha512-+lTU0PxZXn0Dr1NBtC7Y8cR21AJr87dLLU953CWA6pMxxv/UDc7jYAY90upcrie1nRcD6XNG5HOYEDtgW5TxAg=="],
+    ///     "is-number": ["is-number@7.0.0", "", {}, "sha512-41Cifkg6e8TylSpdtTpeLVMqvSBEVzTttHvERD741+pnZ8ANv0004MRL43QKPDlK9cGvNp6NZWZUBlbGXYxxng=="],
+    ///   }
+    /// }"#;
+    ///
+    /// let options = Options::default();
+    /// let nix = convert_lockfile_to_nix_expression(lockfile.to_owned(), options).unwrap();
+    ///
+    /// assert_eq!(nix.matches(r#"passthru.os = [ "linux" ];"#).count(), 1);
+    /// assert_eq!(nix.matches(r#"passthru.cpu = [ "x64" ];"#).count(), 1);
+    /// ```
+    pub fn with_platforms(mut self, os: Vec<String>, cpu: Vec<String>) -> Self {
+        self.os = os;
+        self.cpu = cpu;
+        self
     }
 }
 
@@ -61,3 +591,17 @@ impl Ord for Package {
         (&self.name, &self.fetcher).cmp(&(&other.name, &other.fetcher))
     }
 }
+
+/// Whether a hash is in SRI form, a supported algorithm followed by a
+/// base64 digest, whatever the digest's length
+fn is_sri_hash(hash: &str) -> bool {
+    let Some((algorithm, digest)) = hash.split_once('-') else {
+        return false;
+    };
+
+    ["sha1", "sha256", "sha512"].contains(&algorithm)
+        && !digest.is_empty()
+        && digest
+            .chars()
+            .all(|char| char.is_ascii_alphanumeric() || matches!(char, '+' | '/' | '='))
+}
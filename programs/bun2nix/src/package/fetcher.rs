@@ -1,6 +1,9 @@
 //! This module holds the implementation for data about a given nix fetcher type
 
-use std::{fmt::Debug, hash::Hash};
+use std::{
+    fmt::{self, Debug},
+    hash::Hash,
+};
 
 use askama::Template;
 use serde::{Deserialize, Serialize};
@@ -8,6 +11,9 @@ use serde::{Deserialize, Serialize};
 use crate::{
     Options,
     error::{Error, Result},
+    lockfile::strip_name_prefix,
+    npmrc::Npmrc,
+    options::DEFAULT_GITHUB_DOMAIN,
 };
 
 #[derive(Debug, Serialize, Deserialize, Clone, Eq, Ord, PartialEq, PartialOrd, Hash, Template)]
@@ -16,15 +22,40 @@ use crate::{
 /// Nix-translated fetcher for a given package
 pub enum Fetcher {
     /// A package which must be retrieved with nix's `pkgs.fetchurl`
+    ///
+    /// The url is also kept as `passthru.url`, so the source of each
+    /// package can be read back from the fetched derivations:
+    ///
+    /// ```rust
+    /// use askama::Template;
+    /// use bun2nix::package::Fetcher;
+    ///
+    /// let fetcher = Fetcher::FetchUrl {
+    ///     url: "https://registry.npmjs.org/is-number/-/is-number-7.0.0.tgz".to_owned(),
+    ///     urls: vec!["https://npm.example.com/mirror/is-number/-/is-number-7.0.0.tgz".to_owned()],
+    ///     hash: "sha512-AAAA".to_owned(),
+    ///     name: Some("is-number-7.0.0.tgz".to_owned()),
+    ///     auth_token_var: None,
+    /// };
+    ///
+    /// assert!(fetcher.render().unwrap().contains(
+    ///     r#"passthru.url = "https://registry.npmjs.org/is-number/-/is-number-7.0.0.tgz";"#
+    /// ));
+    /// ```
     #[template(path = "fetchurl.nix_template")]
     FetchUrl {
         /// The url to fetch the package from
         url: String,
+        /// Mirrors of `url` to try first, in order, before falling back to it
+        urls: Vec<String>,
         /// The hash of the downloaded results
         /// This can be derived from the bun lockfile
         hash: String,
         /// Optional explicit filename (used for non-default registries to ensure .tgz extension)
         name: Option<String>,
+        /// Optional environment variable to read a private registry's auth
+        /// token from when the package is fetched, as given in an `.npmrc` file
+        auth_token_var: Option<String>,
     },
     /// A package which must be retrieved with nix's `pkgs.fetchgit`
     #[template(path = "fetchgit.nix_template")]
@@ -36,8 +67,49 @@ pub enum Fetcher {
         /// The hash of the downloaded results
         /// This must be calculated via nix-prefetch
         hash: String,
+        /// Whether the repo's submodules are fetched along with it
+        fetch_submodules: bool,
+        /// Whether the repo's full history is cloned, rather than only the commit
+        deep_clone: bool,
+        /// Whether every ref is fetched, for commits not reachable from the default branch
+        all_refs: bool,
+        /// Whether the `.git` directory is kept, which `fetchgit` requires of deep clones
+        leave_dot_git: bool,
+        /// The subdirectory of the repo the package lives in, if not the root
+        dir: Option<String>,
+        /// Optional explicit store path name, `<repo>-<rev>` when deserialized
+        name: Option<String>,
     },
     /// A package which must be retrieved with nix's `pkgs.fetchFromGitHub`
+    ///
+    /// Repos on a GitHub Enterprise instance are fetched from it's
+    /// domain, given to `fetchFromGitHub` as `githubBase`:
+    ///
+    /// ```rust
+    /// use askama::Template;
+    /// use bun2nix::package::Fetcher;
+    ///
+    /// let fetcher = |domain: &str| Fetcher::FetchGitHub {
+    ///     owner: "acme".to_owned(),
+    ///     repo: "ui".to_owned(),
+    ///     rev: "5bfc8f2".to_owned(),
+    ///     hash: "sha256-AAAA".to_owned(),
+    ///     domain: domain.to_owned(),
+    ///     dir: None,
+    ///     name: None,
+    /// };
+    ///
+    /// let enterprise = fetcher("github.acme.com");
+    /// assert!(enterprise.render().unwrap().contains(r#"githubBase = "github.acme.com";"#));
+    /// assert_eq!(
+    ///     enterprise.prefetch_url().as_deref(),
+    ///     Some("github:acme/ui?ref=5bfc8f2&host=github.acme.com")
+    /// );
+    ///
+    /// let public = fetcher("github.com");
+    /// assert!(!public.render().unwrap().contains("githubBase"));
+    /// assert_eq!(public.prefetch_url().as_deref(), Some("github:acme/ui?ref=5bfc8f2"));
+    /// ```
     #[template(path = "fetchgithub.nix_template")]
     FetchGitHub {
         /// The owner of the repo to fetch from
@@ -49,6 +121,40 @@ pub enum Fetcher {
         /// The hash of the downloaded results
         /// This must be calculated via nix-prefetch
         hash: String,
+        /// The domain of the github instance to fetch from
+        domain: String,
+        /// The subdirectory of the repo the package lives in, if not the root
+        dir: Option<String>,
+        /// Optional explicit store path name, `<repo>-<rev>` when deserialized
+        name: Option<String>,
+    },
+    /// A package which must be retrieved with nix's `pkgs.fetchFromGitLab`
+    #[template(path = "fetchgitlab.nix_template")]
+    FetchFromGitLab {
+        /// The owner (group and any subgroups) of the repo to fetch from
+        owner: String,
+        /// The repo to fetch
+        repo: String,
+        /// The git ref to fetch
+        rev: String,
+        /// The hash of the downloaded results
+        /// This must be calculated via nix-prefetch
+        hash: String,
+        /// The domain of the gitlab instance to fetch from
+        domain: String,
+    },
+    /// A package which must be retrieved with nix's `pkgs.fetchFromBitbucket`
+    #[template(path = "fetchbitbucket.nix_template")]
+    FetchFromBitbucket {
+        /// The owner of the repo to fetch from
+        owner: String,
+        /// The repo to fetch
+        repo: String,
+        /// The git ref to fetch
+        rev: String,
+        /// The hash of the downloaded results
+        /// This must be calculated via nix-prefetch
+        hash: String,
     },
     /// A package which must be retrieved with nix's `pkgs.fetchtarball`
     #[template(path = "fetchtarball.nix_template")]
@@ -59,18 +165,571 @@ pub enum Fetcher {
         /// This must be calculated via nix-prefetch
         hash: String,
     },
+    /// A zip archive package which must be retrieved with nix's `pkgs.fetchzip`
+    #[template(path = "fetchzip.nix_template")]
+    FetchZip {
+        /// The url to fetch the package from
+        url: String,
+        /// The hash of the unpacked results
+        /// This must be calculated via nix-prefetch
+        hash: String,
+    },
     /// A package can be a path copied to the store directly
     #[template(path = "copy-to-store.nix_template")]
     CopyToStore {
         /// The path from the root to copy to the store
         path: String,
+        /// Globs of files and directories to leave out of the copy
+        exclude: Vec<String>,
+    },
+    /// A package which is symlinked to it's source path, rather than copied
+    ///
+    /// The link is to the source as added to the store, as a sandboxed
+    /// build can't read it anywhere else. For development without the
+    /// sandbox, evaluating with `--impure` and `BUN2NIX_LINK_IN_PLACE=1`
+    /// links to the source where it is instead.
+    ///
+    /// ```rust
+    /// use askama::Template;
+    /// use bun2nix::package::Fetcher;
+    ///
+    /// let fetcher = Fetcher::SymlinkIntoStore {
+    ///     path: "packages/lib".to_owned(),
+    /// };
+    /// let nix = fetcher.render().unwrap();
+    ///
+    /// assert!(nix.contains("builtins.path { path = ./packages/lib; }"));
+    /// assert!(nix.contains("toString ./packages/lib"));
+    ///
+    /// // The same as the `linkPackageBuilds` check builds, bar indentation
+    /// let words = |nix: &str| nix.split_whitespace().collect::<Vec<_>>().join(" ");
+    /// let check = std::fs::read_to_string(concat!(
+    ///     env!("CARGO_MANIFEST_DIR"),
+    ///     "/../../nix/checks/link-package-builds.nix"
+    /// ))
+    /// .unwrap();
+    /// let fetcher = Fetcher::SymlinkIntoStore {
+    ///     path: "link-package-builds/my-lib".to_owned(),
+    /// };
+    ///
+    /// assert!(words(&check).contains(&words(&fetcher.render().unwrap())));
+    /// ```
+    #[template(path = "symlink-into-store.nix_template")]
+    SymlinkIntoStore {
+        /// The path from the root to link to
+        path: String,
     },
+    /// A local tarball package, copied to the store under a `.tgz`
+    /// name so it is unpacked like one which was downloaded
+    ///
+    /// ```rust
+    /// use askama::Template;
+    /// use bun2nix::package::Fetcher;
+    ///
+    /// let fetcher = Fetcher::LocalTarball {
+    ///     path: "vendor/foo-1.0.0.tar.gz".to_owned(),
+    ///     name: "foo-1.0.0.tgz".to_owned(),
+    ///     hash: "sha256-AAAA".to_owned(),
+    /// };
+    /// let nix = fetcher.render().unwrap();
+    ///
+    /// assert!(nix.contains("path = ./vendor/foo-1.0.0.tar.gz;"));
+    /// assert!(nix.contains(r#"name = "foo-1.0.0.tgz";"#));
+    /// assert!(nix.contains(r#"sha256 = "sha256-AAAA";"#));
+    /// ```
+    #[template(path = "local-tarball.nix_template")]
+    LocalTarball {
+        /// The path from the root of the tarball, or its absolute path
+        /// for a `file://` url
+        path: String,
+        /// The store path name, the tarball's file name ending in `.tgz`
+        name: String,
+        /// The hash of the tarball's contents
+        /// This must be calculated via nix-prefetch
+        hash: String,
+    },
+}
+
+/// # Fetcher Kind
+///
+/// The kind of a fetcher, without any of its source, for classifying
+/// packages by how they are fetched
+///
+/// Displays as the name of the nix function used to fetch it.
+///
+/// ```rust
+/// use bun2nix::package::{Fetcher, FetcherKind};
+///
+/// let git = |rev: &str| Fetcher::FetchGit {
+///     url: "https://example.com/repo.git".to_owned(),
+///     rev: rev.to_owned(),
+///     hash: String::new(),
+///     fetch_submodules: false,
+///     deep_clone: false,
+///     all_refs: false,
+///     leave_dot_git: false,
+///     dir: None,
+///     name: None,
+/// };
+/// let url = "https://example.com/tiny-1.0.0.tgz".to_owned();
+/// let hash = String::new();
+/// let (owner, repo, rev) = ("acme".to_owned(), "tiny".to_owned(), "a1b2c3".to_owned());
+///
+/// let kinds = [
+///     (Fetcher::new_tarball_with_integrity(url.clone(), hash.clone()), FetcherKind::Url, "fetchurl"),
+///     (git("a1b2c3"), FetcherKind::Git, "fetchgit"),
+///     (
+///         Fetcher::FetchGitHub { owner: owner.clone(), repo: repo.clone(), rev: rev.clone(), hash: hash.clone(), domain: "github.com".to_owned(), dir: None, name: None },
+///         FetcherKind::GitHub,
+///         "fetchFromGitHub",
+///     ),
+///     (
+///         Fetcher::FetchFromGitLab { owner: owner.clone(), repo: repo.clone(), rev: rev.clone(), hash: hash.clone(), domain: "gitlab.com".to_owned() },
+///         FetcherKind::GitLab,
+///         "fetchFromGitLab",
+///     ),
+///     (
+///         Fetcher::FetchFromBitbucket { owner, repo, rev, hash: hash.clone() },
+///         FetcherKind::Bitbucket,
+///         "fetchFromBitbucket",
+///     ),
+///     (Fetcher::FetchTarball { url: url.clone(), hash: hash.clone() }, FetcherKind::Tarball, "builtins.fetchTarball"),
+///     (Fetcher::FetchZip { url, hash }, FetcherKind::Zip, "fetchzip"),
+///     (
+///         Fetcher::CopyToStore { path: "packages/lib".to_owned(), exclude: Vec::new() },
+///         FetcherKind::CopyToStore,
+///         "copyPathToStore",
+///     ),
+///     (
+///         Fetcher::SymlinkIntoStore { path: "packages/lib".to_owned() },
+///         FetcherKind::SymlinkIntoStore,
+///         "runCommandLocal",
+///     ),
+///     (
+///         Fetcher::LocalTarball { path: "vendor/foo.tgz".to_owned(), name: "foo.tgz".to_owned(), hash: String::new() },
+///         FetcherKind::LocalTarball,
+///         "builtins.path",
+///     ),
+/// ];
+///
+/// for (fetcher, kind, function) in kinds {
+///     assert_eq!(fetcher.kind(), kind);
+///     assert_eq!(kind.to_string(), function);
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum FetcherKind {
+    /// Fetched with `fetchurl`
+    Url,
+    /// Fetched with `fetchgit`
+    Git,
+    /// Fetched with `fetchFromGitHub`
+    GitHub,
+    /// Fetched with `fetchFromGitLab`
+    GitLab,
+    /// Fetched with `fetchFromBitbucket`
+    Bitbucket,
+    /// Fetched with `builtins.fetchTarball`
+    Tarball,
+    /// Fetched with `fetchzip`
+    Zip,
+    /// Copied from a local path with `copyPathToStore`
+    CopyToStore,
+    /// Symlinked to a local path with `runCommandLocal`
+    SymlinkIntoStore,
+    /// Copied from a local tarball with `builtins.path`
+    LocalTarball,
+}
+
+impl FetcherKind {
+    /// Every kind of fetcher
+    pub const ALL: [Self; 10] = [
+        Self::Url,
+        Self::Git,
+        Self::GitHub,
+        Self::GitLab,
+        Self::Bitbucket,
+        Self::Tarball,
+        Self::Zip,
+        Self::CopyToStore,
+        Self::SymlinkIntoStore,
+        Self::LocalTarball,
+    ];
+
+    /// # Template Name
+    ///
+    /// The name of the template this kind of fetcher is rendered with
+    pub fn template_name(self) -> &'static str {
+        match self {
+            Self::Url => "fetchurl.nix_template",
+            Self::Git => "fetchgit.nix_template",
+            Self::GitHub => "fetchgithub.nix_template",
+            Self::GitLab => "fetchgitlab.nix_template",
+            Self::Bitbucket => "fetchbitbucket.nix_template",
+            Self::Tarball => "fetchtarball.nix_template",
+            Self::Zip => "fetchzip.nix_template",
+            Self::CopyToStore => "copy-to-store.nix_template",
+            Self::SymlinkIntoStore => "symlink-into-store.nix_template",
+            Self::LocalTarball => "local-tarball.nix_template",
+        }
+    }
+
+    /// # Nix Function
+    ///
+    /// The name of the nix function used by this kind of fetcher
+    pub fn nix_function(self) -> &'static str {
+        match self {
+            Self::Url => "fetchurl",
+            Self::Git => "fetchgit",
+            Self::GitHub => "fetchFromGitHub",
+            Self::GitLab => "fetchFromGitLab",
+            Self::Bitbucket => "fetchFromBitbucket",
+            Self::Tarball => "builtins.fetchTarball",
+            Self::Zip => "fetchzip",
+            Self::CopyToStore => "copyPathToStore",
+            Self::SymlinkIntoStore => "runCommandLocal",
+            Self::LocalTarball => "builtins.path",
+        }
+    }
+}
+
+impl fmt::Display for FetcherKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.nix_function())
+    }
 }
 
 /// The default NPM registry URL
 pub const DEFAULT_REGISTRY: &str = "https://registry.npmjs.org/";
 
 impl Fetcher {
+    /// # Fetcher Kind
+    ///
+    /// The kind of this fetcher, which displays as the
+    /// name of the nix function it uses
+    pub fn kind(&self) -> FetcherKind {
+        match self {
+            Self::FetchUrl { .. } => FetcherKind::Url,
+            Self::FetchGit { .. } => FetcherKind::Git,
+            Self::FetchGitHub { .. } => FetcherKind::GitHub,
+            Self::FetchFromGitLab { .. } => FetcherKind::GitLab,
+            Self::FetchFromBitbucket { .. } => FetcherKind::Bitbucket,
+            Self::FetchTarball { .. } => FetcherKind::Tarball,
+            Self::FetchZip { .. } => FetcherKind::Zip,
+            Self::CopyToStore { .. } => FetcherKind::CopyToStore,
+            Self::SymlinkIntoStore { .. } => FetcherKind::SymlinkIntoStore,
+            Self::LocalTarball { .. } => FetcherKind::LocalTarball,
+        }
+    }
+
+    /// # Fetcher Source
+    ///
+    /// A short description of where this fetcher retrieves the package
+    /// from, such as it's url or repo and ref, for use in messages
+    pub fn source(&self) -> String {
+        match self {
+            Self::FetchUrl { url, .. }
+            | Self::FetchTarball { url, .. }
+            | Self::FetchZip { url, .. } => url.clone(),
+            Self::FetchGit { url, rev, .. } => format!("{url}#{rev}"),
+            Self::FetchGitHub {
+                owner,
+                repo,
+                rev,
+                domain,
+                ..
+            } if domain != DEFAULT_GITHUB_DOMAIN => {
+                format!("https://{domain}/{owner}/{repo}#{rev}")
+            }
+            Self::FetchGitHub {
+                owner, repo, rev, ..
+            } => format!("github:{owner}/{repo}#{rev}"),
+            Self::FetchFromGitLab {
+                domain,
+                owner,
+                repo,
+                rev,
+                ..
+            } => format!("https://{domain}/{owner}/{repo}#{rev}"),
+            Self::FetchFromBitbucket {
+                owner, repo, rev, ..
+            } => format!("bitbucket:{owner}/{repo}#{rev}"),
+            Self::CopyToStore { path, .. }
+            | Self::SymlinkIntoStore { path }
+            | Self::LocalTarball { path, .. } => path.clone(),
+        }
+    }
+
+    /// # Prefetch Url
+    ///
+    /// The url `nix flake prefetch` is given to hash this fetcher's
+    /// source, or `None` if its hash is known without prefetching
+    ///
+    /// Every part of the source is in the url, so a change
+    /// of url or rev is always a change of prefetch url.
+    ///
+    /// ```rust
+    /// use askama::Template;
+    /// use bun2nix::package::Fetcher;
+    ///
+    /// let fetcher = Fetcher::FetchGit {
+    ///     url: "https://example.com/repo.git".to_owned(),
+    ///     rev: "5bfc8f2a1b2c3d4e5f60718293a4b5c6d7e8f901".to_owned(),
+    ///     hash: String::new(),
+    ///     fetch_submodules: true,
+    ///     deep_clone: false,
+    ///     all_refs: false,
+    ///     leave_dot_git: false,
+    ///     dir: None,
+    ///     name: None,
+    /// };
+    ///
+    /// assert_eq!(
+    ///     fetcher.prefetch_url().as_deref(),
+    ///     Some("git+https://example.com/repo.git?rev=5bfc8f2a1b2c3d4e5f60718293a4b5c6d7e8f901&submodules=1&shallow=1")
+    /// );
+    ///
+    /// // Commits off the default branch need every ref fetched to be found
+    /// let fetcher = Fetcher::FetchGit {
+    ///     url: "https://example.com/repo.git".to_owned(),
+    ///     rev: "5bfc8f2a1b2c3d4e5f60718293a4b5c6d7e8f901".to_owned(),
+    ///     hash: String::new(),
+    ///     fetch_submodules: false,
+    ///     deep_clone: false,
+    ///     all_refs: true,
+    ///     leave_dot_git: false,
+    ///     dir: None,
+    ///     name: None,
+    /// };
+    ///
+    /// assert_eq!(
+    ///     fetcher.prefetch_url().as_deref(),
+    ///     Some("git+https://example.com/repo.git?rev=5bfc8f2a1b2c3d4e5f60718293a4b5c6d7e8f901&allRefs=1&shallow=1")
+    /// );
+    ///
+    /// // Deep clones keep their `.git` directory, which is then part of the hash
+    /// let fetcher = Fetcher::FetchGit {
+    ///     url: "https://example.com/repo.git".to_owned(),
+    ///     rev: "5bfc8f2a1b2c3d4e5f60718293a4b5c6d7e8f901".to_owned(),
+    ///     hash: String::new(),
+    ///     fetch_submodules: false,
+    ///     deep_clone: true,
+    ///     all_refs: false,
+    ///     leave_dot_git: true,
+    ///     dir: None,
+    ///     name: None,
+    /// };
+    ///
+    /// assert_eq!(
+    ///     fetcher.prefetch_url().as_deref(),
+    ///     Some("git+https://example.com/repo.git?rev=5bfc8f2a1b2c3d4e5f60718293a4b5c6d7e8f901&leaveDotGit=1")
+    /// );
+    /// assert!(fetcher.render().unwrap().contains("leaveDotGit = true;"));
+    ///
+    /// // As do those which ask to keep it, without the full history
+    /// let fetcher = Fetcher::FetchGit {
+    ///     url: "https://example.com/repo.git".to_owned(),
+    ///     rev: "5bfc8f2a1b2c3d4e5f60718293a4b5c6d7e8f901".to_owned(),
+    ///     hash: String::new(),
+    ///     fetch_submodules: false,
+    ///     deep_clone: false,
+    ///     all_refs: false,
+    ///     leave_dot_git: true,
+    ///     dir: None,
+    ///     name: None,
+    /// };
+    ///
+    /// assert_eq!(
+    ///     fetcher.prefetch_url().as_deref(),
+    ///     Some("git+https://example.com/repo.git?rev=5bfc8f2a1b2c3d4e5f60718293a4b5c6d7e8f901&shallow=1&leaveDotGit=1")
+    /// );
+    /// assert!(fetcher.render().unwrap().contains("leaveDotGit = true;"));
+    /// ```
+    pub fn prefetch_url(&self) -> Option<String> {
+        let url = match self {
+            Self::FetchGit {
+                url,
+                rev,
+                fetch_submodules,
+                deep_clone,
+                all_refs,
+                leave_dot_git,
+                ..
+            } => {
+                // Nix only accepts commits as a `rev`, so branches and tags are given as a `ref`
+                let rev_key = if Self::is_full_commit(rev) {
+                    "rev"
+                } else {
+                    "ref"
+                };
+                let mut prefetch_url = format!("git+{url}?{rev_key}={rev}");
+
+                // The hash of a checkout differs when it includes submodules
+                if *fetch_submodules {
+                    prefetch_url.push_str("&submodules=1");
+                }
+                if *all_refs {
+                    prefetch_url.push_str("&allRefs=1");
+                }
+                if !deep_clone {
+                    prefetch_url.push_str("&shallow=1");
+                }
+                // `nix flake prefetch` always removes `.git`, so these
+                // are marked for the prefetcher to keep it
+                if *leave_dot_git {
+                    prefetch_url.push_str("&leaveDotGit=1");
+                }
+
+                prefetch_url
+            }
+            // The host is left out for github.com, to keep cached prefetches valid
+            Self::FetchGitHub {
+                owner,
+                repo,
+                rev,
+                domain,
+                ..
+            } if domain != DEFAULT_GITHUB_DOMAIN => {
+                format!("github:{owner}/{repo}?ref={rev}&host={domain}")
+            }
+            Self::FetchGitHub {
+                owner, repo, rev, ..
+            } => format!("github:{owner}/{repo}?ref={rev}"),
+            Self::FetchFromGitLab {
+                owner,
+                repo,
+                rev,
+                domain,
+                ..
+            } => format!(
+                "gitlab:{}/{repo}?ref={rev}&host={domain}",
+                owner.replace('/', "%2F")
+            ),
+            // Nix has no `bitbucket:` flake input, so the same archive
+            // `fetchFromBitbucket` downloads is prefetched instead
+            Self::FetchFromBitbucket {
+                owner, repo, rev, ..
+            } => format!("tarball+https://bitbucket.org/{owner}/{repo}/get/{rev}.tar.gz"),
+            // Prefetched as unpacked archives to match the hashes of
+            // `builtins.fetchTarball` and `fetchzip`, even for urls
+            // without an archive extension which redirect to one
+            Self::FetchTarball { url, .. } | Self::FetchZip { url, .. } => {
+                format!("tarball+{url}")
+            }
+            // Local tarballs are prefetched by their absolute path, which
+            // only the deserializer knows
+            Self::FetchUrl { .. }
+            | Self::CopyToStore { .. }
+            | Self::SymlinkIntoStore { .. }
+            | Self::LocalTarball { .. } => {
+                return None;
+            }
+        };
+
+        Some(url)
+    }
+
+    /// # Source Hash
+    ///
+    /// The hash of a fetcher's source, if it has one
+    pub fn source_hash(&self) -> Option<String> {
+        match self {
+            Self::FetchUrl { hash, .. }
+            | Self::FetchGit { hash, .. }
+            | Self::FetchGitHub { hash, .. }
+            | Self::FetchFromGitLab { hash, .. }
+            | Self::FetchFromBitbucket { hash, .. }
+            | Self::FetchTarball { hash, .. }
+            | Self::FetchZip { hash, .. }
+            | Self::LocalTarball { hash, .. } => Some(hash.clone()),
+            Self::CopyToStore { .. } | Self::SymlinkIntoStore { .. } => None,
+        }
+    }
+
+    /// # Symbolic Ref
+    ///
+    /// The branch or tag a git fetcher's rev names, if it isn't
+    /// already a commit
+    ///
+    /// ```rust
+    /// use bun2nix::package::Fetcher;
+    ///
+    /// let fetcher = |rev: &str| Fetcher::FetchGitHub {
+    ///     owner: "colinhacks".to_owned(),
+    ///     repo: "zod".to_owned(),
+    ///     rev: rev.to_owned(),
+    ///     hash: String::new(),
+    ///     domain: "github.com".to_owned(),
+    ///     dir: None,
+    ///     name: None,
+    /// };
+    ///
+    /// assert_eq!(fetcher("v3.24.2").symbolic_ref(), Some("v3.24.2"));
+    /// assert_eq!(fetcher("main").symbolic_ref(), Some("main"));
+    /// assert_eq!(fetcher("cafe").symbolic_ref(), Some("cafe"));
+    /// assert_eq!(fetcher("5bfc8f2").symbolic_ref(), None);
+    /// assert_eq!(
+    ///     fetcher("5bfc8f2a1b2c3d4e5f60718293a4b5c6d7e8f901").symbolic_ref(),
+    ///     None
+    /// );
+    /// ```
+    pub fn symbolic_ref(&self) -> Option<&str> {
+        match self {
+            Self::FetchGit { rev, .. }
+            | Self::FetchGitHub { rev, .. }
+            | Self::FetchFromGitLab { rev, .. }
+            | Self::FetchFromBitbucket { rev, .. } => {
+                (!Self::is_commit(rev)).then_some(rev.as_str())
+            }
+            _ => None,
+        }
+    }
+
+    /// # With Rev
+    ///
+    /// Pin a git fetcher to a commit, such as the one its
+    /// symbolic ref was resolved to, renaming its source to match
+    pub fn with_rev(mut self, new_rev: String) -> Self {
+        match &mut self {
+            Self::FetchGit { url, rev, name, .. } => {
+                *name = name.as_ref().map(|_| Self::git_source_name(url, &new_rev));
+                *rev = new_rev;
+            }
+            Self::FetchGitHub {
+                repo, rev, name, ..
+            } => {
+                *name = name.as_ref().map(|_| Self::git_source_name(repo, &new_rev));
+                *rev = new_rev;
+            }
+            Self::FetchFromGitLab { rev, .. } | Self::FetchFromBitbucket { rev, .. } => {
+                *rev = new_rev;
+            }
+            _ => (),
+        }
+
+        self
+    }
+
+    /// # With Hash
+    ///
+    /// Set the hash of a fetcher which has one, such as
+    /// once its source has been prefetched
+    pub fn with_hash(mut self, new_hash: String) -> Self {
+        match &mut self {
+            Self::FetchUrl { hash, .. }
+            | Self::FetchGit { hash, .. }
+            | Self::FetchGitHub { hash, .. }
+            | Self::FetchFromGitLab { hash, .. }
+            | Self::FetchFromBitbucket { hash, .. }
+            | Self::FetchTarball { hash, .. }
+            | Self::FetchZip { hash, .. }
+            | Self::LocalTarball { hash, .. } => *hash = new_hash,
+            Self::CopyToStore { .. } | Self::SymlinkIntoStore { .. } => (),
+        }
+
+        self
+    }
+
     /// # From NPM Package Name
     ///
     /// Initialize a fetcher from an npm identifier and
@@ -81,88 +740,650 @@ impl Fetcher {
     /// * `hash` - The integrity hash of the package
     /// * `tarball_url` - Optional explicit tarball URL from bun.lock. If provided
     ///   and non-empty, used directly. Otherwise, URL is constructed from the
-    ///   default npmjs.org registry.
-    pub fn new_npm_package(ident: &str, hash: String, tarball_url: Option<&str>) -> Result<Self> {
-        let url = Self::to_npm_url(ident, tarball_url)?;
+    ///   package's scope registry, or the default registry.
+    /// * `npmrc` - Registry settings used to find the scope registry and the
+    ///   environment variable holding an auth token for the URL
+    /// * `default_registry` - The registry for packages without a scope registry,
+    ///   usually `DEFAULT_REGISTRY`
+    /// * `registry_mirrors` - Registries to try before `default_registry`, for
+    ///   packages which would be fetched from it, each with a trailing slash
+    ///
+    /// ```rust
+    /// use askama::Template;
+    /// use bun2nix::{Error, npmrc::Npmrc, package::{DEFAULT_REGISTRY, Fetcher}};
+    ///
+    /// let fetcher = Fetcher::new_npm_package(
+    ///     "is-number@7.0.0",
+    ///     "sha512-AAAA".to_owned(),
+    ///     None,
+    ///     &Npmrc::default(),
+    ///     "https://npm.example.com/mirror/",
+    ///     &[],
+    /// )
+    /// .unwrap();
+    ///
+    /// let Fetcher::FetchUrl { url, .. } = fetcher else { panic!() };
+    /// assert_eq!(url, "https://npm.example.com/mirror/is-number/-/is-number-7.0.0.tgz");
+    ///
+    /// // The file is named to match the registry's tarball, including any pre-release
+    /// let fetcher = Fetcher::new_npm_package(
+    ///     "@scope/pkg@1.2.3-beta.1+build.5",
+    ///     "sha512-AAAA".to_owned(),
+    ///     None,
+    ///     &Npmrc::default(),
+    ///     "https://npm.example.com/mirror/",
+    ///     &[],
+    /// )
+    /// .unwrap();
+    ///
+    /// let Fetcher::FetchUrl { url, name, .. } = fetcher else { panic!() };
+    /// assert_eq!(url, "https://npm.example.com/mirror/@scope/pkg/-/pkg-1.2.3-beta.1.tgz");
+    /// assert_eq!(name.as_deref(), Some("pkg-1.2.3-beta.1.tgz"));
+    ///
+    /// // Every package gets the same clean name, whichever registry it's from
+    /// for registry in [DEFAULT_REGISTRY, "https://npm.example.com/mirror/"] {
+    ///     for (ident, expected) in [
+    ///         ("is-number@7.0.0", "is-number-7.0.0.tgz"),
+    ///         ("@types/node@22.13.5", "node-22.13.5.tgz"),
+    ///         ("@babel.dev/preset-env@7.26.9", "preset-env-7.26.9.tgz"),
+    ///         ("lodash.merge@4.6.2", "lodash.merge-4.6.2.tgz"),
+    ///         ("@scope/pkg@1.0.0-rc.1+build@5", "pkg-1.0.0-rc.1.tgz"),
+    ///         // Characters nix doesn't allow in store paths are replaced
+    ///         ("@scope/odd name@1.0.0", "odd-name-1.0.0.tgz"),
+    ///         ("café@1.0.0", "caf--1.0.0.tgz"),
+    ///     ] {
+    ///         let fetcher = Fetcher::new_npm_package(
+    ///             ident,
+    ///             "sha512-AAAA".to_owned(),
+    ///             None,
+    ///             &Npmrc::default(),
+    ///             registry,
+    ///             &[],
+    ///         )
+    ///         .unwrap();
+    ///
+    ///         let Fetcher::FetchUrl { name, .. } = fetcher else { panic!() };
+    ///         assert_eq!(name.as_deref(), Some(expected));
+    ///     }
+    /// }
+    ///
+    /// // Mirrors are tried first, and share the one hash, as their contents are identical
+    /// let fetcher = Fetcher::new_npm_package(
+    ///     "is-number@7.0.0",
+    ///     "sha512-AAAA".to_owned(),
+    ///     None,
+    ///     &Npmrc::default(),
+    ///     DEFAULT_REGISTRY,
+    ///     &["https://npm.example.com/mirror/".to_owned()],
+    /// )
+    /// .unwrap();
+    ///
+    /// let nix = fetcher.render().unwrap();
+    /// assert!(nix.contains(
+    ///     r#"urls = [
+    ///       "https://npm.example.com/mirror/is-number/-/is-number-7.0.0.tgz"
+    ///       "https://registry.npmjs.org/is-number/-/is-number-7.0.0.tgz"
+    ///     ];"#
+    /// ));
+    ///
+    /// // Auth tokens are read from their environment variable by the fetch, so aren't written out
+    /// let mut npmrc: Npmrc = "//npm.acme.internal/:_authToken=${ACME_NPM_TOKEN}".parse().unwrap();
+    /// // As if `ACME_NPM_TOKEN` were set to `s3cret` when it was read
+    /// npmrc.auth_tokens.insert("//npm.acme.internal/".to_owned(), "s3cret".to_owned());
+    ///
+    /// let acme_lib = |npmrc: &Npmrc| {
+    ///     Fetcher::new_npm_package(
+    ///         "@acme/lib@1.0.0",
+    ///         "sha512-AAAA".to_owned(),
+    ///         Some("https://npm.acme.internal/@acme/lib/-/lib-1.0.0.tgz"),
+    ///         npmrc,
+    ///         DEFAULT_REGISTRY,
+    ///         &[],
+    ///     )
+    /// };
+    ///
+    /// let nix = acme_lib(&npmrc).unwrap().render().unwrap();
+    /// assert!(nix.contains(r#"netrcImpureEnvVars = [ "ACME_NPM_TOKEN" ];"#));
+    /// assert!(nix.contains(r#"echo "Authorization: Bearer $ACME_NPM_TOKEN" > auth-header"#));
+    /// assert!(!nix.contains("s3cret"));
+    ///
+    /// // Tokens which aren't a single environment variable would have to be written out
+    /// let npmrc: Npmrc = "//npm.acme.internal/:_authToken=s3cret".parse().unwrap();
+    ///
+    /// assert!(matches!(
+    ///     acme_lib(&npmrc),
+    ///     Err(Error::UnmappedAuthToken(registry)) if registry == "//npm.acme.internal/"
+    /// ));
+    /// ```
+    pub fn new_npm_package(
+        ident: &str,
+        hash: String,
+        tarball_url: Option<&str>,
+        npmrc: &Npmrc,
+        default_registry: &str,
+        registry_mirrors: &[String],
+    ) -> Result<Self> {
+        let scope_registry = npmrc.registry_for(ident);
+        let registry = scope_registry.unwrap_or(default_registry);
+        let url = Self::to_npm_url(ident, tarball_url, registry)?;
+
+        // Only packages from the default registry have mirrors
+        let urls = match (tarball_url.filter(|url| !url.is_empty()), scope_registry) {
+            (None, None) => registry_mirrors
+                .iter()
+                .map(|mirror| Self::to_npm_url(ident, None, mirror))
+                .collect::<Result<_>>()?,
+            _ => Vec::new(),
+        };
+
+        // Always name the file, rather than leave nix to infer a store
+        // name from the url, which may lack a .tgz extension or differ
+        // between registries
+        let name = Some(Self::extract_tgz_filename(ident));
 
-        // For non-default registries, explicitly set the filename to ensure .tgz extension
-        let name = tarball_url
-            .filter(|u| !u.is_empty())
-            .map(|_| Self::extract_tgz_filename(ident));
+        // Tokens are only ever read from the environment as the package
+        // is fetched, so they never end up in the output or the store
+        let auth_token_var = npmrc
+            .auth_registry_for(&url)
+            .map(|registry| {
+                npmrc
+                    .auth_token_vars
+                    .get(registry)
+                    .cloned()
+                    .ok_or_else(|| Error::UnmappedAuthToken(registry.to_owned()))
+            })
+            .transpose()?;
 
-        Ok(Self::FetchUrl { url, hash, name })
+        Ok(Self::FetchUrl {
+            url,
+            urls,
+            hash,
+            name,
+            auth_token_var,
+        })
     }
 
-    /// Extract a .tgz filename from a package identifier
-    fn extract_tgz_filename(ident: &str) -> String {
-        // Handle scoped packages like @scope/name@version
-        if let Some((_, name_and_ver)) = ident.split_once("/")
-            && let Some((name, ver)) = name_and_ver.split_once("@")
-        {
-            return format!("{}-{}.tgz", name, ver);
+    /// # SRI From Integrity
+    ///
+    /// Convert a lockfile integrity value into a Nix SRI hash, without
+    /// needing to prefetch the package
+    ///
+    /// The `sha1`, `sha256` and `sha512` algorithms are supported, other
+    /// algorithms produce `Error::UnsupportedIntegrityAlgorithm`. The base64
+    /// digest (including it's padding) is validated against the algorithm's
+    /// digest length, producing `Error::MalformedIntegrity` if incorrect.
+    ///
+    /// ## Usage
+    ///```rust
+    /// use bun2nix::{Error, package::Fetcher};
+    ///
+    /// let integrity = "sha512-tzzskb3bG8LvYGFF/mDTpq3jpI6Q9wc3LEmBaghu+DdCssd1FakN7Bc0hVNmEyGq1bq3RgfkCb3cmQLpNPOroA==";
+    ///
+    /// assert_eq!(Fetcher::sri_from_integrity(integrity).unwrap(), integrity);
+    ///
+    /// // Older packages may use weaker algorithms
+    /// assert_eq!(
+    ///     Fetcher::sri_from_integrity("sha256-f8cRVBcn26NDNrBecwTLJSQD2yHBWPYIUclv1j1UxIY=").unwrap(),
+    ///     "sha256-f8cRVBcn26NDNrBecwTLJSQD2yHBWPYIUclv1j1UxIY="
+    /// );
+    /// assert_eq!(
+    ///     Fetcher::sri_from_integrity("sha1-KmxDRN9mBE/646QCOWUIKYql+ZE=").unwrap(),
+    ///     "sha1-KmxDRN9mBE/646QCOWUIKYql+ZE="
+    /// );
+    ///
+    /// // Unknown algorithm
+    /// assert!(matches!(
+    ///     Fetcher::sri_from_integrity("md5-1B2M2Y8AsgTpgAmY7PhCfg=="),
+    ///     Err(Error::UnsupportedIntegrityAlgorithm { .. })
+    /// ));
+    ///
+    /// // Missing padding
+    /// assert!(matches!(
+    ///     Fetcher::sri_from_integrity(integrity.trim_end_matches('=')),
+    ///     Err(Error::MalformedIntegrity(_))
+    /// ));
+    ///
+    /// // Digest length doesn't match the algorithm
+    /// let err = Fetcher::sri_from_integrity("sha256-KmxDRN9mBE/646QCOWUIKYql+ZE=").unwrap_err();
+    /// assert!(err.to_string().contains("`sha1-`, `sha256-` or `sha512-` prefixed"));
+    /// ```
+    pub fn sri_from_integrity(integrity: &str) -> Result<String> {
+        let malformed = || Error::MalformedIntegrity(integrity.to_owned());
+
+        let (algorithm, digest) = integrity.split_once('-').ok_or_else(malformed)?;
+
+        let digest_len = match algorithm {
+            "sha1" => 20,
+            "sha256" => 32,
+            "sha512" => 64,
+            _ => {
+                return Err(Error::UnsupportedIntegrityAlgorithm {
+                    algorithm: algorithm.to_owned(),
+                    integrity: integrity.to_owned(),
+                });
+            }
+        };
+
+        if !Self::is_padded_base64(digest, digest_len) {
+            return Err(malformed());
         }
-        // Handle unscoped packages like name@version
-        if let Some((name, ver)) = ident.split_once("@") {
-            return format!("{}-{}.tgz", name, ver);
+
+        Ok(format!("{}-{}", algorithm, digest))
+    }
+
+    /// Check that a digest is the padded base64 encoding of `len` bytes
+    fn is_padded_base64(digest: &str, len: usize) -> bool {
+        let padding = (3 - len % 3) % 3;
+        let encoded_len = len.div_ceil(3) * 4;
+
+        if digest.len() != encoded_len || !digest.is_ascii() {
+            return false;
+        }
+
+        let (data, pad) = digest.split_at(encoded_len - padding);
+
+        data.bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'+' || b == b'/')
+            && pad.bytes().all(|b| b == b'=')
+    }
+
+    /// # From JSR Package Name
+    ///
+    /// Initialize a fetcher from a JSR identifier and it's hash, fetched
+    /// from the tarball url bun recorded for it in the lockfile
+    ///
+    /// JSR's npm compatibility registry has no fixed url layout to fall
+    /// back on, so a missing url is an error.
+    ///
+    /// ```rust
+    /// use bun2nix::{Error, package::Fetcher};
+    ///
+    /// let fetcher = Fetcher::new_jsr_package(
+    ///     "@jsr/std__path@1.0.8",
+    ///     "sha512-AAAA".to_owned(),
+    ///     Some("https://npm.jsr.io/~/11/@jsr/std__path/1.0.8.tgz"),
+    /// )
+    /// .unwrap();
+    ///
+    /// assert!(matches!(
+    ///     fetcher,
+    ///     Fetcher::FetchUrl { url, .. } if url == "https://npm.jsr.io/~/11/@jsr/std__path/1.0.8.tgz"
+    /// ));
+    ///
+    /// assert!(matches!(
+    ///     Fetcher::new_jsr_package("@jsr/std__path@1.0.8", "sha512-AAAA".to_owned(), None),
+    ///     Err(Error::MissingJsrTarballUrl(_))
+    /// ));
+    /// ```
+    pub fn new_jsr_package(ident: &str, hash: String, tarball_url: Option<&str>) -> Result<Self> {
+        let url = tarball_url
+            .filter(|url| !url.is_empty())
+            .ok_or_else(|| Error::MissingJsrTarballUrl(ident.to_owned()))?
+            .to_owned();
+
+        let name = Some(Self::extract_tgz_filename(ident));
+
+        Ok(Self::FetchUrl {
+            url,
+            urls: Vec::new(),
+            hash,
+            name,
+            auth_token_var: None,
+        })
+    }
+
+    /// # Is JSR Identifier
+    ///
+    /// Check if a package identifier refers to a package from the JSR registry,
+    /// either in it's `jsr:` form or it's npm compatible `@jsr/` form
+    ///
+    /// ```rust
+    /// use bun2nix::package::Fetcher;
+    ///
+    /// assert!(Fetcher::is_jsr_identifier("@jsr/std__path@1.0.8"));
+    /// assert!(Fetcher::is_jsr_identifier("jsr:@std/path@1.0.8"));
+    /// assert!(!Fetcher::is_jsr_identifier("@types/node@22.13.5"));
+    /// ```
+    pub fn is_jsr_identifier(ident: &str) -> bool {
+        ident.starts_with("@jsr/") || ident.starts_with("jsr:")
+    }
+
+    /// # Exclude Regex
+    ///
+    /// Translate an exclude glob into a regex for `builtins.match`,
+    /// escaped to be written into a nix string, which matches paths
+    /// relative to the copied directory
+    ///
+    /// Globs without a `/` match a name at any depth, as in a `.gitignore`
+    ///
+    /// ```rust
+    /// use bun2nix::package::Fetcher;
+    ///
+    /// assert_eq!(Fetcher::exclude_regex("node_modules"), "(.*/)?node_modules");
+    /// assert_eq!(Fetcher::exclude_regex("*.log"), r"(.*/)?[^/]*\\.log");
+    /// assert_eq!(Fetcher::exclude_regex("/dist"), "dist");
+    /// assert_eq!(Fetcher::exclude_regex("src/**/*.test.ts"), r"src/(.*/)?[^/]*\\.test\\.ts");
+    /// ```
+    ///
+    /// Copied packages with any excludes are filtered with `lib.cleanSourceWith`:
+    ///
+    /// ```rust
+    /// use askama::Template;
+    /// use bun2nix::package::Fetcher;
+    ///
+    /// let fetcher = Fetcher::CopyToStore {
+    ///     path: "packages/app".to_owned(),
+    ///     exclude: vec!["node_modules".to_owned(), "*.log".to_owned()],
+    /// };
+    /// let nix = fetcher.render().unwrap();
+    ///
+    /// assert!(nix.starts_with("lib.cleanSourceWith {\n    src = ./packages/app;"));
+    /// assert!(nix.contains(r#""(.*/)?node_modules""#));
+    /// assert!(nix.contains(r#""(.*/)?[^/]*\\.log""#));
+    ///
+    /// let fetcher = Fetcher::CopyToStore {
+    ///     path: "packages/app".to_owned(),
+    ///     exclude: Vec::new(),
+    /// };
+    ///
+    /// assert_eq!(fetcher.render().unwrap(), "copyPathToStore ./packages/app");
+    /// ```
+    pub fn exclude_regex(glob: &str) -> String {
+        let mut regex = String::new();
+
+        if !glob.contains('/') {
+            regex.push_str("(.*/)?");
+        }
+
+        let mut chars = glob.trim_start_matches('/').chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '*' if chars.next_if_eq(&'*').is_some() => {
+                    if chars.next_if_eq(&'/').is_some() {
+                        regex.push_str("(.*/)?");
+                    } else {
+                        regex.push_str(".*");
+                    }
+                }
+                '*' => regex.push_str("[^/]*"),
+                '?' => regex.push_str("[^/]"),
+                '.' | '+' | '(' | ')' | '|' | '{' | '}' | '[' | ']' | '^' | '$' => {
+                    regex.push_str(r"\\");
+                    regex.push(c);
+                }
+                '\\' => regex.push_str(r"\\\\"),
+                '"' => regex.push_str("\\\""),
+                _ => regex.push(c),
+            }
+        }
+
+        regex
+    }
+
+    /// # Git Source Name
+    ///
+    /// The store path name given to a git checkout, `<repo>-<rev>`, so
+    /// that it doesn't depend on the defaults of the nix version in use
+    ///
+    /// The repo is taken from the end of a url, without any `.git`, and
+    /// characters nix doesn't allow in store paths are replaced with `-`.
+    ///
+    /// ```rust
+    /// use askama::Template;
+    /// use bun2nix::package::Fetcher;
+    ///
+    /// assert_eq!(
+    ///     Fetcher::git_source_name("https://example.com/acme/repo.git", "a1b2c3"),
+    ///     "repo-a1b2c3"
+    /// );
+    /// assert_eq!(
+    ///     Fetcher::git_source_name("git@example.com:acme/my repo", "v1.0.0"),
+    ///     "my-repo-v1.0.0"
+    /// );
+    ///
+    /// let fetcher = Fetcher::FetchGitHub {
+    ///     owner: "colinhacks".to_owned(),
+    ///     repo: "zod".to_owned(),
+    ///     rev: "5bfc8f2".to_owned(),
+    ///     hash: "sha256-AAAA".to_owned(),
+    ///     domain: "github.com".to_owned(),
+    ///     dir: None,
+    ///     name: Some(Fetcher::git_source_name("zod", "5bfc8f2")),
+    /// };
+    ///
+    /// assert!(fetcher.render().unwrap().contains(r#"name = "zod-5bfc8f2";"#));
+    /// ```
+    pub fn git_source_name(url: &str, rev: &str) -> String {
+        let repo = url
+            .trim_end_matches('/')
+            .rsplit(['/', ':'])
+            .next()
+            .unwrap_or(url);
+        let repo = repo.strip_suffix(".git").unwrap_or(repo);
+
+        Self::store_path_name(&format!("{repo}-{rev}"))
+    }
+
+    /// # Store Path Name
+    ///
+    /// Replace the characters nix doesn't allow in store path names with `-`
+    ///
+    /// ```rust
+    /// use bun2nix::package::Fetcher;
+    ///
+    /// assert_eq!(Fetcher::store_path_name("pkg@1.0.0.tgz"), "pkg-1.0.0.tgz");
+    /// assert_eq!(Fetcher::store_path_name(".hidden"), "hidden");
+    /// ```
+    pub fn store_path_name(name: &str) -> String {
+        name.chars()
+            .map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '+' | '-' | '.' | '_' | '?' | '=' => c,
+                _ => '-',
+            })
+            .collect::<String>()
+            .trim_start_matches('.')
+            .to_owned()
+    }
+
+    /// # Tarball With Integrity
+    ///
+    /// A tarball whose integrity the lockfile gives, which is fetched
+    /// with `fetchurl` rather than prefetched and unpacked, named after
+    /// the last segment of its url
+    pub fn new_tarball_with_integrity(url: String, hash: String) -> Self {
+        let file_name = url
+            .split(['?', '#'])
+            .next()
+            .and_then(|path| path.trim_end_matches('/').rsplit('/').next())
+            .map(Self::store_path_name)
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| "source".to_owned());
+
+        Self::FetchUrl {
+            url,
+            urls: Vec::new(),
+            hash,
+            name: Some(file_name),
+            auth_token_var: None,
+        }
+    }
+
+    /// Extract a .tgz filename from a package identifier, usable as a store path name
+    fn extract_tgz_filename(ident: &str) -> String {
+        let file_name = match Self::split_npm_identifier(ident) {
+            // Scoped packages like @scope/name@version are named without their scope
+            Some((name, ver)) => {
+                let (_, name) = Self::split_scope(name);
+
+                format!("{}-{}.tgz", name, Self::tarball_version(ver))
+            }
+            None => format!("{}.tgz", Self::split_scope(ident).1),
+        };
+
+        Self::store_path_name(&file_name)
+    }
+
+    /// Split an npm `name@version` identifier into it's name and version
+    ///
+    /// Names can only contain an `@` as the first character of their scope,
+    /// so they end at the first `@` after it, while versions and alias
+    /// targets may contain more.
+    fn split_npm_identifier(ident: &str) -> Option<(&str, &str)> {
+        let ver = strip_name_prefix(ident)?;
+
+        Some((&ident[..ident.len() - ver.len() - 1], ver))
+    }
+
+    /// Split an npm package name into it's scope, without the `@`, and
+    /// the rest of the name, at the first `/`
+    fn split_scope(name: &str) -> (Option<&str>, &str) {
+        match name.strip_prefix('@').and_then(|name| name.split_once('/')) {
+            Some((scope, name)) => (Some(scope), name),
+            None => (None, name),
         }
-        // Fallback
-        format!("{}.tgz", ident)
     }
 
     /// # NPM url converter
     ///
     /// Produce a url needed to fetch from the npm api from a package
     ///
+    /// The `registry` is only used when no explicit tarball url is present,
+    /// and must end with a trailing slash.
+    ///
     /// ## Usage
     ///```rust
-    /// use bun2nix::package::Fetcher;
+    /// use bun2nix::package::{DEFAULT_REGISTRY, Fetcher};
     ///
     /// // Default registry (empty or None tarball_url)
     /// let npm_identifier = "@alloc/quick-lru@5.2.0";
     ///
     /// assert_eq!(
-    ///     Fetcher::to_npm_url(npm_identifier, None).unwrap(),
+    ///     Fetcher::to_npm_url(npm_identifier, None, DEFAULT_REGISTRY).unwrap(),
     ///     "https://registry.npmjs.org/@alloc/quick-lru/-/quick-lru-5.2.0.tgz"
     /// );
     ///
     /// assert_eq!(
-    ///     Fetcher::to_npm_url(npm_identifier, Some("")).unwrap(),
+    ///     Fetcher::to_npm_url(npm_identifier, Some(""), DEFAULT_REGISTRY).unwrap(),
     ///     "https://registry.npmjs.org/@alloc/quick-lru/-/quick-lru-5.2.0.tgz"
     /// );
     ///
-    /// // Explicit tarball URL (used directly)
+    /// // Scope registry
     /// assert_eq!(
-    ///     Fetcher::to_npm_url(npm_identifier, Some("https://npm.pkg.github.com/@alloc/quick-lru/-/quick-lru-5.2.0.tgz")).unwrap(),
+    ///     Fetcher::to_npm_url(npm_identifier, None, "https://npm.acme.internal/").unwrap(),
+    ///     "https://npm.acme.internal/@alloc/quick-lru/-/quick-lru-5.2.0.tgz"
+    /// );
+    ///
+    /// // Explicit tarball URL (used directly, even over a scope registry)
+    /// assert_eq!(
+    ///     Fetcher::to_npm_url(npm_identifier, Some("https://npm.pkg.github.com/@alloc/quick-lru/-/quick-lru-5.2.0.tgz"), "https://npm.acme.internal/").unwrap(),
     ///     "https://npm.pkg.github.com/@alloc/quick-lru/-/quick-lru-5.2.0.tgz"
     /// );
+    ///
+    /// // Unusual characters are percent encoded, while the scope's `@` stays literal
+    /// assert_eq!(
+    ///     Fetcher::to_npm_url("@scope/odd name@1.0.0", None, DEFAULT_REGISTRY).unwrap(),
+    ///     "https://registry.npmjs.org/@scope/odd%20name/-/odd%20name-1.0.0.tgz"
+    /// );
+    ///
+    /// // Pre-release versions are kept, but build metadata is dropped, as in the published tarball
+    /// assert_eq!(
+    ///     Fetcher::to_npm_url("next@15.0.0-canary.1", None, DEFAULT_REGISTRY).unwrap(),
+    ///     "https://registry.npmjs.org/next/-/next-15.0.0-canary.1.tgz"
+    /// );
+    /// assert_eq!(
+    ///     Fetcher::to_npm_url("pkg@1.2.3+build.5", None, DEFAULT_REGISTRY).unwrap(),
+    ///     "https://registry.npmjs.org/pkg/-/pkg-1.2.3.tgz"
+    /// );
+    /// assert_eq!(
+    ///     Fetcher::to_npm_url("@scope/pkg@1.2.3-beta.1+build.5", None, DEFAULT_REGISTRY).unwrap(),
+    ///     "https://registry.npmjs.org/@scope/pkg/-/pkg-1.2.3-beta.1.tgz"
+    /// );
+    ///
+    /// assert_eq!(
+    ///     Fetcher::to_npm_url("café@1.0.0", None, DEFAULT_REGISTRY).unwrap(),
+    ///     "https://registry.npmjs.org/caf%C3%A9/-/caf%C3%A9-1.0.0.tgz"
+    /// );
+    ///
+    /// // The scope ends at the first `/`, and the name at the first `@` after the scope,
+    /// // however many dots, dashes or `@`s are either side of them
+    /// for (ident, expected) in [
+    ///     ("@babel/preset-env@7.26.9", "@babel/preset-env/-/preset-env-7.26.9.tgz"),
+    ///     ("@babel.dev/preset.env@7.26.9", "@babel.dev/preset.env/-/preset.env-7.26.9.tgz"),
+    ///     ("@-scope-/-name-@1.0.0", "@-scope-/-name-/-/-name--1.0.0.tgz"),
+    ///     ("@a/b@1", "@a/b/-/b-1.tgz"),
+    ///     ("lodash.merge@4.6.2", "lodash.merge/-/lodash.merge-4.6.2.tgz"),
+    ///     ("@scope/pkg@1.0.0-rc.1+build@5", "@scope/pkg/-/pkg-1.0.0-rc.1.tgz"),
+    ///     ("@scope/pkg@1.0.0-x.7.z.92", "@scope/pkg/-/pkg-1.0.0-x.7.z.92.tgz"),
+    ///     ("pkg@1.0.0-@beta", "pkg/-/pkg-1.0.0-%40beta.tgz"),
+    /// ] {
+    ///     assert_eq!(
+    ///         Fetcher::to_npm_url(ident, None, DEFAULT_REGISTRY).unwrap(),
+    ///         format!("{DEFAULT_REGISTRY}{expected}"),
+    ///         "{ident}"
+    ///     );
+    /// }
     /// ```
-    pub fn to_npm_url(ident: &str, tarball_url: Option<&str>) -> Result<String> {
+    pub fn to_npm_url(ident: &str, tarball_url: Option<&str>, registry: &str) -> Result<String> {
         // If an explicit tarball URL is provided, use it directly
-        if let Some(url) = tarball_url {
-            if !url.is_empty() {
-                return Ok(url.to_string());
-            }
+        if let Some(url) = tarball_url
+            && !url.is_empty()
+        {
+            return Ok(url.to_string());
         }
 
-        // Otherwise, construct the URL from the default registry
-        let Some((user, name_and_ver)) = ident.split_once("/") else {
-            let Some((name, ver)) = ident.split_once("@") else {
-                return Err(Error::NoAtInPackageIdentifier);
-            };
+        // Otherwise, construct the URL from the registry
+        let (name, ver) = Self::split_npm_identifier(ident)
+            .ok_or_else(|| Error::NoAtInPackageIdentifier(ident.to_owned()))?;
+        let ver = Self::encode_path_segment(Self::tarball_version(ver));
 
-            return Ok(format!(
-                "{}{}/-/{}-{}.tgz",
-                DEFAULT_REGISTRY, name, name, ver
-            ));
-        };
+        let (scope, name) = Self::split_scope(name);
+        let name = Self::encode_path_segment(name);
 
-        let Some((name, ver)) = name_and_ver.split_once("@") else {
-            return Err(Error::NoAtInPackageIdentifier);
+        let Some(scope) = scope else {
+            return Ok(format!("{}{}/-/{}-{}.tgz", registry, name, name, ver));
         };
 
         Ok(format!(
-            "{}{}/{}/-/{}-{}.tgz",
-            DEFAULT_REGISTRY, user, name, name, ver
+            "{}@{}/{}/-/{}-{}.tgz",
+            registry,
+            Self::encode_path_segment(scope),
+            name,
+            name,
+            ver
         ))
     }
+
+    /// The version a registry names a package's tarball with, which
+    /// never includes semver build metadata, as npm drops it on publish
+    fn tarball_version(ver: &str) -> &str {
+        ver.split_once('+').map_or(ver, |(ver, _)| ver)
+    }
+
+    /// Whether a git rev names a commit, by it's full sha or abbreviated to
+    /// at least 7 characters as bun writes them, rather than a branch or tag
+    ///
+    /// Shorter hex names, such as `cafe`, are taken to be branches.
+    fn is_commit(rev: &str) -> bool {
+        (7..=40).contains(&rev.len()) && rev.chars().all(|c| c.is_ascii_hexdigit())
+    }
+
+    /// Whether a git rev is a full commit sha, which is all nix accepts as a `rev`
+    fn is_full_commit(rev: &str) -> bool {
+        rev.len() == 40 && Self::is_commit(rev)
+    }
+
+    /// Percent encode everything but the unreserved characters of a url path segment
+    fn encode_path_segment(segment: &str) -> String {
+        let mut encoded = String::with_capacity(segment.len());
+
+        for byte in segment.bytes() {
+            if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~') {
+                encoded.push(char::from(byte));
+            } else {
+                encoded.push_str(&format!("%{:02X}", byte));
+            }
+        }
+
+        encoded
+    }
 }
@@ -0,0 +1,106 @@
+//! This module holds the report mapping each lockfile entry to what it was converted into, for
+//! debugging why a package was fetched the way it was
+
+use std::fmt;
+
+use crate::{Package, package::FetcherKind};
+
+/// # Report
+///
+/// A plain text table of every lockfile entry, with the package
+/// it was converted into, the fetcher used and where it fetches
+/// from, written one entry per line so it diffs cleanly between
+/// runs
+///
+/// ```rust
+/// use bun2nix::{Lockfile, Options, lockfile::NixPrefetcher, report::Report};
+///
+/// let lockfile: Lockfile = r#"{
+///   "lockfileVersion": 1,
+///   "packages": {
+///     "bun": ["bun@github:oven-sh/bun#a1b2c3", {}, "oven-sh-bun-a1b2c3"],
+///     "fsevents": ["fsevents@2.3.3", "", { "os": "darwin" }, "sha512-5xoDfX+fL7faATnagmWPpbFtwh/R77WmMMqqHGS65C3vvB0YHrgF+B1YmZ3441tMj5n63k0212XNoJwzlhffQw=="],
+///     "is-number": ["is-number@7.0.0", "", {}, "sha512-41Cifkg6e8TylSpdtTpeLVMqvSBEVzTttHvERD741+pnZ8ANv0004MRL43QKPDlK9cGvNp6NZWZUBlbGXYxxng=="],
+///   }
+/// }"#.parse().unwrap();
+///
+/// let options = Options {
+///     dry_run: true,
+///     target_systems: vec!["x86_64-linux".to_owned()],
+///     ..Default::default()
+/// };
+/// let entries = lockfile.entries_with_prefetcher(&options, &NixPrefetcher).unwrap();
+/// let report = Report::new(&entries).to_string();
+/// let lines: Vec<_> = report.lines().collect();
+///
+/// assert_eq!(lines[0], "lockfile entry\tpackage\tfetcher\tsource");
+/// assert_eq!(lines[1], "bun\tgithub:oven-sh-bun-a1b2c3\tfetchFromGitHub\tgithub:oven-sh/bun#a1b2c3");
+/// assert_eq!(lines[2], "fsevents\t-\tskipped\t-");
+/// assert_eq!(
+///     lines[3],
+///     "is-number\tis-number@7.0.0\tfetchurl\thttps://registry.npmjs.org/is-number/-/is-number-7.0.0.tgz"
+/// );
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Report {
+    /// The lines of the report, in lockfile order
+    pub entries: Vec<ReportEntry>,
+}
+
+/// # Report Entry
+///
+/// A single lockfile entry and what it was converted into, if
+/// it wasn't skipped
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ReportEntry {
+    /// The key of the entry in the lockfile
+    pub key: String,
+
+    /// The name of the package the entry was converted into
+    pub name: Option<String>,
+
+    /// The kind of fetcher the package is fetched with
+    pub fetcher: Option<FetcherKind>,
+
+    /// The url, rev or path the package is fetched from
+    pub source: Option<String>,
+}
+
+impl Report {
+    /// # New Report
+    ///
+    /// Build a report from a lockfile's deserialized entries, as
+    /// returned by `Lockfile::entries_with_prefetcher`
+    pub fn new(entries: &[(String, Option<Package>)]) -> Self {
+        let entries = entries
+            .iter()
+            .map(|(key, pkg)| ReportEntry {
+                key: key.clone(),
+                name: pkg.as_ref().map(|pkg| pkg.name.clone()),
+                fetcher: pkg.as_ref().map(|pkg| pkg.fetcher.kind()),
+                source: pkg.as_ref().map(|pkg| pkg.fetcher.source()),
+            })
+            .collect();
+
+        Self { entries }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "lockfile entry\tpackage\tfetcher\tsource")?;
+
+        for entry in &self.entries {
+            writeln!(
+                f,
+                "{}\t{}\t{}\t{}",
+                entry.key,
+                entry.name.as_deref().unwrap_or("-"),
+                entry.fetcher.map_or("skipped", FetcherKind::nix_function),
+                entry.source.as_deref().unwrap_or("-"),
+            )?;
+        }
+
+        Ok(())
+    }
+}
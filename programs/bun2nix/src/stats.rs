@@ -0,0 +1,139 @@
+//! This module holds the statistics optionally written to stderr after generating, for comparing
+//! the packages and prefetches of one run against another
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use crate::{
+    Options, Package, Result,
+    lockfile::{Prefetch, Prefetcher},
+    package::FetcherKind,
+};
+
+/// # Stats
+///
+/// The number of packages written out with each kind of fetcher,
+/// along with how many live prefetches were run and how long the
+/// whole conversion took
+///
+/// ```rust
+/// use std::{collections::BTreeMap, time::Duration};
+///
+/// use bun2nix::{Package, package::Fetcher, stats::Stats};
+///
+/// let npm = |name: &str| {
+///     let fetcher = Fetcher::FetchUrl {
+///         url: format!("https://registry.npmjs.org/{name}/-/{name}-1.0.0.tgz"),
+///         urls: Vec::new(),
+///         hash: "sha512-AAAA".to_owned(),
+///         name: None,
+///         auth_token_var: None,
+///     };
+///
+///     Package::new(format!("{name}@1.0.0"), fetcher)
+/// };
+/// let workspace = Package::new(
+///     "app".to_owned(),
+///     Fetcher::CopyToStore { path: "packages/app".to_owned(), exclude: Vec::new() },
+/// );
+///
+/// let packages = [npm("a"), npm("b"), workspace];
+/// let stats = Stats::new(packages.iter(), 0, Duration::from_millis(1500));
+///
+/// assert_eq!(
+///     stats.to_string(),
+///     "Packages: 3
+///   fetchurl: 2
+///   copyPathToStore: 1
+/// Live prefetches: 0
+/// Total time: 1.50s
+/// "
+/// );
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Stats {
+    /// The number of packages using each kind of fetcher
+    pub fetchers: BTreeMap<FetcherKind, usize>,
+
+    /// The number of packages which were prefetched, rather than
+    /// having a known hash
+    pub prefetches: usize,
+
+    /// How long the conversion took
+    pub elapsed: Duration,
+}
+
+impl Stats {
+    /// # New Stats
+    ///
+    /// Count the fetchers of the packages written out, along with
+    /// the prefetches it took to hash them
+    pub fn new<'a>(
+        packages: impl IntoIterator<Item = &'a Package>,
+        prefetches: usize,
+        elapsed: Duration,
+    ) -> Self {
+        let mut fetchers = BTreeMap::new();
+        for package in packages {
+            *fetchers.entry(package.fetcher.kind()).or_default() += 1;
+        }
+
+        Self {
+            fetchers,
+            prefetches,
+            elapsed,
+        }
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Packages: {}", self.fetchers.values().sum::<usize>())?;
+        for (fetcher, count) in &self.fetchers {
+            writeln!(f, "  {fetcher}: {count}")?;
+        }
+
+        writeln!(f, "Live prefetches: {}", self.prefetches)?;
+        writeln!(f, "Total time: {:.2}s", self.elapsed.as_secs_f64())
+    }
+}
+
+/// # Counting Prefetcher
+///
+/// Wraps another prefetcher, counting each prefetch it runs
+#[derive(Debug)]
+pub struct CountingPrefetcher<'a> {
+    prefetcher: &'a dyn Prefetcher,
+    count: AtomicUsize,
+}
+
+impl<'a> CountingPrefetcher<'a> {
+    /// # New Counting Prefetcher
+    ///
+    /// Count the prefetches run by the given prefetcher
+    pub fn new(prefetcher: &'a dyn Prefetcher) -> Self {
+        Self {
+            prefetcher,
+            count: AtomicUsize::new(0),
+        }
+    }
+
+    /// # Count
+    ///
+    /// The number of prefetches run so far, whether or not they succeeded
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+}
+
+impl Prefetcher for CountingPrefetcher<'_> {
+    fn prefetch(&self, url: &str, options: &Options) -> Result<Prefetch> {
+        self.count.fetch_add(1, Ordering::Relaxed);
+
+        self.prefetcher.prefetch(url, options)
+    }
+}
@@ -0,0 +1,125 @@
+//! This module holds the check that a generated nix expression parses, to catch template
+//! regressions before they reach a build
+
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+use crate::{
+    error::{Error, Result},
+    nix_expression::escape_nix_attr,
+};
+
+/// The command used to parse the generated expression, which reads it from stdin
+pub const NIX_PARSE_COMMAND: &[&str] = &["nix-instantiate", "--parse", "-"];
+
+/// # Validate Nix
+///
+/// Parse a generated nix expression with `nix-instantiate --parse`,
+/// failing if it is not syntactically valid
+///
+/// The error names the package the parse error is in, if it can be
+/// traced back to one.
+pub fn validate_nix(nix: &str) -> Result<()> {
+    cfg_if::cfg_if! {
+        if #[cfg(target_arch = "wasm32")] {
+            let _ = nix;
+
+            Err(Error::UnsupportedWASMCliAction(NIX_PARSE_COMMAND.join(" ")))
+        } else {
+            let (program, args) = NIX_PARSE_COMMAND
+                .split_first()
+                .expect("the parse command has a program");
+
+            let mut child = Command::new(program)
+                .args(args)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::null())
+                .stderr(Stdio::piped())
+                .spawn()
+                .map_err(Error::ValidateCommandError)?;
+
+            if let Some(mut stdin) = child.stdin.take() {
+                stdin
+                    .write_all(nix.as_bytes())
+                    .map_err(Error::ValidateCommandError)?;
+            }
+
+            let output = child
+                .wait_with_output()
+                .map_err(Error::ValidateCommandError)?;
+
+            if output.status.success() {
+                return Ok(());
+            }
+
+            let message = String::from_utf8_lossy(&output.stderr).trim().to_owned();
+            let location = match error_package(nix, &message) {
+                Some(package) => format!("in package '{package}'"),
+                None => "outside of any package".to_owned(),
+            };
+
+            Err(Error::InvalidNixOutput { location, message })
+        }
+    }
+}
+
+/// # Error Package
+///
+/// The attribute name of the package a `nix-instantiate --parse`
+/// error message points into, if it points into one
+///
+/// ```rust
+/// use bun2nix::validate::error_package;
+///
+/// let nix = r#"{
+///   "is-number@7.0.0" = fetchurl {
+///     url = "https://registry.npmjs.org/is-number/-/is-number-7.0.0.tgz";
+///     hash = "sha512-AAAA";
+///   };
+///   "zod@3.24.2" = fetchurl {
+///     url = "https://registry.npmjs.org/zod/-/zod-3.24.2.tgz"
+///     hash = "sha512-BBBB";
+///   };
+/// }"#;
+///
+/// let stderr = "error: syntax error, unexpected ID, expecting '.' or '='\n       at «stdin»:8:5:";
+/// assert_eq!(error_package(nix, stderr).as_deref(), Some("zod@3.24.2"));
+///
+/// // Older versions of nix name stdin differently
+/// let stderr = "error: syntax error, unexpected ID, at (string):3:7";
+/// assert_eq!(error_package(nix, stderr).as_deref(), Some("is-number@7.0.0"));
+///
+/// // As are packages written under a bare name
+/// let nix = nix.replace(r#""zod@3.24.2""#, "zod");
+/// assert_eq!(error_package(&nix, "error: syntax error, at «stdin»:8:5:").as_deref(), Some("zod"));
+///
+/// assert_eq!(error_package(&nix, "error: syntax error, at «stdin»:1:1"), None);
+/// assert_eq!(error_package(&nix, "error: out of memory"), None);
+/// ```
+pub fn error_package(nix: &str, stderr: &str) -> Option<String> {
+    let line = ["«stdin»:", "(string):"].iter().find_map(|marker| {
+        let (_, position) = stderr.split_once(marker)?;
+        let digits: String = position.chars().take_while(char::is_ascii_digit).collect();
+
+        digits.parse::<usize>().ok()
+    })?;
+
+    // Packages start at the top level of the attribute set, so are
+    // the nearest line indented by exactly two spaces at or before it
+    nix.lines()
+        .take(line)
+        .filter_map(|line| {
+            let entry = line.strip_prefix("  ")?;
+
+            if let Some(name) = entry.strip_prefix('"') {
+                return name.rsplit_once("\" = ").map(|(name, _)| name.to_owned());
+            }
+
+            // Names which are valid identifiers are written bare
+            let (name, _) = entry.split_once(" = ")?;
+            (escape_nix_attr(name) == name).then(|| name.to_owned())
+        })
+        .last()
+}
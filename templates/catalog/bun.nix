@@ -8,11 +8,34 @@
 {
   copyPathToStore,
   fetchurl,
+  lib,
   ...
 }:
 {
-  "@workspace/app" = copyPathToStore ./packages/app;
-  "@workspace/lib" = copyPathToStore ./packages/lib;
+  "@workspace/app" = lib.cleanSourceWith {
+    src = ./packages/app;
+    filter =
+      path: type:
+      let
+        relPath = lib.removePrefix (toString ./packages/app + "/") (toString path);
+      in
+      !(lib.any (regex: builtins.match regex relPath != null) [
+        "(.*/)?\\.git"
+        "(.*/)?node_modules"
+      ]);
+  };
+  "@workspace/lib" = lib.cleanSourceWith {
+    src = ./packages/lib;
+    filter =
+      path: type:
+      let
+        relPath = lib.removePrefix (toString ./packages/lib + "/") (toString path);
+      in
+      !(lib.any (regex: builtins.match regex relPath != null) [
+        "(.*/)?\\.git"
+        "(.*/)?node_modules"
+      ]);
+  };
   "bun2nix@2.0.8" = fetchurl {
     url = "https://registry.npmjs.org/bun2nix/-/bun2nix-2.0.8.tgz";
     hash = "sha512-pwq35hA81X1Kjsi5Xo69Aii9aY3zZHhWXwqF1QRz/uB35KzKbwJZ16WrhadiG9/T6bjOsRrPZtzuAyqmlXopLw==";
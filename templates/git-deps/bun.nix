@@ -36,6 +36,9 @@
     url = "https://gitlab.com/gitlab-examples/semantic-release-npm";
     rev = "ee100d81f12ae315a81c2a664979a6cc1bce99a2";
     hash = "sha256-jHz3ybhO4oQVk7sKkMpbKtanZnR3eetiUytARuy2mJM=";
+    fetchSubmodules = false;
+    deepClone = false;
+    leaveDotGit = false;
   };
   "github:colinhacks-zod-5bfc8f2" = fetchFromGitHub {
     owner = "colinhacks";
@@ -13,6 +13,7 @@
   "@sindresorhus/is@7.0.1" = fetchurl {
     url = "https://registry.npmjs.org/@sindresorhus/is/-/is-7.0.1.tgz";
     hash = "sha512-QWLl2P+rsCJeofkDNIT3WFmb6NrRud1SUYW8dIhXK/46XFV8Q/g7Bsvib0Askb0reRLe+WYPeeE+l5cH7SlkuQ==";
+    passthru.patch = ./. + "/patches/@sindresorhus+is@7.0.1.patch";
   };
   "@types/bun@1.3.12" = fetchurl {
     url = "https://registry.npmjs.org/@types/bun/-/bun-1.3.12.tgz";
@@ -1,27 +1,16 @@
 {
   bun2nix,
-  lib,
   ...
 }:
-let
-  src = ./.;
-  packageJsonPath = ./package.json;
-  packageJsonContents = lib.importJSON packageJsonPath;
-  patchedDependencies = lib.mapAttrs (_: path: "${src}/${path}") (
-    packageJsonContents.patchedDependencies or { }
-  );
-  patchOverrides = bun2nix.patchedDependenciesToOverrides {
-    inherit patchedDependencies;
-  };
-in
 bun2nix.mkDerivation {
-  packageJson = packageJsonPath;
+  packageJson = ./package.json;
 
-  inherit src;
+  src = ./.;
 
+  # Patches listed in `patchedDependencies` are recorded in `bun.nix`
+  # by `bun2nix` and applied automatically
   bunDeps = bun2nix.fetchBunDeps {
     bunNix = ./bun.nix;
-    overrides = patchOverrides;
   };
 
   # Verify the patch was applied by running the test script